@@ -1,12 +1,21 @@
 use std::collections::HashSet;
 
 use crate::common::{Shape, Time, Triangles, Vertex};
+use crate::denoise::Denoiser;
+use crate::post_process::PostProcess;
 use crate::raytracing::Raytracer;
 use crate::render_env::RenderEnv;
-use crate::resources::Camera;
+use crate::options::Options;
+use crate::resources::{Camera, Environment, Scheme, Sky};
+use crate::scene::Scene;
+use crate::scene_graph::NodeId;
 use crate::world::World;
 use crate::screen::Screen;
-use glam::Vec2;
+use crate::screenshot::save_screenshot;
+use crate::export::{save_exr, save_hdr, ExrPrecision};
+use crate::scene_export::save_obj;
+use glam::{Mat4, Vec2, Vec3};
+use std::path::PathBuf;
 use wgpu::{ColorTargetState, CommandEncoderDescriptor, RenderPipeline, TextureViewDescriptor};
 use winit::dpi::PhysicalPosition;
 use winit::event::{KeyEvent, ElementState, MouseButton, WindowEvent, Modifiers};
@@ -15,54 +24,125 @@ use winit::keyboard::{PhysicalKey, KeyCode};
 pub struct App {
     time: Time,
     ray_tracer: Raytracer,
+    denoiser: Denoiser,
+    post_process: PostProcess,
     screen: Screen,
     camera: Camera,
+    environment: Environment,
+    sky: Sky,
+    scene: Scene,
+    scene_path: Option<PathBuf>,
+    /// The node of the scene graph the keys edit
+    selected_node: NodeId,
+    /// How many times R was pressed, it picks the palette scheme and seed
+    palette_count: u64,
+    /// Where the palette seeds start, from --seed
+    palette_seed: u64,
     world: World,
     modifiers: Modifiers,
+    screenshot_requested: bool,
     cursor_pos: Vec2,
     keys_held: HashSet<KeyCode>
 }
 
 impl App {
-    pub fn new(render_env: &RenderEnv) -> anyhow::Result<Self> {
+    pub fn new(render_env: &RenderEnv, options: &Options) -> anyhow::Result<Self> {
         let time = Time::new(render_env);
-        let camera = Camera::new(render_env);
-        let world = World::new(render_env, camera.buffer());
+        let mut camera = Camera::new(render_env);
+        let mut scene = match (&options.scene, options.generator) {
+            (Some(_), Some(_)) => anyhow::bail!("--scene and --generate can't be used together"),
+            (Some(path), None) => Scene::load(path)?,
+            (None, Some(generator)) => Scene::generate(generator, options.seed),
+            (None, None) => Scene::default_scene(),
+        };
+        let environment_path = options.environment.as_ref().or(scene.environment.as_ref());
+        let environment = match environment_path {
+            Some(path) => Environment::load(render_env, path)?,
+            None => Environment::none(render_env),
+        };
+        // the sky replaces the gradient unless there is a map to light the scene
+        let sky = Sky::new(render_env, environment_path.is_none());
+        if let Some(viewpoint) = scene.camera {
+            camera.set_viewpoint(render_env, viewpoint);
+        }
+        let world = World::new(render_env, camera.buffer(), &environment, &sky, &scene);
+        scene.graph.take_changed();
         let ray_tracer = Raytracer::new(render_env, &world.bind_group_layout, &time.bind_layout);
+        let denoiser = Denoiser::new(render_env, &ray_tracer);
+        let post_process = PostProcess::new(render_env, &ray_tracer, &time.bind_layout);
         let screen = Screen::new(
             render_env,
             &ray_tracer.sampler_bind_layout,
+            &ray_tracer.stats_view(),
         );
         let modifiers = Modifiers::default();
 
         Ok(Self {
             time,
             ray_tracer,
+            denoiser,
+            post_process,
             screen,
             world,
             camera,
+            environment,
+            sky,
+            scene,
+            scene_path: options.scene.clone(),
+            selected_node: 0,
+            palette_count: 0,
+            palette_seed: options.seed.unwrap_or_default(),
             modifiers,
+            screenshot_requested: false,
             cursor_pos: Vec2::ZERO,
             keys_held: HashSet::new(),
         })
     }
 
     pub fn on_key_input(&mut self, render_env: &RenderEnv, input: winit::event::KeyEvent) {
-        match input {
-            KeyEvent { physical_key: PhysicalKey::Code(key), state, .. } => {
-                if !state.is_pressed() {
-                    self.keys_held.remove(&key);
-                    return;
-                }
+        if let KeyEvent { physical_key: PhysicalKey::Code(key), state, .. } = input {
+            if !state.is_pressed() {
+                self.keys_held.remove(&key);
+                return;
+            }
 
-                self.keys_held.insert(key);
-                self.camera.key_press(render_env, key, &self.keys_held);
-                if key == KeyCode::KeyR {
-                    self.world.reload(render_env, self.camera.buffer());
-                    self.camera.scene_was_updated(render_env);
+            self.keys_held.insert(key);
+            self.camera.key_press(render_env, key, &self.keys_held);
+            self.screen.key_press(key);
+            self.post_process.key_press(render_env, key);
+            if key == KeyCode::KeyR {
+                self.next_palette(render_env);
+            }
+            if key == KeyCode::F5 {
+                self.reload_scene(render_env);
+            }
+            if key == KeyCode::F6 {
+                match save_obj(&self.scene) {
+                    Ok(path) => println!("\nSaved scene to {}", path.display()),
+                    Err(e) => println!("\nUnable to save the scene: {:?}", e),
                 }
             }
-            _ => (),
+            self.edit_graph(key);
+            if self.scene.graph.take_changed() {
+                self.reload_world(render_env);
+            }
+            let light_changed = self.world.lights.key_press(render_env, key);
+            if light_changed {
+                // The world builds its lights from the scene whenever it is reloaded
+                self.scene.lights = self.world.lights.lights().to_vec();
+            }
+            if self.environment.key_press(render_env, key) || self.sky.key_press(render_env, key) || light_changed {
+                self.camera.scene_was_updated(render_env);
+            }
+            if key == KeyCode::KeyF {
+                self.denoiser.toggle();
+            }
+            if key == KeyCode::KeyP {
+                self.screenshot_requested = true;
+            }
+            if key == KeyCode::KeyE || key == KeyCode::KeyL {
+                self.export(render_env, key);
+            }
         }
     }
 
@@ -92,8 +172,12 @@ impl App {
 
     pub fn update(&mut self, render_env: &RenderEnv) {
         self.time.add_delta(render_env, 0.01);
+        if self.sky.update_time(render_env, &self.time) {
+            self.camera.scene_was_updated(render_env);
+        }
         // TODO: Fix this awful solution lol
         self.camera.key_press(render_env, KeyCode::F35, &self.keys_held);
+        self.screen.update(render_env, self.camera.samples_taken());
     }
 
     pub fn render(&mut self, render_env: &RenderEnv) -> anyhow::Result<()> {
@@ -103,18 +187,131 @@ impl App {
         let current_texture = render_env.surface.get_current_texture()?;
         let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
 
-        if !self.camera.render_finished() {
-            self.ray_tracer.compute(&mut encoder, &self.world.bind_group, &self.time.bind_group);
+        let computing = !self.camera.render_finished();
+        if computing {
+            let readback_tag = self.camera.is_adaptive().then(|| self.camera.frame_tag());
+            self.ray_tracer.compute(&mut encoder, &self.world.bind_group, &self.time.bind_group, readback_tag);
         }
-        self.screen.render(&mut encoder, &current_texture, &self.ray_tracer.sampler_bind_group);
+        let image_bind_group = if self.denoiser.is_enabled() {
+            self.denoiser.denoise(&mut encoder);
+            &self.denoiser.output_bind_group
+        } else {
+            &self.ray_tracer.sampler_bind_group
+        };
+        let image_bind_group = self.post_process.apply(&mut encoder, image_bind_group, &self.time.bind_group);
+        let view = current_texture.texture.create_view(&TextureViewDescriptor::default());
+        self.screen.render(&mut encoder, &view, image_bind_group);
 
         queue.submit(Some(encoder.finish()));
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            match save_screenshot(render_env, &self.screen, image_bind_group, &self.camera) {
+                Ok(path) => println!("\nSaved screenshot to {}", path.display()),
+                Err(e) => println!("\nUnable to save screenshot: {:?}", e),
+            }
+        }
+        // The count lags a frame or two behind so the cpu doesn't wait for the gpu
+        if let Some((tag, active_pixels)) = self.ray_tracer.poll_active_pixels(render_env) {
+            self.camera.set_active_pixels(tag, active_pixels);
+        }
         // this needs to be after the submit
         self.camera.increase_frame(render_env);
         current_texture.present();
         Ok(())
     }
 
+    fn reload_world(&mut self, render_env: &RenderEnv) {
+        self.world.reload(render_env, self.camera.buffer(), &self.environment, &self.sky, &self.scene);
+        self.scene.graph.take_changed();
+        self.camera.scene_was_updated(render_env);
+    }
+
+    /// Tab selects the next node, backquote hides or shows it and home and
+    /// end move it up and down
+    fn edit_graph(&mut self, key: KeyCode) {
+        let graph = &mut self.scene.graph;
+        if graph.is_empty() {
+            return;
+        }
+
+        let id = self.selected_node.min(graph.len() - 1);
+        let node = graph.node(id);
+        match key {
+            KeyCode::Tab => self.selected_node = (id + 1) % graph.len(),
+            KeyCode::Backquote => graph.set_visible(id, !node.visible),
+            KeyCode::Home | KeyCode::End => {
+                let step = if key == KeyCode::Home { 0.1 } else { -0.1 };
+                graph.set_transform(id, Mat4::from_translation(Vec3::Y * step) * node.transform);
+            }
+            _ => return,
+        }
+
+        let node = graph.node(self.selected_node);
+        let state = if graph.is_visible(self.selected_node) { "shown" } else { "hidden" };
+        let position = graph.world_transform(self.selected_node).w_axis;
+        println!(
+            "\nNode: {} ({}) at {:.2} {:.2} {:.2} tags [{}]",
+            node.name,
+            state,
+            position.x,
+            position.y,
+            position.z,
+            node.tags.join(", ")
+        );
+    }
+
+    /// Schemes take turns and every press gets the next seed so the same
+    /// presses give the same materials
+    fn next_palette(&mut self, render_env: &RenderEnv) {
+        let scheme = Scheme::ALL[(self.palette_count % Scheme::ALL.len() as u64) as usize];
+        let seed = self.palette_seed.wrapping_add(self.palette_count);
+        self.palette_count += 1;
+        self.scene.randomize_materials(scheme, seed);
+        self.reload_world(render_env);
+        println!("\nPalette: {:?} with seed {}", scheme, seed);
+    }
+
+    /// Reads the scene file again so it can be edited while the app is running
+    fn reload_scene(&mut self, render_env: &RenderEnv) {
+        let Some(path) = self.scene_path.clone() else {
+            return;
+        };
+
+        match Scene::load(&path) {
+            Ok(scene) => {
+                if let Some(viewpoint) = scene.camera {
+                    self.camera.set_viewpoint(render_env, viewpoint);
+                }
+                self.scene = scene;
+                self.selected_node = 0;
+                self.reload_world(render_env);
+                println!("\nReloaded {}", path.display());
+            }
+            Err(e) => println!("\nUnable to reload the scene: {:?}", e),
+        }
+    }
+
+    /// E saves an OpenEXR, in full float precision when control is held,
+    /// and L saves a Radiance .hdr
+    fn export(&self, render_env: &RenderEnv, key: KeyCode) {
+        let layers = self.ray_tracer.read_layers(render_env);
+        let saved = if key == KeyCode::KeyE {
+            let precision = if self.modifiers.state().control_key() {
+                ExrPrecision::Float
+            } else {
+                ExrPrecision::Half
+            };
+            save_exr(&layers, precision, &self.camera)
+        } else {
+            save_hdr(&layers)
+        };
+
+        match saved {
+            Ok(path) => println!("\nSaved render to {}", path.display()),
+            Err(e) => println!("\nUnable to save render: {:?}", e),
+        }
+    }
+
     fn performance(&mut self) {
 
     }
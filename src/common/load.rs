@@ -1,4 +1,7 @@
+use std::path::Path;
+
 use crate::render_env::RenderEnv;
+use anyhow::Context;
 use image::GenericImageView;
 use wgpu::{BindGroup, BindGroupLayout};
 
@@ -7,62 +10,245 @@ pub struct Image {
     pub bind_layout: BindGroupLayout,
 }
 
-impl Image {
-    pub fn new(render_env: &RenderEnv, file: &str) -> anyhow::Result<Self> {
-        let device = &render_env.device;
-        let queue = &render_env.queue;
+/// Linear high dynamic range pixels, one rgba value per pixel starting at the top left
+pub struct FloatImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
 
-        let bytes = std::fs::read(file)?;
-        let image = image::load_from_memory(&bytes)?;
-        let image_rgba = image.to_rgba8();
-        let dimensions = image.dimensions();
+impl FloatImage {
+    /// Loads a Radiance .hdr or an OpenEXR file
+    pub fn load(file: &Path) -> anyhow::Result<Self> {
+        let extension = file.extension().and_then(|e| e.to_str()).unwrap_or_default();
+        match extension.to_ascii_lowercase().as_str() {
+            "hdr" => {
+                let reader = std::io::BufReader::new(std::fs::File::open(file)?);
+                let decoder = image::codecs::hdr::HdrDecoder::new(reader)?;
+                let meta = decoder.metadata();
+                let pixels = decoder
+                    .read_image_hdr()?
+                    .into_iter()
+                    .map(|p| [p.0[0], p.0[1], p.0[2], 1.0])
+                    .collect();
+                Ok(Self { width: meta.width, height: meta.height, pixels })
+            }
+            "exr" => {
+                let image = exr::prelude::read_first_rgba_layer_from_file(
+                    file,
+                    |resolution, _| FloatImage {
+                        width: resolution.width() as u32,
+                        height: resolution.height() as u32,
+                        pixels: vec![[0.0; 4]; resolution.area()],
+                    },
+                    |image, position, (r, g, b, a): (f32, f32, f32, f32)| {
+                        let index = position.y() * image.width as usize + position.x();
+                        image.pixels[index] = [r, g, b, a];
+                    },
+                )?;
+                Ok(image.layer_data.channel_data.pixels)
+            }
+            _ => anyhow::bail!("{} is not a .hdr or .exr file", file.display()),
+        }
+    }
 
-        let texture_size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
-        };
-
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            // All textures are stored as 3D, we represent our 2D texture
-            // by setting depth to 1.
-            size: texture_size,
-            mip_level_count: 1, // We'll talk about this a little later
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            // Most images are stored using sRGB so we need to reflect that here.
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
-            // COPY_DST means that we want to copy data to this texture
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            label: Some("diffuse_texture"),
-            // This is the same as with the SurfaceConfig. It
-            // specifies what texture formats can be used to
-            // create TextureViews for this texture. The base
-            // texture format (Rgba8UnormSrgb in this case) is
-            // always supported. Note that using a different
-            // texture format is not supported on the WebGL2
-            // backend.
-            view_formats: &[],
-        });
+    /// Averages every 2 by 2 block, an odd last row or column is averaged with itself
+    pub fn half_size(&self) -> Self {
+        let width = self.width.div_ceil(2).max(1);
+        let height = self.height.div_ceil(2).max(1);
+        let pixel = |x: u32, y: u32| self.pixels[(y.min(self.height - 1) * self.width + x.min(self.width - 1)) as usize];
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let block = [pixel(2 * x, 2 * y), pixel(2 * x + 1, 2 * y), pixel(2 * x, 2 * y + 1), pixel(2 * x + 1, 2 * y + 1)];
+                let mut sum = [0.0; 4];
+                for p in block {
+                    for c in 0..4 {
+                        sum[c] += p[c] / 4.0;
+                    }
+                }
+                sum
+            })
+            .collect();
+        Self { width, height, pixels }
+    }
+
+    pub fn to_texture(&self, render_env: &RenderEnv) -> wgpu::Texture {
+        upload_texture(
+            render_env,
+            self.width,
+            self.height,
+            wgpu::TextureFormat::Rgba32Float,
+            bytemuck::cast_slice(&self.pixels),
+        )
+    }
+}
+
+/// Creates a 2D texture that can be bound in shaders filled with `pixels`
+pub fn upload_texture(
+    render_env: &RenderEnv,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    pixels: &[u8],
+) -> wgpu::Texture {
+    let device = &render_env.device;
+    let queue = &render_env.queue;
+
+    let texture_size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
 
-        queue.write_texture(
-            // Tells wgpu where to copy the pixel data
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        // All textures are stored as 3D, we represent our 2D texture
+        // by setting depth to 1.
+        size: texture_size,
+        mip_level_count: 1, // We'll talk about this a little later
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
+        // COPY_DST means that we want to copy data to this texture
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        label: Some("diffuse_texture"),
+        // This is the same as with the SurfaceConfig. It
+        // specifies what texture formats can be used to
+        // create TextureViews for this texture. The base
+        // texture format is always supported. Note that using
+        // a different texture format is not supported on the
+        // WebGL2 backend.
+        view_formats: &[],
+    });
+
+    let pixel_size = format.block_size(None).expect("Texture format can't be uploaded");
+    queue.write_texture(
+        // Tells wgpu where to copy the pixel data
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        // The actual pixel data
+        pixels,
+        // The layout of the texture
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(pixel_size * width),
+            rows_per_image: Some(height),
+        },
+        texture_size,
+    );
+
+    texture
+}
+
+/// Creates a 2D texture array with one layer per entry in `layers`, which all
+/// need to be `width` by `height`
+pub fn upload_texture_array(
+    render_env: &RenderEnv,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    layers: &[&[u8]],
+) -> wgpu::Texture {
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: layers.len() as u32,
+    };
+
+    let texture = render_env.device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        label: Some("Texture array"),
+        view_formats: &[],
+    });
+
+    let pixel_size = format.block_size(None).expect("Texture format can't be uploaded");
+    for (layer, pixels) in layers.iter().enumerate() {
+        render_env.queue.write_texture(
             wgpu::ImageCopyTexture {
                 texture: &texture,
                 mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+                origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
                 aspect: wgpu::TextureAspect::All,
             },
-            // The actual pixel data
-            &image_rgba,
-            // The layout of the texture
+            pixels,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+                bytes_per_row: Some(pixel_size * width),
+                rows_per_image: Some(height),
             },
-            texture_size,
+            wgpu::Extent3d { depth_or_array_layers: 1, ..size },
+        );
+    }
+
+    texture
+}
+
+/// Creates a 3D texture filled with `voxels`, x changes fastest and then y
+pub fn upload_texture_3d(render_env: &RenderEnv, size: [u32; 3], format: wgpu::TextureFormat, voxels: &[u8]) -> wgpu::Texture {
+    let [width, height, depth] = size;
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: depth,
+    };
+
+    let texture = render_env.device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        label: Some("3D texture"),
+        view_formats: &[],
+    });
+
+    let voxel_size = format.block_size(None).expect("Texture format can't be uploaded");
+    render_env.queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        voxels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(voxel_size * width),
+            rows_per_image: Some(height),
+        },
+        size,
+    );
+
+    texture
+}
+
+impl Image {
+    pub fn new(render_env: &RenderEnv, file: &str) -> anyhow::Result<Self> {
+        let device = &render_env.device;
+
+        let bytes = std::fs::read(file).with_context(|| format!("Unable to read {}", file))?;
+        let image = image::load_from_memory(&bytes)?;
+        let image_rgba = image.to_rgba8();
+        let dimensions = image.dimensions();
+
+        // Most images are stored using sRGB so we need to reflect that here.
+        let texture = upload_texture(
+            render_env,
+            dimensions.0,
+            dimensions.1,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            &image_rgba,
         );
 
         let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
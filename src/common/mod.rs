@@ -1,11 +1,15 @@
 mod time;
+mod color;
 mod consts;
 mod load;
+mod readback;
 mod vertex;
 mod shapes;
 
+pub use color::{linear_to_srgb, srgb_to_linear};
 pub use consts::*;
 pub use time::Time;
-pub use load::Image;
+pub use load::{upload_texture, upload_texture_3d, upload_texture_array, FloatImage, Image};
+pub use readback::read_texture;
 pub use vertex::Vertex;
 pub use shapes::*;
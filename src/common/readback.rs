@@ -0,0 +1,49 @@
+use wgpu::*;
+
+use crate::render_env::RenderEnv;
+
+/// Copies a texture back to the cpu, the rows are tightly packed so the
+/// padding wgpu needs for `bytes_per_row` is already removed
+pub fn read_texture(render_env: &RenderEnv, texture: &Texture) -> Vec<u8> {
+    let device = &render_env.device;
+    let size = texture.size();
+    let pixel_size = texture.format().block_size(None).expect("Texture format can't be copied");
+    let row_size = size.width * pixel_size;
+    let padded_row_size = row_size.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT) * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Readback buffer"),
+        size: (padded_row_size * size.height) as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        ImageCopyBuffer {
+            buffer: &buffer,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_size),
+                rows_per_image: Some(size.height),
+            },
+        },
+        size,
+    );
+    render_env.queue.submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    slice.map_async(MapMode::Read, |_| ());
+    device.poll(Maintain::Wait);
+
+    let padded = slice.get_mapped_range();
+    let pixels = padded
+        .chunks(padded_row_size as usize)
+        .flat_map(|row| &row[..row_size as usize])
+        .copied()
+        .collect();
+    drop(padded);
+    buffer.unmap();
+    pixels
+}
@@ -6,6 +6,7 @@ use crate::common::UNIFORM_BUFFER_BINDING;
 
 pub struct Time {
     time: f32,
+    delta: f32,
     pub time_uniform: Buffer,
     pub delta_uniform: Buffer,
     pub bind_layout: BindGroupLayout,
@@ -59,6 +60,7 @@ impl Time {
 
         Self {
             time: 0.0,
+            delta: 0.0,
             time_uniform,
             delta_uniform,
             bind_layout,
@@ -72,7 +74,13 @@ impl Time {
     pub fn add_delta(&mut self, render_env: &RenderEnv, delta: f32) {
         let queue = &render_env.queue;
         self.time += delta;
+        self.delta = delta;
         queue.write_buffer(&self.time_uniform, 0, &self.time.to_ne_bytes());
         queue.write_buffer(&self.delta_uniform, 0, &delta.to_ne_bytes());
     }
+
+    /// The delta from the last call to [`Time::add_delta`]
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
 }
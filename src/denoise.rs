@@ -0,0 +1,215 @@
+use bytemuck::{bytes_of, Pod, Zeroable};
+use wgpu::{*, util::{BufferInitDescriptor, DeviceExt}};
+
+use crate::{
+    common::UNIFORM_BUFFER_BINDING,
+    raytracing::{Raytracer, ACCUMULATION_FORMAT},
+    render_env::RenderEnv,
+};
+
+/// Each pass doubles the distance between the kernel taps
+const PASSES: u32 = 5;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct Params {
+    step_size: i32,
+    first_pass: u32,
+    last_pass: u32,
+    padding: u32,
+}
+
+/// Smooths the accumulated image using the albedo, normal and depth
+/// of the first hit so the edges between objects stay sharp
+pub struct Denoiser {
+    pipeline: ComputePipeline,
+    pass_bind_groups: Vec<BindGroup>,
+    size: Extent3d,
+    enabled: bool,
+    pub output_bind_group: BindGroup,
+}
+
+impl Denoiser {
+    pub fn new(render_env: &RenderEnv, ray_tracer: &Raytracer) -> Self {
+        let device = &render_env.device;
+        let size = ray_tracer.size();
+        let shader = device.create_shader_module(include_wgsl!("./shaders/denoise.wgsl"));
+
+        // The passes ping pong between these two
+        let ping = filter_texture(device, size);
+        let pong = filter_texture(device, size);
+        let ping_view = ping.create_view(&TextureViewDescriptor::default());
+        let pong_view = pong.create_view(&TextureViewDescriptor::default());
+
+        let color_view = ray_tracer.color_view();
+        let albedo_view = ray_tracer.albedo_view();
+        let normal_depth_view = ray_tracer.normal_depth_view();
+        let stats_view = ray_tracer.stats_view();
+
+        let input_texture = |binding| BindGroupLayoutEntry {
+            binding,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            visibility: ShaderStages::COMPUTE,
+            count: None,
+        };
+
+        let bind_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Denoise bind group layout"),
+            entries: &[
+                input_texture(0),
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: ACCUMULATION_FORMAT,
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    visibility: ShaderStages::COMPUTE,
+                    count: None,
+                },
+                input_texture(2),
+                input_texture(3),
+                input_texture(4),
+                BindGroupLayoutEntry {
+                    binding: 5,
+                    ty: UNIFORM_BUFFER_BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    count: None,
+                },
+            ],
+        });
+
+        let pass_bind_groups = (0..PASSES)
+            .map(|pass| {
+                let params = device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("Denoise params buffer"),
+                    contents: bytes_of(&Params {
+                        step_size: 1 << pass,
+                        first_pass: (pass == 0) as u32,
+                        last_pass: (pass == PASSES - 1) as u32,
+                        padding: 0,
+                    }),
+                    usage: BufferUsages::UNIFORM,
+                });
+
+                let (input, output) = match pass {
+                    0 => (&color_view, &ping_view),
+                    p if p % 2 == 1 => (&ping_view, &pong_view),
+                    _ => (&pong_view, &ping_view),
+                };
+
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Denoise bind group"),
+                    layout: &bind_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(input),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(output),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: BindingResource::TextureView(&albedo_view),
+                        },
+                        BindGroupEntry {
+                            binding: 3,
+                            resource: BindingResource::TextureView(&normal_depth_view),
+                        },
+                        BindGroupEntry {
+                            binding: 4,
+                            resource: BindingResource::TextureView(&stats_view),
+                        },
+                        BindGroupEntry {
+                            binding: 5,
+                            resource: params.as_entire_binding(),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Denoise Pipeline Layout"),
+            bind_group_layouts: &[&bind_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Denoise pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let output_view = if PASSES % 2 == 1 { &ping_view } else { &pong_view };
+        let output_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Denoised screen bind group"),
+            layout: &ray_tracer.sampler_bind_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(output_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        Self {
+            pipeline,
+            pass_bind_groups,
+            size,
+            enabled: false,
+            output_bind_group,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn denoise(&self, encoder: &mut CommandEncoder) {
+        let mut denoise_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Denoise pass"),
+            timestamp_writes: None,
+        });
+
+        denoise_pass.set_pipeline(&self.pipeline);
+        for bind_group in &self.pass_bind_groups {
+            denoise_pass.set_bind_group(0, bind_group, &[]);
+            denoise_pass.dispatch_workgroups(self.size.width.div_ceil(16), self.size.height.div_ceil(16), 1);
+        }
+    }
+}
+
+fn filter_texture(device: &Device, size: Extent3d) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some("Denoise texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: ACCUMULATION_FORMAT,
+        usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
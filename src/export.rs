@@ -0,0 +1,85 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use exr::prelude::*;
+use image::{codecs::hdr::HdrEncoder, Rgb};
+
+use crate::{
+    raytracing::RenderLayers,
+    resources::Camera,
+    screenshot::{timestamp, SCREENSHOT_DIR},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+/// Saves the linear render as a multi layer OpenEXR with a beauty layer
+/// and the albedo, normal and depth feature layers
+pub fn save_exr(layers: &RenderLayers, precision: ExrPrecision, camera: &Camera) -> anyhow::Result<PathBuf> {
+    let size = (layers.width as usize, layers.height as usize);
+    let samples = |values: Vec<f32>| match precision {
+        ExrPrecision::Half => FlatSamples::F16(values.into_iter().map(f16::from_f32).collect()),
+        ExrPrecision::Float => FlatSamples::F32(values),
+    };
+    let channel = |name: &str, pixels: &[[f32; 4]], index: usize| {
+        AnyChannel::new(name, samples(pixels.iter().map(|p| p[index]).collect()))
+    };
+    let layer = |attributes: LayerAttributes, channels: Vec<AnyChannel<FlatSamples>>| {
+        Layer::new(size, attributes, Encoding::FAST_LOSSLESS, AnyChannels::sort(channels.into()))
+    };
+
+    let mut beauty_attributes = LayerAttributes::named("beauty");
+    beauty_attributes.other = camera
+        .metadata()
+        .into_iter()
+        .map(|(key, value)| (Text::from(key), AttributeValue::Text(Text::from(value.as_str()))))
+        .collect::<HashMap<_, _>>();
+
+    // Nothing was hit so the background is infinitely far away
+    let depth: Vec<f32> = layers
+        .normal_depth
+        .iter()
+        .map(|p| if p[3] < 0.0 { f32::INFINITY } else { p[3] })
+        .collect();
+
+    let exr_layers: Vec<_> = vec![
+        layer(beauty_attributes, vec![
+            channel("R", &layers.beauty, 0),
+            channel("G", &layers.beauty, 1),
+            channel("B", &layers.beauty, 2),
+            channel("A", &layers.beauty, 3),
+        ]),
+        layer(LayerAttributes::named("albedo"), vec![
+            channel("R", &layers.albedo, 0),
+            channel("G", &layers.albedo, 1),
+            channel("B", &layers.albedo, 2),
+        ]),
+        layer(LayerAttributes::named("normal"), vec![
+            channel("X", &layers.normal_depth, 0),
+            channel("Y", &layers.normal_depth, 1),
+            channel("Z", &layers.normal_depth, 2),
+        ]),
+        layer(LayerAttributes::named("depth"), vec![AnyChannel::new("Z", samples(depth))]),
+    ];
+
+    let image = Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(size)), exr_layers);
+    let path = export_path("exr")?;
+    image.write().to_file(&path)?;
+    Ok(path)
+}
+
+/// Saves the beauty layer as a Radiance .hdr
+pub fn save_hdr(layers: &RenderLayers) -> anyhow::Result<PathBuf> {
+    let pixels: Vec<Rgb<f32>> = layers.beauty.iter().map(|p| Rgb([p[0], p[1], p[2]])).collect();
+    let path = export_path("hdr")?;
+    let file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+    HdrEncoder::new(file).encode(&pixels, layers.width as usize, layers.height as usize)?;
+    Ok(path)
+}
+
+fn export_path(extension: &str) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(SCREENSHOT_DIR)?;
+    Ok(PathBuf::from(SCREENSHOT_DIR).join(format!("render_{}.{}", timestamp(), extension)))
+}
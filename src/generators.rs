@@ -0,0 +1,234 @@
+use std::{f32::consts::PI, str::FromStr};
+
+use glam::{vec3, Mat4, Quat, Vec3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+
+use crate::{
+    resources::{InstanceObject, Material, Primitive, Sphere, Viewpoint},
+    scene::Scene,
+    scene_graph::SceneGraph,
+};
+
+/// Scenes made from code, the same seed always gives the same scene
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Generator {
+    /// The cover of Ray Tracing in One Weekend
+    OneWeekend,
+    /// Metallic going up to the right and roughness going up to the back
+    SphereGrid,
+    /// Spheres of random sizes that don't touch
+    SphereField,
+    CornellBox,
+}
+
+impl Generator {
+    pub const NAMES: &'static str = "one_weekend, sphere_grid, sphere_field and cornell_box";
+}
+
+impl FromStr for Generator {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "one_weekend" => Self::OneWeekend,
+            "sphere_grid" => Self::SphereGrid,
+            "sphere_field" => Self::SphereField,
+            "cornell_box" => Self::CornellBox,
+            _ => anyhow::bail!("There is no generator named {}, there are {}", name, Generator::NAMES),
+        })
+    }
+}
+
+impl Scene {
+    /// Builds the scene of `generator` from `seed`, or from a random seed
+    /// that is printed so the scene can be made again
+    pub fn generate(generator: Generator, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(rand::random);
+        println!("\nGenerated {:?} with seed {}", generator, seed);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut builder = Builder::default();
+        match generator {
+            Generator::OneWeekend => one_weekend(&mut builder, &mut rng),
+            Generator::SphereGrid => sphere_grid(&mut builder, &mut rng),
+            Generator::SphereField => sphere_field(&mut builder, &mut rng),
+            Generator::CornellBox => cornell_box(&mut builder),
+        }
+        builder.scene
+    }
+}
+
+fn diffuse(color: Vec3) -> Material {
+    Material { color: color.extend(1.0), roughness: 1.0, ..Default::default() }
+}
+
+fn metal(color: Vec3, roughness: f32) -> Material {
+    Material { color: color.extend(1.0), metallic: 1.0, roughness, ..Default::default() }
+}
+
+fn glass() -> Material {
+    Material::preset("glass").unwrap_or_default()
+}
+
+struct Builder {
+    scene: Scene,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            scene: Scene {
+                materials: Vec::new(),
+                spheres: Vec::new(),
+                primitives: Vec::new(),
+                meshes: Vec::new(),
+                graph: SceneGraph::new(),
+                lights: Vec::new(),
+                textures: Vec::new(),
+                images: Vec::new(),
+                fog: None,
+                volumes: Vec::new(),
+                grid: None,
+                camera: None,
+                environment: None,
+            },
+        }
+    }
+}
+
+impl Builder {
+    fn material(&mut self, material: Material) -> u32 {
+        self.scene.materials.push(material);
+        self.scene.materials.len() as u32 - 1
+    }
+
+    fn sphere(&mut self, sphere: Sphere) {
+        let index = self.scene.spheres.len();
+        let name = format!("sphere {}", index);
+        self.scene.graph.add(name, Mat4::IDENTITY, Some(InstanceObject::Sphere(index)), None).unwrap();
+        self.scene.spheres.push(sphere);
+    }
+
+    fn primitive(&mut self, primitive: Primitive) {
+        let index = self.scene.primitives.len();
+        let name = format!("primitive {}", index);
+        self.scene.graph.add(name, Mat4::IDENTITY, Some(InstanceObject::Primitive(index)), None).unwrap();
+        self.scene.primitives.push(primitive);
+    }
+
+    fn look_at(&mut self, position: Vec3, target: Vec3, vertical_fov: f32) {
+        self.scene.camera = Some(Viewpoint {
+            position,
+            direction: target - position,
+            fov: vertical_fov.to_radians(),
+        });
+    }
+}
+
+/// Small spheres on a grid around three big ones, following the book
+fn one_weekend(builder: &mut Builder, rng: &mut StdRng) {
+    let ground = builder.material(diffuse(Vec3::splat(0.5)));
+    builder.sphere(Sphere::new(vec3(0.0, -1000.0, 0.0), 1000.0, ground));
+
+    let glass = builder.material(glass());
+    for a in -11..11 {
+        for b in -11..11 {
+            let center = vec3(a as f32 + 0.9 * rng.gen::<f32>(), 0.2, b as f32 + 0.9 * rng.gen::<f32>());
+            if center.distance(vec3(4.0, 0.2, 0.0)) <= 0.9 {
+                continue;
+            }
+            let choice: f32 = rng.gen();
+            let material = if choice < 0.8 {
+                builder.material(diffuse(rng.gen::<Vec3>() * rng.gen::<Vec3>()))
+            } else if choice < 0.95 {
+                let color = rng.gen::<Vec3>() * 0.5 + 0.5;
+                builder.material(metal(color, rng.gen_range(0.0..0.5)))
+            } else {
+                glass
+            };
+            builder.sphere(Sphere::new(center, 0.2, material));
+        }
+    }
+
+    let brown = builder.material(diffuse(vec3(0.4, 0.2, 0.1)));
+    let steel = builder.material(metal(vec3(0.7, 0.6, 0.5), 0.0));
+    builder.sphere(Sphere::new(vec3(0.0, 1.0, 0.0), 1.0, glass));
+    builder.sphere(Sphere::new(vec3(-4.0, 1.0, 0.0), 1.0, brown));
+    builder.sphere(Sphere::new(vec3(4.0, 1.0, 0.0), 1.0, steel));
+
+    builder.look_at(vec3(13.0, 2.0, 3.0), Vec3::ZERO, 20.0);
+}
+
+/// One color chosen by the seed on every sphere so only the sweep changes
+fn sphere_grid(builder: &mut Builder, rng: &mut StdRng) {
+    const SIZE: usize = 7;
+
+    let ground = builder.material(diffuse(Vec3::splat(0.6)));
+    builder.primitive(Primitive::plane(Vec3::ZERO, Vec3::Y, ground));
+
+    let color = rng.gen::<Vec3>() * 0.7 + 0.3;
+    for row in 0..SIZE {
+        for column in 0..SIZE {
+            let metallic = column as f32 / (SIZE - 1) as f32;
+            let roughness = row as f32 / (SIZE - 1) as f32;
+            let material = builder.material(Material {
+                color: color.extend(1.0),
+                metallic,
+                roughness,
+                ..Default::default()
+            });
+            let offset = (SIZE - 1) as f32 * 0.5;
+            let center = vec3(column as f32 - offset, 0.4, -(row as f32) + offset);
+            builder.sphere(Sphere::new(center, 0.4, material));
+        }
+    }
+
+    builder.look_at(vec3(0.0, 5.0, 9.0), vec3(0.0, 0.0, -0.5), 40.0);
+}
+
+fn sphere_field(builder: &mut Builder, rng: &mut StdRng) {
+    const MATERIALS: u32 = 8;
+
+    let ground = builder.material(diffuse(Vec3::splat(0.5)));
+    builder.primitive(Primitive::plane(Vec3::ZERO, Vec3::Y, ground));
+
+    for _ in 0..MATERIALS {
+        let color = rng.gen::<Vec3>() * 0.8 + 0.2;
+        let material = if rng.gen_bool(0.3) { metal(color, rng.gen_range(0.0..0.4)) } else { diffuse(color) };
+        builder.material(material);
+    }
+    for mut sphere in Sphere::random_bunch(rng, 60, MATERIALS) {
+        // After the ground
+        sphere.material_index += 1;
+        builder.sphere(sphere);
+    }
+
+    builder.look_at(vec3(0.0, 6.0, 14.0), Vec3::ZERO, 40.0);
+}
+
+/// Two units wide and high with the camera inside, so only the ceiling light lights it
+fn cornell_box(builder: &mut Builder) {
+    let white = builder.material(diffuse(Vec3::splat(0.73)));
+    let red = builder.material(diffuse(vec3(0.65, 0.05, 0.05)));
+    let green = builder.material(diffuse(vec3(0.12, 0.45, 0.15)));
+    let black = builder.material(diffuse(Vec3::ZERO));
+    let light = builder.material(Material { emission: vec3(17.0, 12.0, 4.0).extend(0.0), ..diffuse(Vec3::ONE) });
+
+    // The floor, ceiling and sides go on past the camera so nothing outside shows
+    let (x, y, z) = (Vec3::X * 2.0, Vec3::Y * 2.0, Vec3::Z * 5.5);
+    builder.primitive(Primitive::quad(vec3(-1.0, 0.0, 4.5), x, -z, white));
+    builder.primitive(Primitive::quad(vec3(-1.0, 2.0, -1.0), x, z, white));
+    builder.primitive(Primitive::quad(vec3(-1.0, 0.0, -1.0), x, y, white));
+    builder.primitive(Primitive::quad(vec3(-1.0, 0.0, 4.5), -z, y, red));
+    builder.primitive(Primitive::quad(vec3(1.0, 0.0, -1.0), z, y, green));
+    builder.primitive(Primitive::quad(vec3(1.0, 0.0, 4.5), -x, y, black));
+    builder.primitive(Primitive::quad(vec3(-0.25, 1.99, -0.25), Vec3::X * 0.5, Vec3::Z * 0.5, light));
+
+    let tall = vec3(0.6, 1.2, 0.6);
+    let short = Vec3::splat(0.6);
+    builder.primitive(Primitive::cuboid(vec3(-0.35, 0.6, -0.35), tall, Quat::from_rotation_y(PI / 12.0), white));
+    builder.primitive(Primitive::cuboid(vec3(0.35, 0.3, 0.3), short, Quat::from_rotation_y(-PI / 10.0), white));
+
+    builder.look_at(vec3(0.0, 1.0, 3.9), vec3(0.0, 1.0, 0.0), 38.0);
+}
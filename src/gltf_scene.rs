@@ -0,0 +1,224 @@
+use std::path::Path;
+
+use anyhow::Context;
+use glam::{Mat4, Vec2, Vec3};
+use gltf::{camera::Projection, khr_lights_punctual::Kind, mesh::Mode};
+use image::RgbaImage;
+
+use crate::{
+    resources::{InstanceObject, Light, Material, MaterialTexture, Mesh, MeshVertex, Triangle, Viewpoint, NO_TEXTURE},
+    scene::Scene,
+    scene_graph::{NodeId, SceneGraph},
+};
+
+/// glTF lights are in candela and lux, the lights here are closer to watts
+const LUMENS_PER_WATT: f32 = 683.0;
+
+impl Scene {
+    /// Reads a .gltf or .glb file with its node hierarchy, metallic roughness
+    /// materials, punctual lights and the first camera
+    pub fn load_gltf(file: &Path) -> anyhow::Result<Self> {
+        let (document, buffers, images) =
+            gltf::import(file).with_context(|| format!("Unable to load {}", file.display()))?;
+
+        let mut materials: Vec<Material> = document.materials().map(material_from).collect();
+        // Primitives without a material get a plain white one
+        let default_material = materials.len() as u32;
+        materials.push(Material::default());
+
+        let textures = document.textures().map(|t| MaterialTexture::image(t.source().index() as u32)).collect();
+        let images = images.iter().map(rgba_image).collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut meshes = Vec::new();
+        for gltf_mesh in document.meshes() {
+            let mut mesh = Mesh::default();
+            for primitive in gltf_mesh.primitives() {
+                if primitive.mode() != Mode::Triangles {
+                    println!("\nWarning: skipped a {:?} primitive in mesh {}", primitive.mode(), gltf_mesh.index());
+                    continue;
+                }
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let Some(positions) = reader.read_positions() else {
+                    continue;
+                };
+                let positions: Vec<Vec3> = positions.map(Vec3::from).collect();
+                let normals: Vec<Vec3> = reader.read_normals().map(|n| n.map(Vec3::from).collect()).unwrap_or_default();
+                let tex_coords: Vec<Vec2> = reader
+                    .read_tex_coords(0)
+                    .map(|t| t.into_f32().map(Vec2::from).collect())
+                    .unwrap_or_default();
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+
+                let offset = mesh.vertices.len() as u32;
+                let material_index = primitive.material().index().map_or(default_material, |i| i as u32);
+                mesh.vertices.extend(positions.iter().enumerate().map(|(i, &position)| {
+                    let normal = normals.get(i).copied().unwrap_or(Vec3::ZERO);
+                    MeshVertex::new(position, normal, tex_coords.get(i).copied().unwrap_or(Vec2::ZERO))
+                }));
+                mesh.triangles.extend(indices.chunks_exact(3).map(|t| Triangle {
+                    vertices: [t[0] + offset, t[1] + offset, t[2] + offset],
+                    material_index,
+                }));
+            }
+            mesh.compute_tangents();
+            meshes.push(mesh);
+        }
+
+        let mut loader = NodeLoader {
+            graph: SceneGraph::new(),
+            lights: Vec::new(),
+            camera: None,
+            meshes: &meshes,
+        };
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .with_context(|| format!("{} has no scenes", file.display()))?;
+        for node in scene.nodes() {
+            loader.add(node, None, Mat4::IDENTITY)?;
+        }
+        let NodeLoader { graph, lights, camera, .. } = loader;
+
+        Ok(Self {
+            materials,
+            spheres: Vec::new(),
+            primitives: Vec::new(),
+            meshes,
+            graph,
+            lights,
+            textures,
+            images,
+            fog: None,
+            volumes: Vec::new(),
+            grid: None,
+            camera,
+            environment: None,
+        })
+    }
+}
+
+/// Walks the nodes of the glTF scene into the scene graph, the lights and
+/// the camera are placed where the nodes end up
+struct NodeLoader<'a> {
+    graph: SceneGraph,
+    lights: Vec<Light>,
+    camera: Option<Viewpoint>,
+    meshes: &'a [Mesh],
+}
+
+impl NodeLoader<'_> {
+    fn add(&mut self, node: gltf::Node, parent: Option<NodeId>, parent_transform: Mat4) -> anyhow::Result<()> {
+        let transform = Mat4::from_cols_array_2d(&node.transform().matrix());
+        let world = parent_transform * transform;
+
+        // Names are optional in glTF and don't have to be unique
+        let mut name = node.name().map_or_else(|| format!("node {}", node.index()), str::to_string);
+        if self.graph.find(&name).is_some() {
+            name = format!("{} {}", name, node.index());
+        }
+        let object = node
+            .mesh()
+            .filter(|mesh| !self.meshes[mesh.index()].triangles.is_empty())
+            .map(|mesh| InstanceObject::Mesh(mesh.index()));
+        let id = self.graph.add(name, transform, object, None)?;
+        self.graph.set_parent(id, parent)?;
+
+        let position = world.transform_point3(Vec3::ZERO);
+        // Lights and cameras look down their -z
+        let direction = world.transform_vector3(Vec3::NEG_Z).normalize_or_zero();
+        if let Some(light) = node.light() {
+            let color = Vec3::from(light.color());
+            let intensity = light.intensity() / LUMENS_PER_WATT;
+            self.lights.push(match light.kind() {
+                Kind::Point => Light::point(position, color, intensity),
+                Kind::Spot { inner_cone_angle, outer_cone_angle } => {
+                    Light::spot(position, direction, color, intensity, inner_cone_angle, outer_cone_angle)
+                }
+                Kind::Directional => Light::directional(direction, color, intensity),
+            });
+        }
+        if let Some(camera) = node.camera() {
+            match camera.projection() {
+                Projection::Perspective(perspective) if self.camera.is_none() => {
+                    self.camera = Some(Viewpoint { position, direction, fov: perspective.yfov() });
+                }
+                Projection::Perspective(_) => (),
+                Projection::Orthographic(_) => println!("\nWarning: orthographic cameras aren't supported"),
+            }
+        }
+
+        for child in node.children() {
+            self.add(child, Some(id), world)?;
+        }
+        Ok(())
+    }
+}
+
+fn material_from(material: gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let texture_index = |info: Option<gltf::texture::Info>| match info {
+        Some(info) if info.tex_coord() == 0 => info.texture().index() as u32,
+        Some(_) => {
+            println!("\nWarning: only the first set of texture coordinates is supported");
+            NO_TEXTURE
+        }
+        None => NO_TEXTURE,
+    };
+    let emission = Vec3::from(material.emissive_factor()) * material.emissive_strength().unwrap_or(1.0);
+    let normal = material.normal_texture();
+
+    Material {
+        color: Vec3::from_slice(&pbr.base_color_factor()).extend(1.0),
+        emission: emission.extend(0.0),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        albedo_texture: texture_index(pbr.base_color_texture()),
+        roughness_metallic_texture: texture_index(pbr.metallic_roughness_texture()),
+        emission_texture: texture_index(material.emissive_texture()),
+        normal_texture: normal.as_ref().map_or(NO_TEXTURE, |n| n.texture().index() as u32),
+        normal_strength: normal.as_ref().map_or(1.0, |n| n.scale()),
+        ..Default::default()
+    }
+}
+
+/// The texture array only holds 8 bit rgba, deeper images lose their low bits
+fn rgba_image(data: &gltf::image::Data) -> anyhow::Result<RgbaImage> {
+    use gltf::image::Format;
+
+    let pixel_count = (data.width * data.height) as usize;
+    let (channels, bytes_per_channel) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let channel = |bytes: &[u8]| -> u8 {
+        match bytes_per_channel {
+            1 => bytes[0],
+            2 => (u16::from_le_bytes([bytes[0], bytes[1]]) >> 8) as u8,
+            _ => (f32::from_le_bytes(bytes.try_into().unwrap()).clamp(0.0, 1.0) * 255.0) as u8,
+        }
+    };
+
+    let mut pixels = Vec::with_capacity(pixel_count * 4);
+    for pixel in data.pixels.chunks_exact(channels * bytes_per_channel).take(pixel_count) {
+        let values: Vec<u8> = pixel.chunks_exact(bytes_per_channel).map(channel).collect();
+        pixels.extend_from_slice(&match *values.as_slice() {
+            [r] => [r, r, r, 255],
+            [luma, alpha] => [luma, luma, luma, alpha],
+            [r, g, b] => [r, g, b, 255],
+            [r, g, b, a, ..] => [r, g, b, a],
+            _ => unreachable!(),
+        });
+    }
+    RgbaImage::from_raw(data.width, data.height, pixels).context("An image has fewer pixels than its size")
+}
@@ -1,18 +1,31 @@
 #![allow(unused_imports, dead_code)]
 mod app;
+mod denoise;
+mod export;
+mod options;
+mod post_process;
 mod render_env;
 mod raytracing;
+mod scene;
+mod scene_graph;
+mod gltf_scene;
+mod pbrt_scene;
+mod scene_export;
+mod generators;
 mod screen;
+mod screenshot;
 mod world; 
 mod common;
 mod resources;
 use std::time::{Instant, Duration};
 use anyhow::Context;
 use app::App;
+use options::Options;
 use render_env::RenderEnv;
 use winit::{event_loop::{EventLoop, ControlFlow}, window::WindowBuilder, event::{Event, WindowEvent, StartCause}, dpi::PhysicalSize};
 
 pub async fn run() -> anyhow::Result<()> {
+    let options = Options::from_args()?;
     let win_size = PhysicalSize::new(2u32.pow(11), 2u32.pow(10));
     let event_loop = EventLoop::new().context("Failed to start event loop")?;
     let window = WindowBuilder::new()
@@ -20,17 +33,11 @@ pub async fn run() -> anyhow::Result<()> {
         .with_inner_size(win_size)
         .build(&event_loop)?;
     let mut render_env = RenderEnv::new(window).await?;
-    let mut app = App::new(&render_env)?;
+    let mut app = App::new(&render_env, &options)?;
 
-    let mut frame_counter = 0;
-    let wait_len = Duration::from_millis(1000);
-    // event_loop.set_control_flow(ControlFlow::wait_duration(wait_len));
+    // event_loop.set_control_flow(ControlFlow::wait_duration(Duration::from_millis(1000)));
     event_loop.set_control_flow(ControlFlow::Poll);
     event_loop.run(move |event, elwt| {
-        frame_counter += 1;
-        // if frame_counter % 1000 == 0 {
-        //     dbg!(frame_counter);
-        // }
         match event {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
@@ -47,7 +54,7 @@ pub async fn run() -> anyhow::Result<()> {
                 // You only need to call this if you've determined that you need to redraw, in
                 // applications which do not always need to. Applications that redraw continuously
                 // can just render here instead.
-                // elwt.set_control_flow(ControlFlow::wait_duration(wait_len));
+                // elwt.set_control_flow(ControlFlow::wait_duration(Duration::from_millis(1000)));
                 render_env.window.request_redraw();
             },
             Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
@@ -65,7 +72,7 @@ pub async fn run() -> anyhow::Result<()> {
                     app.update(&render_env);
                     app.render(&render_env).unwrap();
                 },
-            Event::WindowEvent { event: WindowEvent::Resized(size), .. } => {
+            Event::WindowEvent { event: WindowEvent::Resized(_), .. } => {
                 render_env.resize();
             }
             Event::WindowEvent { event, .. } => {
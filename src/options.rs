@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use crate::generators::Generator;
+
+/// Options given on the command line
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Equirectangular .hdr or .exr lighting the scene
+    pub environment: Option<PathBuf>,
+    /// TOML file with the materials, objects and lights, or a glTF or pbrt file
+    pub scene: Option<PathBuf>,
+    /// Builds the scene from code instead of a file
+    pub generator: Option<Generator>,
+    /// Seed of the generator, a random one is printed when there is none
+    pub seed: Option<u64>,
+}
+
+impl Options {
+    pub fn from_args() -> anyhow::Result<Self> {
+        let mut options = Options::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--env" => {
+                    let path = args.next().context("--env needs a path to an environment map")?;
+                    options.environment = Some(path.into());
+                }
+                "--scene" => {
+                    let path = args.next().context("--scene needs a path to a scene file")?;
+                    options.scene = Some(path.into());
+                }
+                "--generate" => {
+                    let name = args.next().with_context(|| format!("--generate needs one of {}", Generator::NAMES))?;
+                    options.generator = Some(name.parse()?);
+                }
+                "--seed" => {
+                    let seed = args.next().context("--seed needs a number")?;
+                    options.seed = Some(seed.parse().with_context(|| format!("{} isn't a seed", seed))?);
+                }
+                _ => anyhow::bail!("Unknown argument {}", arg),
+            }
+        }
+        Ok(options)
+    }
+}
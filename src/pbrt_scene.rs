@@ -0,0 +1,736 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use glam::{vec2, Mat4, Quat, Vec2, Vec3};
+
+use crate::{
+    resources::{
+        InstanceObject, Light, Material, MaterialTexture, Mesh, MeshVertex, Sphere, Triangle, Viewpoint, NO_TEXTURE,
+    },
+    scene::Scene,
+    scene_graph::{NodeId, SceneGraph},
+};
+
+impl Scene {
+    /// Reads the shapes, materials, lights and camera of a pbrt-v4 scene that
+    /// this renderer can show, anything else is skipped with a warning. pbrt is
+    /// left handed so the scene is mirrored to look the same through this camera
+    pub fn load_pbrt(file: &Path) -> anyhow::Result<Self> {
+        let mut loader = PbrtLoader::new(file.parent().unwrap_or(Path::new("")));
+        loader.include(file)?;
+        loader.finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Number(f64),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '#' => {
+                while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            }
+            '[' => tokens.push(Token::Open),
+            ']' => tokens.push(Token::Close),
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => string.push(c),
+                        None => anyhow::bail!("A string isn't closed"),
+                    }
+                }
+                tokens.push(Token::Str(string));
+            }
+            c if c.is_whitespace() => (),
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|&(_, c)| !c.is_whitespace() && !"[]\"#".contains(c)) {
+                    end = i + c.len_utf8();
+                }
+                let word = &text[start..end];
+                tokens.push(match word.parse() {
+                    Ok(number) => Token::Number(number),
+                    Err(_) => Token::Word(word.to_string()),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f64),
+    Str(String),
+    Bool(bool),
+}
+
+/// A parameter like `"rgb reflectance" [0.5 0.5 0.5]`
+#[derive(Debug)]
+struct Param {
+    kind: String,
+    name: String,
+    values: Vec<Value>,
+}
+
+#[derive(Debug, Default)]
+struct Params(Vec<Param>);
+
+impl Params {
+    fn get(&self, name: &str) -> Option<&Param> {
+        self.0.iter().find(|p| p.name == name)
+    }
+
+    fn floats(&self, name: &str) -> Option<Vec<f32>> {
+        let values = &self.get(name)?.values;
+        Some(values.iter().filter_map(|v| if let Value::Number(n) = v { Some(*n as f32) } else { None }).collect())
+    }
+
+    /// Straight from the f64 token, an f32 would round indices from 2^24 on
+    fn integers(&self, name: &str) -> anyhow::Result<Option<Vec<u32>>> {
+        let Some(param) = self.get(name) else {
+            return Ok(None);
+        };
+        param
+            .values
+            .iter()
+            .map(|v| match v {
+                Value::Number(n) if n.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(n) => Ok(*n as u32),
+                _ => anyhow::bail!("{} has to be made of whole numbers from 0 up", name),
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Some)
+    }
+
+    fn float(&self, name: &str) -> Option<f32> {
+        self.floats(name)?.first().copied()
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        match self.get(name)?.values.first()? {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn point(&self, name: &str) -> Option<Vec3> {
+        self.floats(name).filter(|f| f.len() >= 3).map(|f| Vec3::from_slice(&f))
+    }
+
+    fn is_texture(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|p| p.kind == "texture")
+    }
+}
+
+/// What `AttributeBegin` saves and `AttributeEnd` brings back
+#[derive(Debug, Clone)]
+struct GraphicsState {
+    transform: Mat4,
+    material: u32,
+    /// Radiance of the area light the shapes get
+    emission: Option<Vec3>,
+}
+
+/// A shape placed in the world, or in an object for `ObjectInstance` to copy
+#[derive(Debug, Clone, Copy)]
+struct Placed {
+    object: InstanceObject,
+    transform: Mat4,
+}
+
+struct PbrtLoader {
+    directory: PathBuf,
+    state: GraphicsState,
+    stack: Vec<GraphicsState>,
+    named_materials: HashMap<String, u32>,
+    named_textures: HashMap<String, u32>,
+    named_coordinate_systems: HashMap<String, Mat4>,
+    /// Shapes of every `ObjectBegin` by name
+    objects: HashMap<String, Vec<Placed>>,
+    current_object: Option<(String, Vec<Placed>)>,
+    camera_from_world: Option<Mat4>,
+    fov: f32,
+    warnings: HashSet<String>,
+
+    materials: Vec<Material>,
+    spheres: Vec<Sphere>,
+    meshes: Vec<Mesh>,
+    placed: Vec<Placed>,
+    /// Lights are placed in the world as pbrt sees it and mirrored at the end
+    lights: Vec<Light>,
+    textures: Vec<MaterialTexture>,
+    image_paths: Vec<PathBuf>,
+    environment: Option<PathBuf>,
+}
+
+impl PbrtLoader {
+    fn new(directory: &Path) -> Self {
+        Self {
+            directory: directory.to_path_buf(),
+            state: GraphicsState { transform: Mat4::IDENTITY, material: 0, emission: None },
+            stack: Vec::new(),
+            named_materials: HashMap::new(),
+            named_textures: HashMap::new(),
+            named_coordinate_systems: HashMap::new(),
+            objects: HashMap::new(),
+            current_object: None,
+            camera_from_world: None,
+            fov: 90.0,
+            warnings: HashSet::new(),
+            // The default material of pbrt
+            materials: vec![Material { color: Vec3::splat(0.5).extend(1.0), roughness: 1.0, ..Default::default() }],
+            spheres: Vec::new(),
+            meshes: Vec::new(),
+            placed: Vec::new(),
+            lights: Vec::new(),
+            textures: Vec::new(),
+            image_paths: Vec::new(),
+            environment: None,
+        }
+    }
+
+    fn warn(&mut self, warning: String) {
+        if self.warnings.insert(warning.clone()) {
+            println!("\nWarning: {}", warning);
+        }
+    }
+
+    fn include(&mut self, file: &Path) -> anyhow::Result<()> {
+        let text = std::fs::read_to_string(file).with_context(|| format!("Unable to read {}", file.display()))?;
+        let tokens = tokenize(&text).with_context(|| format!("Unable to parse {}", file.display()))?;
+
+        let mut i = 0;
+        while i < tokens.len() {
+            let Token::Word(directive) = &tokens[i] else {
+                anyhow::bail!("Expected a directive in {} but found {:?}", file.display(), tokens[i]);
+            };
+            i += 1;
+            let start = i;
+            let mut depth = 0;
+            while i < tokens.len() {
+                match &tokens[i] {
+                    Token::Word(w) if depth == 0 && w != "true" && w != "false" => break,
+                    Token::Open => depth += 1,
+                    Token::Close => depth -= 1,
+                    _ => (),
+                }
+                i += 1;
+            }
+            self.directive(directive, &tokens[start..i])
+                .with_context(|| format!("Unable to read {} in {}", directive, file.display()))?;
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, directive: &str, args: &[Token]) -> anyhow::Result<()> {
+        let numbers: Vec<f32> = args.iter().filter_map(|t| if let Token::Number(n) = t { Some(*n as f32) } else { None }).collect();
+        let number_count = |count: usize| -> anyhow::Result<()> {
+            if numbers.len() != count {
+                anyhow::bail!("Expected {} numbers but found {}", count, numbers.len());
+            }
+            Ok(())
+        };
+        let (name, params) = split_args(args, if directive == "Texture" { 3 } else { 1 })?;
+        let name = name.first().cloned().unwrap_or_default();
+
+        match directive {
+            "Identity" => self.state.transform = Mat4::IDENTITY,
+            "Translate" => {
+                number_count(3)?;
+                self.state.transform *= Mat4::from_translation(Vec3::from_slice(&numbers));
+            }
+            "Scale" => {
+                number_count(3)?;
+                self.state.transform *= Mat4::from_scale(Vec3::from_slice(&numbers));
+            }
+            "Rotate" => {
+                number_count(4)?;
+                let axis = Vec3::from_slice(&numbers[1..]).normalize_or_zero();
+                self.state.transform *= Mat4::from_quat(Quat::from_axis_angle(axis, numbers[0].to_radians()));
+            }
+            "LookAt" => {
+                number_count(9)?;
+                let eye = Vec3::from_slice(&numbers[0..3]);
+                let look = Vec3::from_slice(&numbers[3..6]);
+                let up = Vec3::from_slice(&numbers[6..9]);
+                self.state.transform *= look_at(eye, look, up);
+            }
+            "Transform" | "ConcatTransform" => {
+                number_count(16)?;
+                let matrix = Mat4::from_cols_slice(&numbers);
+                if directive == "Transform" {
+                    self.state.transform = matrix;
+                } else {
+                    self.state.transform *= matrix;
+                }
+            }
+            "CoordinateSystem" => {
+                self.named_coordinate_systems.insert(name, self.state.transform);
+            }
+            "CoordSysTransform" => match self.named_coordinate_systems.get(&name) {
+                Some(&system) => self.state.transform = system,
+                None => self.warn(format!("No coordinate system is named {}", name)),
+            },
+            "AttributeBegin" | "TransformBegin" => self.stack.push(self.state.clone()),
+            "AttributeEnd" | "TransformEnd" => {
+                let state = self.stack.pop().context("There are more ends than begins")?;
+                if directive == "TransformEnd" {
+                    self.state.transform = state.transform;
+                } else {
+                    self.state = state;
+                }
+            }
+            "Camera" => {
+                if name != "perspective" {
+                    self.warn(format!("{} cameras aren't supported, it is used as a perspective one", name));
+                }
+                self.camera_from_world = Some(self.state.transform);
+                self.fov = params.float("fov").unwrap_or(90.0);
+            }
+            "WorldBegin" => {
+                self.state.transform = Mat4::IDENTITY;
+                self.named_coordinate_systems.insert("world".to_string(), Mat4::IDENTITY);
+            }
+            "Include" | "Import" => {
+                let path = self.directory.join(&name);
+                self.include(&path)?;
+            }
+            "Material" => self.state.material = self.material(&name, &params),
+            "MakeNamedMaterial" => {
+                let kind = params.string("type").unwrap_or("diffuse").to_string();
+                let material = self.material(&kind, &params);
+                self.named_materials.insert(name, material);
+            }
+            "NamedMaterial" => match self.named_materials.get(&name) {
+                Some(&material) => self.state.material = material,
+                None => self.warn(format!("No material is named {}", name)),
+            },
+            "Texture" => self.texture(args, &params)?,
+            "AreaLightSource" => {
+                if name != "diffuse" {
+                    self.warn(format!("{} area lights aren't supported", name));
+                }
+                let radiance = self.color(&params, "L").unwrap_or(Vec3::ONE);
+                self.state.emission = Some(radiance * params.float("scale").unwrap_or(1.0));
+            }
+            "LightSource" => self.light(&name, &params),
+            "Shape" => self.shape(&name, &params)?,
+            "ObjectBegin" => {
+                self.stack.push(self.state.clone());
+                self.current_object = Some((name, Vec::new()));
+            }
+            "ObjectEnd" => {
+                if let Some((name, shapes)) = self.current_object.take() {
+                    self.objects.insert(name, shapes);
+                }
+                self.state = self.stack.pop().context("ObjectEnd without ObjectBegin")?;
+            }
+            "ObjectInstance" => match self.objects.get(&name) {
+                Some(shapes) => {
+                    let placed: Vec<Placed> = shapes
+                        .iter()
+                        .map(|shape| Placed { transform: self.state.transform * shape.transform, ..*shape })
+                        .collect();
+                    self.placed.extend(placed);
+                }
+                None => self.warn(format!("No object is named {}", name)),
+            },
+            // These only change how pbrt renders, not what is in the scene
+            "Film" | "Sampler" | "Integrator" | "PixelFilter" | "Accelerator" | "ColorSpace" | "Option" | "Attribute" => {
+                self.warn(format!("{} is ignored", directive));
+            }
+            _ => self.warn(format!("{} isn't supported and was skipped", directive)),
+        }
+        Ok(())
+    }
+
+    /// pbrt materials mapped onto the lobes of the principled material, the
+    /// coated ones get a clearcoat
+    fn material(&mut self, kind: &str, params: &Params) -> u32 {
+        let roughness = params.float("roughness").or_else(|| params.float("uroughness")).unwrap_or(0.0);
+        let mut material = Material::default();
+        match kind {
+            "diffuse" | "coateddiffuse" | "diffusetransmission" => {
+                material.roughness = 1.0;
+                if kind == "coateddiffuse" {
+                    material.clearcoat = 1.0;
+                    material.clearcoat_roughness = roughness;
+                }
+                material.color = self.color(params, "reflectance").unwrap_or(Vec3::splat(0.5)).extend(1.0);
+                material.albedo_texture = self.texture_param(params, "reflectance");
+                if kind == "diffusetransmission" {
+                    self.warn("diffusetransmission is rendered without transmission".to_string());
+                }
+            }
+            "conductor" | "coatedconductor" => {
+                let eta = params.get("eta").or_else(|| params.get("conductor.eta"));
+                let k = params.floats("k").or_else(|| params.floats("conductor.k"));
+                material = match (self.color(params, "reflectance"), eta.and_then(|e| e.values.first())) {
+                    (Some(color), _) => Material { color: color.extend(1.0), metallic: 1.0, ..Default::default() },
+                    (None, Some(Value::Str(spectrum))) => metal_preset(spectrum),
+                    (None, Some(Value::Number(_))) => {
+                        let eta = params.floats("eta").or_else(|| params.floats("conductor.eta")).unwrap_or_default();
+                        match k {
+                            Some(k) if eta.len() >= 3 && k.len() >= 3 => {
+                                Material::conductor(Vec3::from_slice(&eta), Vec3::from_slice(&k), 0.0)
+                            }
+                            _ => metal_preset("metal-Cu-eta"),
+                        }
+                    }
+                    // pbrt defaults to copper
+                    _ => metal_preset("metal-Cu-eta"),
+                };
+                material.roughness = roughness;
+                if kind == "coatedconductor" {
+                    material.roughness = params.float("conductor.roughness").unwrap_or(0.0);
+                    material.clearcoat = 1.0;
+                    material.clearcoat_roughness = params.float("interface.roughness").unwrap_or(0.0);
+                }
+            }
+            "dielectric" | "thindielectric" => {
+                // Named glass spectra are close enough to 1.5
+                let ior = params.float("eta").unwrap_or(1.5);
+                material = Material::dielectric(Vec3::ONE, ior, roughness, 1.0);
+            }
+            _ => {
+                self.warn(format!("{} materials aren't supported and are rendered as diffuse", kind));
+                material.roughness = 1.0;
+            }
+        }
+        if params.is_texture("displacement") || params.get("normalmap").is_some() {
+            self.warn("Displacement and normal maps of pbrt materials are ignored".to_string());
+        }
+
+        self.materials.push(material);
+        self.materials.len() as u32 - 1
+    }
+
+    /// Only image textures are read, they become albedo textures
+    fn texture(&mut self, args: &[Token], params: &Params) -> anyhow::Result<()> {
+        let (names, _) = split_args(args, 3)?;
+        let [name, kind, class] = names.as_slice() else {
+            anyhow::bail!("Textures need a name, a type and a class");
+        };
+        if kind == "float" || class != "imagemap" {
+            self.warn(format!("{} {} textures aren't supported", kind, class));
+            return Ok(());
+        }
+
+        let file = params.string("filename").context("Image textures need a filename")?;
+        let path = self.directory.join(file);
+        let layer = self.image_paths.iter().position(|p| *p == path).unwrap_or_else(|| {
+            self.image_paths.push(path);
+            self.image_paths.len() - 1
+        });
+        self.textures.push(MaterialTexture::image(layer as u32));
+        self.named_textures.insert(name.clone(), self.textures.len() as u32 - 1);
+        Ok(())
+    }
+
+    fn texture_param(&mut self, params: &Params, name: &str) -> u32 {
+        if !params.is_texture(name) {
+            return NO_TEXTURE;
+        }
+        let texture = params.string(name).unwrap_or_default();
+        match self.named_textures.get(texture) {
+            Some(&index) => index,
+            None => {
+                self.warn(format!("No image texture is named {}", texture));
+                NO_TEXTURE
+            }
+        }
+    }
+
+    /// rgb and blackbody colors, spectra are only known by name for metals
+    fn color(&mut self, params: &Params, name: &str) -> Option<Vec3> {
+        let param = params.get(name)?;
+        let numbers: Vec<f32> = param.values.iter().filter_map(|v| if let Value::Number(n) = v { Some(*n as f32) } else { None }).collect();
+        match param.kind.as_str() {
+            "rgb" if numbers.len() >= 3 => Some(Vec3::from_slice(&numbers)),
+            "blackbody" if !numbers.is_empty() => Some(blackbody(numbers[0])),
+            "texture" => Some(Vec3::ONE),
+            kind => {
+                self.warn(format!("{} {} can't be read, it is left white", kind, name));
+                None
+            }
+        }
+    }
+
+    fn light(&mut self, kind: &str, params: &Params) {
+        let transform = self.state.transform;
+        let scale = params.float("scale").unwrap_or(1.0);
+        if params.get("power").is_some() {
+            self.warn("Light power is ignored, only scale is used".to_string());
+        }
+        let from = transform.transform_point3(params.point("from").unwrap_or(Vec3::ZERO));
+        let to = transform.transform_point3(params.point("to").unwrap_or(Vec3::Z));
+        match kind {
+            "point" => {
+                let intensity = self.color(params, "I").unwrap_or(Vec3::ONE) * scale;
+                self.lights.push(Light::point(from, intensity, 1.0));
+            }
+            "spot" => {
+                let intensity = self.color(params, "I").unwrap_or(Vec3::ONE) * scale;
+                let outer = params.float("coneangle").unwrap_or(30.0);
+                let inner = outer - params.float("conedeltaangle").unwrap_or(5.0);
+                let (inner, outer) = (inner.max(0.0).to_radians(), outer.to_radians());
+                self.lights.push(Light::spot(from, to - from, intensity, 1.0, inner, outer));
+            }
+            "distant" => {
+                let radiance = self.color(params, "L").unwrap_or(Vec3::ONE) * scale;
+                self.lights.push(Light::directional(to - from, radiance, 1.0));
+            }
+            "infinite" => match params.string("filename") {
+                Some(file) => {
+                    self.environment = Some(self.directory.join(file));
+                    if scale != 1.0 || transform != Mat4::IDENTITY {
+                        self.warn("The scale and rotation of infinite lights are ignored".to_string());
+                    }
+                }
+                None => self.warn("Infinite lights without a map aren't supported, the sky lights the scene".to_string()),
+            },
+            _ => self.warn(format!("{} lights aren't supported", kind)),
+        }
+    }
+
+    fn shape(&mut self, kind: &str, params: &Params) -> anyhow::Result<()> {
+        let material = match self.state.emission {
+            // Area lights glow with the color of their own material
+            Some(emission) => {
+                let material = Material { emission: emission.extend(0.0), ..self.materials[self.state.material as usize] };
+                self.materials.push(material);
+                self.materials.len() as u32 - 1
+            }
+            None => self.state.material,
+        };
+
+        let object = match kind {
+            "sphere" => {
+                if params.get("zmin").is_some() || params.get("zmax").is_some() || params.get("phimax").is_some() {
+                    self.warn("Partial spheres are rendered whole".to_string());
+                }
+                let radius = params.float("radius").unwrap_or(1.0);
+                self.spheres.push(Sphere::new(Vec3::ZERO, radius, material));
+                InstanceObject::Sphere(self.spheres.len() - 1)
+            }
+            "trianglemesh" => {
+                let positions = params.floats("P").context("Triangle meshes need P")?;
+                let normals = params.floats("N").unwrap_or_default();
+                let uvs = params.floats("uv").unwrap_or_default();
+                let vertex_count = positions.len() / 3;
+                let indices: Vec<u32> = match params.integers("indices")? {
+                    Some(indices) => indices,
+                    None if vertex_count == 3 => vec![0, 1, 2],
+                    None => anyhow::bail!("Triangle meshes with more than three points need indices"),
+                };
+                if let Some(index) = indices.iter().find(|&&i| i as usize >= vertex_count) {
+                    anyhow::bail!("A triangle uses point {} of {}", index, vertex_count);
+                }
+
+                let mut mesh = Mesh::default();
+                mesh.vertices.extend((0..vertex_count).map(|i| {
+                    let normal = normals.get(i * 3..i * 3 + 3).map_or(Vec3::ZERO, Vec3::from_slice);
+                    // pbrt puts v = 0 at the bottom of the image
+                    let tex_coords = uvs.get(i * 2..i * 2 + 2).map_or(Vec2::ZERO, |t| vec2(t[0], 1.0 - t[1]));
+                    MeshVertex::new(Vec3::from_slice(&positions[i * 3..i * 3 + 3]), normal, tex_coords)
+                }));
+                mesh.triangles.extend(indices.chunks_exact(3).map(|t| Triangle { vertices: [t[0], t[1], t[2]], material_index: material }));
+                mesh.compute_tangents();
+                self.meshes.push(mesh);
+                InstanceObject::Mesh(self.meshes.len() - 1)
+            }
+            "plymesh" => {
+                let file = params.string("filename").context("PLY meshes need a filename")?;
+                if params.is_texture("displacement") {
+                    self.warn("Displacement of PLY meshes is ignored".to_string());
+                }
+                self.meshes.push(Mesh::load_ply(&self.directory.join(file), material)?);
+                InstanceObject::Mesh(self.meshes.len() - 1)
+            }
+            _ => {
+                self.warn(format!("{} shapes aren't supported", kind));
+                return Ok(());
+            }
+        };
+
+        let placed = Placed { object, transform: self.state.transform };
+        match &mut self.current_object {
+            Some((_, shapes)) => shapes.push(placed),
+            None => self.placed.push(placed),
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> anyhow::Result<Scene> {
+        if !self.stack.is_empty() {
+            self.warn("Some attributes were never ended".to_string());
+        }
+
+        // Turns the up of the camera to +y and mirrors x, the scene then looks
+        // the same through the right handed camera as it does in pbrt
+        let camera_from_world = self.camera_from_world.unwrap_or(Mat4::IDENTITY);
+        let world_from_camera = camera_from_world.inverse();
+        let up = world_from_camera.transform_vector3(Vec3::Y).normalize_or_zero();
+        let to_y_up = Mat4::from_quat(Quat::from_rotation_arc(up, Vec3::Y));
+        let mirror = Mat4::from_scale(Vec3::new(-1.0, 1.0, 1.0));
+        let world = mirror * to_y_up;
+        let camera = Viewpoint {
+            position: world.transform_point3(world_from_camera.transform_point3(Vec3::ZERO)),
+            direction: world.transform_vector3(world_from_camera.transform_vector3(Vec3::Z)),
+            fov: self.fov.to_radians(),
+        };
+
+        let mut graph = SceneGraph::new();
+        let root: NodeId = graph.add("pbrt".to_string(), world, None, None)?;
+        for (i, placed) in self.placed.iter().enumerate() {
+            let kind = match placed.object {
+                InstanceObject::Mesh(_) => "mesh",
+                InstanceObject::Sphere(_) => "sphere",
+                InstanceObject::Primitive(_) => "primitive",
+            };
+            let id = graph.add(format!("{} {}", kind, i), placed.transform, Some(placed.object), None)?;
+            graph.set_parent(id, Some(root))?;
+        }
+        for light in &mut self.lights {
+            light.position = world.transform_point3(light.position);
+            light.direction = world.transform_vector3(light.direction).normalize_or_zero();
+        }
+
+        let images = self
+            .image_paths
+            .iter()
+            .map(|path| {
+                image::open(path)
+                    .map(|i| i.to_rgba8())
+                    .with_context(|| format!("Unable to load texture {}", path.display()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Scene {
+            materials: self.materials,
+            spheres: self.spheres,
+            primitives: Vec::new(),
+            meshes: self.meshes,
+            graph,
+            lights: self.lights,
+            textures: self.textures,
+            images,
+            fog: None,
+            volumes: Vec::new(),
+            grid: None,
+            camera: Some(camera),
+            environment: self.environment,
+        })
+    }
+}
+
+/// Splits the arguments of a directive into the strings in front and the parameter list
+fn split_args(args: &[Token], names: usize) -> anyhow::Result<(Vec<String>, Params)> {
+    let mut leading = Vec::new();
+    let mut i = 0;
+    while leading.len() < names {
+        match args.get(i) {
+            Some(Token::Str(s)) => leading.push(s.clone()),
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let mut params = Params::default();
+    while i < args.len() {
+        let Token::Str(declaration) = &args[i] else {
+            // Numbers of transforms
+            i += 1;
+            continue;
+        };
+        let mut words = declaration.split_whitespace();
+        let (Some(kind), Some(name)) = (words.next(), words.next()) else {
+            anyhow::bail!("Expected a type and a name in \"{}\"", declaration);
+        };
+        i += 1;
+
+        let mut values = Vec::new();
+        let mut push = |token: &Token| match token {
+            Token::Number(n) => values.push(Value::Number(*n)),
+            Token::Str(s) if kind == "bool" => values.push(Value::Bool(s == "true")),
+            Token::Str(s) => values.push(Value::Str(s.clone())),
+            Token::Word(w) => values.push(Value::Bool(w == "true")),
+            _ => (),
+        };
+        match args.get(i) {
+            Some(Token::Open) => {
+                i += 1;
+                while i < args.len() && args[i] != Token::Close {
+                    push(&args[i]);
+                    i += 1;
+                }
+                i += 1;
+            }
+            Some(token) => {
+                push(token);
+                i += 1;
+            }
+            None => anyhow::bail!("{} has no value", name),
+        }
+        params.0.push(Param { kind: kind.to_string(), name: name.to_string(), values });
+    }
+    Ok((leading, params))
+}
+
+/// The camera from world transform of pbrt
+fn look_at(eye: Vec3, look: Vec3, up: Vec3) -> Mat4 {
+    let direction = (look - eye).normalize();
+    let right = up.normalize().cross(direction).normalize();
+    let up = direction.cross(right);
+    Mat4::from_cols(right.extend(0.0), up.extend(0.0), direction.extend(0.0), eye.extend(1.0)).inverse()
+}
+
+/// The presets of the metals pbrt has spectra for
+fn metal_preset(spectrum: &str) -> Material {
+    let metal = spectrum.trim_start_matches("metal-").split('-').next().unwrap_or_default();
+    let name = match metal {
+        "Au" => "gold",
+        "Ag" => "silver",
+        "Al" => "aluminium",
+        "Cu" => "copper",
+        "CuZn" => "brass",
+        "Cr" => "chromium",
+        "TiN" | "Ti" => "titanium",
+        "Pt" => "platinum",
+        "Ni" => "nickel",
+        _ => "iron",
+    };
+    Material::preset(name).unwrap_or_default()
+}
+
+/// Roughly the color of a black body at `kelvin`, scaled to a brightest channel of one
+fn blackbody(kelvin: f32) -> Vec3 {
+    let t = (kelvin / 100.0).clamp(10.0, 400.0);
+    let red = if t <= 66.0 { 255.0 } else { 329.7 * (t - 60.0).powf(-0.1332) };
+    let green = if t <= 66.0 { 99.47 * t.ln() - 161.12 } else { 288.12 * (t - 60.0).powf(-0.0755) };
+    let blue = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.52 * (t - 10.0).ln() - 305.04
+    };
+    let srgb = (Vec3::new(red, green, blue) / 255.0).clamp(Vec3::ZERO, Vec3::ONE);
+    // The formula gives sRGB
+    let linear = srgb.powf(2.2);
+    linear / linear.max_element()
+}
@@ -0,0 +1,312 @@
+use bytemuck::{bytes_of, Pod, Zeroable};
+use wgpu::{*, util::{BufferInitDescriptor, DeviceExt}};
+use winit::keyboard::KeyCode;
+
+use crate::{
+    raytracing::{Raytracer, ACCUMULATION_FORMAT},
+    render_env::RenderEnv,
+};
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffectKind {
+    /// Saturation scale, hue shift in radians and lightness scale in OKLCH
+    Grade = 0,
+    /// Strength, radius where the darkening starts and how soft it is
+    Vignette = 1,
+    /// Amount and the distance to the neighbouring pixels
+    Sharpen = 2,
+    /// Animated rings in the chroma, amount, period and speed
+    Wave = 3,
+}
+
+impl EffectKind {
+    fn default_values(self) -> [f32; 4] {
+        match self {
+            EffectKind::Grade => [1.3, 0.0, 1.0, 0.0],
+            EffectKind::Vignette => [0.5, 0.6, 0.45, 0.0],
+            EffectKind::Sharpen => [0.5, 1.0, 0.0, 0.0],
+            EffectKind::Wave => [1.0, 0.2, 0.1, 0.0],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct EffectRaw {
+    kind: u32,
+    padding: [u32; 3],
+    values: [f32; 4],
+}
+
+pub struct Effect {
+    pub kind: EffectKind,
+    pub enabled: bool,
+    pub values: [f32; 4],
+    buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+impl Effect {
+    fn to_raw(&self) -> EffectRaw {
+        EffectRaw {
+            kind: self.kind as u32,
+            padding: [0; 3],
+            values: self.values,
+        }
+    }
+}
+
+/// Ordered stack of effects that run on the image before it reaches the screen
+pub struct PostProcess {
+    pipeline: ComputePipeline,
+    effects: Vec<Effect>,
+    selected: usize,
+    size: Extent3d,
+    // Each target has a bind group to read from it and one to write to it
+    read_bind_groups: [BindGroup; 2],
+    write_bind_groups: [BindGroup; 2],
+}
+
+impl PostProcess {
+    pub fn new(render_env: &RenderEnv, ray_tracer: &Raytracer, time_bind_layout: &BindGroupLayout) -> Self {
+        let device = &render_env.device;
+        let size = ray_tracer.size();
+        let shader = device.create_shader_module(include_wgsl!("./shaders/color.wgsl"));
+
+        let targets = [target_texture(device, size), target_texture(device, size)];
+        let views = targets.each_ref().map(|t| t.create_view(&TextureViewDescriptor::default()));
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let read_bind_groups = views.each_ref().map(|view| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: Some("Post process read bind group"),
+                layout: &ray_tracer.sampler_bind_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&sampler),
+                    },
+                ],
+            })
+        });
+
+        let write_bind_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Post process write bind group layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: ACCUMULATION_FORMAT,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                visibility: ShaderStages::COMPUTE,
+                count: None,
+            }],
+        });
+
+        let write_bind_groups = views.each_ref().map(|view| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: Some("Post process write bind group"),
+                layout: &write_bind_layout,
+                entries: &[BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(view),
+                }],
+            })
+        });
+
+        let effect_bind_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Post process effect bind group layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                ty: crate::common::UNIFORM_BUFFER_BINDING,
+                visibility: ShaderStages::COMPUTE,
+                count: None,
+            }],
+        });
+
+        let effects = [EffectKind::Grade, EffectKind::Sharpen, EffectKind::Wave, EffectKind::Vignette]
+            .into_iter()
+            .map(|kind| {
+                let values = kind.default_values();
+                let buffer = device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("Post process effect buffer"),
+                    contents: bytes_of(&EffectRaw {
+                        kind: kind as u32,
+                        padding: [0; 3],
+                        values,
+                    }),
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                });
+
+                let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Post process effect bind group"),
+                    layout: &effect_bind_layout,
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                });
+
+                Effect {
+                    kind,
+                    enabled: false,
+                    values,
+                    buffer,
+                    bind_group,
+                }
+            })
+            .collect();
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Post process Pipeline Layout"),
+            bind_group_layouts: &[
+                &ray_tracer.sampler_bind_layout,
+                &write_bind_layout,
+                &effect_bind_layout,
+                time_bind_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Post process pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        Self {
+            pipeline,
+            effects,
+            selected: 0,
+            size,
+            read_bind_groups,
+            write_bind_groups,
+        }
+    }
+
+    pub fn effects(&self) -> &[Effect] {
+        &self.effects
+    }
+
+    /// Changes what an effect does, see [`EffectKind`] for what the values mean
+    pub fn set_effect(&mut self, render_env: &RenderEnv, kind: EffectKind, enabled: bool, values: [f32; 4]) {
+        let Some(effect) = self.effects.iter_mut().find(|e| e.kind == kind) else {
+            return;
+        };
+
+        effect.enabled = enabled;
+        effect.values = values;
+        render_env.queue.write_buffer(&effect.buffer, 0, bytes_of(&effect.to_raw()));
+    }
+
+    /// Moves an effect to a different place in the stack
+    pub fn move_effect(&mut self, from: usize, to: usize) {
+        if from >= self.effects.len() || to >= self.effects.len() {
+            return;
+        }
+
+        let effect = self.effects.remove(from);
+        self.effects.insert(to, effect);
+    }
+
+    /// The number keys toggle the effect at that place in the stack and select
+    /// it, the brackets scale its first value and page up/down reorder it
+    pub fn key_press(&mut self, render_env: &RenderEnv, key: KeyCode) {
+        let digits = [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4];
+        if let Some(index) = digits.iter().position(|d| *d == key) {
+            let Some(effect) = self.effects.get(index) else {
+                return;
+            };
+
+            let (kind, enabled, values) = (effect.kind, effect.enabled, effect.values);
+            self.selected = index;
+            self.set_effect(render_env, kind, !enabled, values);
+            println!("\nPost processing: {}", self);
+            return;
+        }
+
+        let selected = &self.effects[self.selected];
+        let (kind, enabled, mut values) = (selected.kind, selected.enabled, selected.values);
+        match key {
+            KeyCode::BracketRight => values[0] *= 1.25,
+            KeyCode::BracketLeft => values[0] /= 1.25,
+            KeyCode::PageUp if self.selected > 0 => {
+                self.move_effect(self.selected, self.selected - 1);
+                self.selected -= 1;
+            }
+            KeyCode::PageDown if self.selected + 1 < self.effects.len() => {
+                self.move_effect(self.selected, self.selected + 1);
+                self.selected += 1;
+            }
+            _ => return,
+        }
+        self.set_effect(render_env, kind, enabled, values);
+        println!("\nPost processing: {}", self);
+    }
+
+    /// Runs the enabled effects in order and returns the bind group of the
+    /// result, which is just `input` when nothing is enabled
+    pub fn apply<'a>(
+        &'a self,
+        encoder: &mut CommandEncoder,
+        input: &'a BindGroup,
+        time_bind_group: &BindGroup,
+    ) -> &'a BindGroup {
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Post process pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(3, time_bind_group, &[]);
+
+        let mut current = input;
+        let mut target = 0;
+        for effect in self.effects.iter().filter(|e| e.enabled) {
+            pass.set_bind_group(0, current, &[]);
+            pass.set_bind_group(1, &self.write_bind_groups[target], &[]);
+            pass.set_bind_group(2, &effect.bind_group, &[]);
+            pass.dispatch_workgroups(self.size.width.div_ceil(16), self.size.height.div_ceil(16), 1);
+
+            current = &self.read_bind_groups[target];
+            target = 1 - target;
+        }
+
+        current
+    }
+}
+
+impl std::fmt::Display for PostProcess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, effect) in self.effects.iter().enumerate() {
+            let marker = if i == self.selected { ">" } else { " " };
+            let state = if effect.enabled { "on" } else { "off" };
+            write!(f, "{}{}:{:?}({}, {:.2}) ", marker, i + 1, effect.kind, state, effect.values[0])?;
+        }
+        Ok(())
+    }
+}
+
+fn target_texture(device: &Device, size: Extent3d) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some("Post process texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: ACCUMULATION_FORMAT,
+        usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
@@ -1,12 +1,53 @@
-use crate::render_env::RenderEnv;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{common::read_texture, render_env::RenderEnv};
 use wgpu::*;
 use winit::dpi::PhysicalSize;
 
+/// Format of the accumulated radiance and the per-pixel sampling statistics
+pub const ACCUMULATION_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+
+/// The linear render and its feature buffers read back from the gpu, with
+/// one rgba value per pixel starting at the top left
+pub struct RenderLayers {
+    pub width: u32,
+    pub height: u32,
+    pub beauty: Vec<[f32; 4]>,
+    pub albedo: Vec<[f32; 4]>,
+    /// The normal in xyz and the distance to the first hit in w, which is
+    /// negative when nothing was hit
+    pub normal_depth: Vec<[f32; 4]>,
+}
+
+/// How many frames the active pixel count can be in flight for, frames
+/// finishing while every buffer is busy aren't read back
+const READBACK_BUFFERS: usize = 3;
+
+/// A buffer the active pixel count is copied into and mapped from without
+/// waiting for the gpu
+#[derive(Debug)]
+struct Readback {
+    buffer: Buffer,
+    /// The render and frame being copied or mapped, none when the buffer is free
+    tag: Option<(u32, u32)>,
+    map_requested: bool,
+    mapped: Arc<AtomicBool>,
+}
+
 #[derive(Debug)]
 pub struct Raytracer {
     pipeline: ComputePipeline,
     color_buffer: Texture,
     color_cache: Texture,
+    stats_buffer: Texture,
+    stats_cache: Texture,
+    albedo_buffer: Texture,
+    normal_depth_buffer: Texture,
+    active_pixels_buffer: Buffer,
+    active_pixels_readbacks: Vec<Readback>,
     storage_bind_group: BindGroup,
     pub sampler_bind_group: BindGroup,
     pub sampler_bind_layout: BindGroupLayout,
@@ -17,75 +58,156 @@ impl Raytracer {
         let device = &render_env.device;
         let size = render_env.window.inner_size();
 
-        let color_buffer = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
-            size: Extent3d {
-                width: size.width,
-                height: size.height,
-                ..Default::default()
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::COPY_DST
-                | TextureUsages::COPY_SRC
-                | TextureUsages::STORAGE_BINDING,
-            view_formats: &[],
-        });
+        let color_buffer = accumulation_texture(device, size, TextureUsages::COPY_SRC);
+        let color_cache = accumulation_texture(
+            device,
+            size,
+            TextureUsages::COPY_DST | TextureUsages::COPY_SRC | TextureUsages::TEXTURE_BINDING,
+        );
+        // x: mean luminance, y: mean squared luminance, z: samples taken
+        let stats_buffer = accumulation_texture(device, size, TextureUsages::COPY_SRC);
+        let stats_cache = accumulation_texture(device, size, TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING);
+        // First hit features for the denoiser, the normal texture holds the depth in w
+        let albedo_buffer = accumulation_texture(device, size, TextureUsages::COPY_SRC | TextureUsages::TEXTURE_BINDING);
+        let normal_depth_buffer = accumulation_texture(device, size, TextureUsages::COPY_SRC | TextureUsages::TEXTURE_BINDING);
 
-        let color_cache = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
-            size: Extent3d {
-                width: size.width,
-                height: size.height,
-                ..Default::default()
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::COPY_DST
-                | TextureUsages::TEXTURE_BINDING
-                | TextureUsages::STORAGE_BINDING,
-            view_formats: &[],
+        // Counts the pixels that are still above the noise threshold after each frame
+        let active_pixels_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Active pixels buffer"),
+            size: std::mem::size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
 
+        let active_pixels_readbacks = (0..READBACK_BUFFERS)
+            .map(|_| Readback {
+                buffer: device.create_buffer(&BufferDescriptor {
+                    label: Some("Active pixels readback buffer"),
+                    size: std::mem::size_of::<u32>() as u64,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                tag: None,
+                map_requested: false,
+                mapped: Arc::new(AtomicBool::new(false)),
+            })
+            .collect();
+
         let compute_shader =
             device.create_shader_module(include_wgsl!("./shaders/raytrace_kernal.wgsl"));
         let color_buffer_view = color_buffer.create_view(&wgpu::TextureViewDescriptor::default());
         let color_cache_view = color_cache.create_view(&wgpu::TextureViewDescriptor::default());
+        let stats_buffer_view = stats_buffer.create_view(&wgpu::TextureViewDescriptor::default());
+        let stats_cache_view = stats_cache.create_view(&wgpu::TextureViewDescriptor::default());
+        let albedo_buffer_view = albedo_buffer.create_view(&wgpu::TextureViewDescriptor::default());
+        let normal_depth_buffer_view = normal_depth_buffer.create_view(&wgpu::TextureViewDescriptor::default());
 
         let storage_bind_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("Ray tracing bind group layout"),
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    ty: wgpu::BindingType::StorageTexture {
-                        access: wgpu::StorageTextureAccess::WriteOnly,
-                        format: wgpu::TextureFormat::Rgba8Unorm,
-                        view_dimension: wgpu::TextureViewDimension::D2,
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: ACCUMULATION_FORMAT,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        visibility: ShaderStages::COMPUTE,
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: ACCUMULATION_FORMAT,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        visibility: ShaderStages::COMPUTE,
+                        count: None,
                     },
-                    visibility: ShaderStages::COMPUTE,
-                    count: None,
-                }],
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        visibility: ShaderStages::COMPUTE,
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        visibility: ShaderStages::COMPUTE,
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: ACCUMULATION_FORMAT,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        visibility: ShaderStages::COMPUTE,
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: ACCUMULATION_FORMAT,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        visibility: ShaderStages::COMPUTE,
+                        count: None,
+                    },
+                ],
             });
 
         let storage_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Ray tracing bind group"),
             layout: &storage_bind_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::TextureView(&color_buffer_view),
-            }],
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&color_buffer_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&stats_buffer_view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&stats_cache_view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: active_pixels_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&albedo_buffer_view),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::TextureView(&normal_depth_buffer_view),
+                },
+            ],
         });
 
+        // 32 bit float textures are not filterable, but the screen is drawn
+        // at the same resolution as the render so nearest sampling is enough
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: AddressMode::Repeat,
             address_mode_v: AddressMode::Repeat,
-            mag_filter: FilterMode::Linear,
-            min_filter: FilterMode::Linear,
-            mipmap_filter: FilterMode::Linear,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
             ..Default::default()
         });
 
@@ -96,7 +218,7 @@ impl Raytracer {
                     BindGroupLayoutEntry {
                         binding: 0,
                         ty: BindingType::Texture {
-                            sample_type: TextureSampleType::Float { filterable: true },
+                            sample_type: TextureSampleType::Float { filterable: false },
                             view_dimension: TextureViewDimension::D2,
                             multisampled: false,
                         },
@@ -105,7 +227,7 @@ impl Raytracer {
                     },
                     BindGroupLayoutEntry {
                         binding: 1,
-                        ty: wgpu::BindingType::Sampler(SamplerBindingType::Filtering),
+                        ty: wgpu::BindingType::Sampler(SamplerBindingType::NonFiltering),
                         visibility: ShaderStages::COMPUTE | ShaderStages::FRAGMENT,
                         count: None,
                     },
@@ -149,6 +271,12 @@ impl Raytracer {
             pipeline,
             color_buffer,
             color_cache,
+            stats_buffer,
+            stats_cache,
+            albedo_buffer,
+            normal_depth_buffer,
+            active_pixels_buffer,
+            active_pixels_readbacks,
             storage_bind_group,
             sampler_bind_group,
             sampler_bind_layout,
@@ -175,7 +303,17 @@ impl Raytracer {
     // }
     //
 
-    pub fn compute(&self, encoder: &mut CommandEncoder, world_bind_group: &BindGroup, time_bind_group: &BindGroup) {
+    /// With a tag the count of active pixels is copied out to be read by
+    /// [`Raytracer::poll_active_pixels`] a few frames later
+    pub fn compute(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        world_bind_group: &BindGroup,
+        time_bind_group: &BindGroup,
+        readback_tag: Option<(u32, u32)>,
+    ) {
+        encoder.clear_buffer(&self.active_pixels_buffer, 0, None);
+
         let mut ray_trace_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("Ray tracing pass"),
             timestamp_writes: None,
@@ -197,5 +335,94 @@ impl Raytracer {
             self.color_cache.as_image_copy(),
             self.color_buffer.size(),
         );
+        encoder.copy_texture_to_texture(
+            self.stats_buffer.as_image_copy(),
+            self.stats_cache.as_image_copy(),
+            self.stats_buffer.size(),
+        );
+        let free = self.active_pixels_readbacks.iter_mut().find(|readback| readback.tag.is_none());
+        if let (Some(tag), Some(readback)) = (readback_tag, free) {
+            encoder.copy_buffer_to_buffer(&self.active_pixels_buffer, 0, &readback.buffer, 0, readback.buffer.size());
+            readback.tag = Some(tag);
+        }
     }
+
+    /// Maps the counts copied since the last call and returns the newest one
+    /// the gpu has finished with its tag, without waiting for it. Needs to be
+    /// called after the encoder was submitted
+    pub fn poll_active_pixels(&mut self, render_env: &RenderEnv) -> Option<((u32, u32), u32)> {
+        for readback in &mut self.active_pixels_readbacks {
+            if readback.tag.is_some() && !readback.map_requested {
+                let mapped = readback.mapped.clone();
+                readback.buffer.slice(..).map_async(MapMode::Read, move |result| {
+                    mapped.store(result.is_ok(), Ordering::Release);
+                });
+                readback.map_requested = true;
+            }
+        }
+        render_env.device.poll(Maintain::Poll);
+
+        let mut newest = None;
+        for readback in &mut self.active_pixels_readbacks {
+            if !readback.mapped.swap(false, Ordering::Acquire) {
+                continue;
+            }
+            let active = bytemuck::pod_read_unaligned::<u32>(&readback.buffer.slice(..).get_mapped_range());
+            readback.buffer.unmap();
+            let tag = readback.tag.take().unwrap();
+            readback.map_requested = false;
+            if newest.is_none_or(|(newest_tag, _)| tag > newest_tag) {
+                newest = Some((tag, active));
+            }
+        }
+        newest
+    }
+
+    pub fn read_layers(&self, render_env: &RenderEnv) -> RenderLayers {
+        let read = |texture| bytemuck::pod_collect_to_vec(&read_texture(render_env, texture));
+        RenderLayers {
+            width: self.color_cache.width(),
+            height: self.color_cache.height(),
+            beauty: read(&self.color_cache),
+            albedo: read(&self.albedo_buffer),
+            normal_depth: read(&self.normal_depth_buffer),
+        }
+    }
+
+    pub fn color_view(&self) -> TextureView {
+        self.color_cache.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    pub fn stats_view(&self) -> TextureView {
+        self.stats_cache.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    pub fn albedo_view(&self) -> TextureView {
+        self.albedo_buffer.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    pub fn normal_depth_view(&self) -> TextureView {
+        self.normal_depth_buffer.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    pub fn size(&self) -> Extent3d {
+        self.color_buffer.size()
+    }
+}
+
+fn accumulation_texture(device: &Device, size: PhysicalSize<u32>, usage: TextureUsages) -> Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width: size.width,
+            height: size.height,
+            ..Default::default()
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: ACCUMULATION_FORMAT,
+        usage: usage | TextureUsages::STORAGE_BINDING,
+        view_formats: &[],
+    })
 }
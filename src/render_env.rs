@@ -12,8 +12,6 @@ pub struct RenderEnv {
 
 impl RenderEnv {
     pub async fn new(window: Window) -> Result<Self> {
-        let size = window.inner_size();
-
         // The instance is a handle to our GPU
         // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
@@ -38,9 +36,13 @@ impl RenderEnv {
                 &wgpu::DeviceDescriptor {
                     label: Some("Device"),
                     features: Features::empty(),
-                    limits: Limits::default(),
+                    // The scene needs more storage buffers than the default allows
+                    limits: Limits {
+                        max_storage_buffers_per_shader_stage: adapter.limits().max_storage_buffers_per_shader_stage,
+                        ..Limits::default()
+                    },
                 },
-                Some(&trace_path),
+                Some(trace_path),
             )
             .await?;
         let surface_config = SurfaceConfiguration {
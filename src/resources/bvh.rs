@@ -0,0 +1,116 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+
+use super::Mesh;
+
+/// Leaves hold at most this many triangles
+const MAX_LEAF_SIZE: usize = 4;
+
+/// A leaf when `count` is more than zero, then its triangles start at
+/// `left_or_first`. Otherwise the children are at `left_or_first` and the one after
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct BvhNode {
+    pub min: Vec3,
+    pub left_or_first: u32,
+    pub max: Vec3,
+    pub count: u32,
+}
+
+impl BvhNode {
+    fn empty() -> Self {
+        Self {
+            min: Vec3::splat(f32::MAX),
+            left_or_first: 0,
+            max: Vec3::splat(f32::MIN),
+            count: 0,
+        }
+    }
+}
+
+/// Bounding volume hierarchy over the triangles of a mesh. Leaves point
+/// into the returned order of the triangles, an empty mesh gets a single
+/// leaf for a triangle that can't be hit
+pub fn build_bvh(mesh: &Mesh) -> (Vec<BvhNode>, Vec<usize>) {
+    if mesh.triangles.is_empty() {
+        let leaf = BvhNode {
+            min: Vec3::ZERO,
+            left_or_first: 0,
+            max: Vec3::ZERO,
+            count: 1,
+        };
+        return (vec![leaf], Vec::new());
+    }
+
+    let bounds: Vec<(Vec3, Vec3)> = mesh
+        .triangles
+        .iter()
+        .map(|t| {
+            let positions = mesh.triangle_positions(t);
+            (positions.into_iter().fold(Vec3::MAX, Vec3::min), positions.into_iter().fold(Vec3::MIN, Vec3::max))
+        })
+        .collect();
+    build_bvh_over(&bounds)
+}
+
+/// Bounding volume hierarchy over boxes given as their min and max corners.
+/// Leaves point into the returned order of the boxes
+pub fn build_bvh_over(bounds: &[(Vec3, Vec3)]) -> (Vec<BvhNode>, Vec<usize>) {
+    let centroids: Vec<Vec3> = bounds.iter().map(|(min, max)| (*min + *max) * 0.5).collect();
+    let mut order: Vec<usize> = (0..bounds.len()).collect();
+
+    let mut nodes = vec![BvhNode::empty()];
+    subdivide(bounds, &centroids, &mut order, &mut nodes, 0, 0, bounds.len());
+    (nodes, order)
+}
+
+fn subdivide(
+    bounds: &[(Vec3, Vec3)],
+    centroids: &[Vec3],
+    order: &mut [usize],
+    nodes: &mut Vec<BvhNode>,
+    node: usize,
+    first: usize,
+    count: usize,
+) {
+    let range = &mut order[first..first + count];
+    let (min, max) = range.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), &i| (min.min(bounds[i].0), max.max(bounds[i].1)),
+    );
+    nodes[node].min = min;
+    nodes[node].max = max;
+
+    if count <= MAX_LEAF_SIZE {
+        nodes[node].left_or_first = first as u32;
+        nodes[node].count = count as u32;
+        return;
+    }
+
+    // Split the centroids in half along the longest axis
+    let (centroid_min, centroid_max) = range
+        .iter()
+        .fold((Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)), |(min, max), &i| {
+            (min.min(centroids[i]), max.max(centroids[i]))
+        });
+    let extent = centroid_max - centroid_min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+
+    let half = count / 2;
+    range.select_nth_unstable_by(half, |&a, &b| centroids[a][axis].total_cmp(&centroids[b][axis]));
+
+    let left = nodes.len();
+    nodes.push(BvhNode::empty());
+    nodes.push(BvhNode::empty());
+    nodes[node].left_or_first = left as u32;
+    nodes[node].count = 0;
+
+    subdivide(bounds, centroids, order, nodes, left, first, half);
+    subdivide(bounds, centroids, order, nodes, left + 1, first + half, count - half);
+}
@@ -14,11 +14,25 @@ const RIGHT: Vec3 = vec3(1.0, 0.0, 0.0);
 const UP: Vec3 = vec3(0.0, 1.0, 0.0);
 const FORWARD: Vec3 = vec3(0.0, 0.0, -1.0);
 
+/// Samples a pixel needs before its variance is trusted for adaptive sampling
+const MIN_ADAPTIVE_SAMPLES: u32 = 16;
+/// Safety net so a noise threshold that is never reached still stops rendering
+const MAX_ADAPTIVE_FRAMES: u32 = 4096;
+
 #[derive(Copy, Clone, Debug)]
 struct Drag {
     pub last_mouse_pos: Vec2,
 }
 
+/// Where a scene file puts the camera
+#[derive(Debug, Clone, Copy)]
+pub struct Viewpoint {
+    pub position: Vec3,
+    pub direction: Vec3,
+    /// Vertical field of view in radians
+    pub fov: f32,
+}
+
 #[derive(Debug)]
 pub struct Camera {
     buffer: Buffer,
@@ -30,6 +44,11 @@ pub struct Camera {
     samples_per_pixel: u32,
     frames_to_render: u32,
     current_frame: u32,
+    adaptive: bool,
+    noise_threshold: f32,
+    active_pixels: u32,
+    /// Counts the restarts so readbacks from an earlier render can be told apart
+    render: u32,
     speed: f32,
     drag: Option<Drag>,
     look_direction: Vec3,
@@ -45,6 +64,9 @@ pub struct CameraRaw {
     samples_per_pixel: u32,
     frames_to_render: u32,
     current_frame: u32,
+    noise_threshold: f32,
+    min_samples: u32,
+    padding: [u32; 2],
 }
 
 impl Camera {
@@ -66,6 +88,10 @@ impl Camera {
             samples_per_pixel: 1,
             frames_to_render: 8,
             current_frame: 0,
+            adaptive: false,
+            noise_threshold: 0.02,
+            active_pixels: u32::MAX,
+            render: 0,
             drag: None,
             look_direction: vec3(0.0, 0.0, -1.0),
             speed: 0.05,
@@ -81,8 +107,11 @@ impl Camera {
             pos: self.pos.xyzz(),
             focal_length: self.z_near,
             samples_per_pixel: self.samples_per_pixel,
-            frames_to_render: self.frames_to_render,
+            frames_to_render: self.frame_limit(),
             current_frame: self.current_frame,
+            noise_threshold: if self.adaptive { self.noise_threshold } else { 0.0 },
+            min_samples: MIN_ADAPTIVE_SAMPLES,
+            padding: [0; 2],
         }
     }
 
@@ -137,6 +166,9 @@ impl Camera {
             KeyCode::KeyJ => self.samples_per_pixel = (self.samples_per_pixel / 2).max(1),
             KeyCode::KeyI => self.frames_to_render = (self.frames_to_render * 2).min(256),
             KeyCode::KeyU => self.frames_to_render = (self.frames_to_render / 2).max(1),
+            KeyCode::KeyT => self.adaptive = !self.adaptive,
+            KeyCode::KeyN => self.noise_threshold = (self.noise_threshold / 2.0).max(0.001),
+            KeyCode::KeyM => self.noise_threshold = (self.noise_threshold * 2.0).min(0.5),
             KeyCode::ArrowUp => self.zoom(-1.0),
             KeyCode::ArrowDown => self.zoom(1.0),
             KeyCode::Backspace => {
@@ -187,7 +219,7 @@ impl Camera {
         };
 
         self.zoom(val);
-        self.scene_was_updated(&render_env);
+        self.scene_was_updated(render_env);
     }
 
     fn zoom(&mut self, val: f32) {
@@ -251,35 +283,71 @@ impl Camera {
 
     pub fn increase_frame(&mut self, render_env: &RenderEnv) {
         self.current_frame += 1;
-        let msg = format!(
-            "{}/{}",
-            self.current_frame.clamp(0, self.frames_to_render),
-            self.frames_to_render
-        );
-        progress_bar(
-            (self.current_frame as f32) / (self.frames_to_render as f32),
-            &msg,
-        );
+        if self.adaptive {
+            let total_pixels = (self.resolution.x * self.resolution.y) as u32;
+            let active = self.active_pixels.min(total_pixels);
+            let msg = format!("frame {}, {} pixels left", self.current_frame, active);
+            progress_bar(1.0 - active as f32 / total_pixels as f32, &msg);
+        } else {
+            let msg = format!(
+                "{}/{}",
+                self.current_frame.clamp(0, self.frames_to_render),
+                self.frames_to_render
+            );
+            progress_bar(
+                (self.current_frame as f32) / (self.frames_to_render as f32),
+                &msg,
+            );
+        }
 
         render_env.queue.write_buffer(&self.buffer, 0, bytes_of(&self.to_raw()));
     }
 
-    pub fn to_string(&self) -> String {
-        format!(
-            "Frames: {}, RaysPerPixel: {}",
-            self.frames_to_render, self.samples_per_pixel
-        )
-    }
-
     pub fn reset_render(&mut self) {
         self.current_frame = 0;
-        println!("\nRendering with:\n{}", self.to_string());
+        self.active_pixels = u32::MAX;
+        self.render = self.render.wrapping_add(1);
+        println!("\nRendering with:\n{}", self);
     }
 
     pub fn render_finished(&self) -> bool {
+        if self.adaptive {
+            return self.active_pixels == 0 || self.current_frame >= MAX_ADAPTIVE_FRAMES;
+        }
         self.current_frame >= self.frames_to_render
     }
 
+    /// The most frames that will be rendered, with adaptive sampling the
+    /// noise threshold decides when to stop instead of the frame count
+    fn frame_limit(&self) -> u32 {
+        if self.adaptive {
+            MAX_ADAPTIVE_FRAMES
+        } else {
+            self.frames_to_render
+        }
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.adaptive
+    }
+
+    /// The render and frame a readback of the active pixels belongs to
+    pub fn frame_tag(&self) -> (u32, u32) {
+        (self.render, self.current_frame)
+    }
+
+    /// Counts read back from before the last restart are ignored
+    pub fn set_active_pixels(&mut self, tag: (u32, u32), active_pixels: u32) {
+        if tag.0 == self.render {
+            self.active_pixels = active_pixels;
+        }
+    }
+
+    /// Most samples any single pixel could have taken so far
+    pub fn samples_taken(&self) -> u32 {
+        self.current_frame.min(self.frame_limit()) * self.samples_per_pixel
+    }
+
     pub fn scene_was_updated(&mut self, render_env: &RenderEnv) {
         self.reset_render();
         render_env
@@ -287,9 +355,56 @@ impl Camera {
             .write_buffer(&self.buffer, 0, bytes_of(&self.to_raw()));
     }
 
+    /// Moves the camera to where the scene wants it, this camera keeps the
+    /// vertical field of view over pi
+    pub fn set_viewpoint(&mut self, render_env: &RenderEnv, viewpoint: Viewpoint) {
+        self.pos = viewpoint.position;
+        self.look_direction = viewpoint.direction.try_normalize().unwrap_or(FORWARD);
+        self.fov = (viewpoint.fov / PI).clamp(0.01, 0.95);
+        self.scene_was_updated(render_env);
+    }
+
     pub fn buffer(&self) -> &Buffer {
         &self.buffer
     }
+
+    /// Describes the camera and the render settings for saved images
+    pub fn metadata(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Software", "raytracer".to_string()),
+            ("Camera position", format!("{} {} {}", self.pos.x, self.pos.y, self.pos.z)),
+            ("Camera direction", format!(
+                "{} {} {}",
+                self.look_direction.x, self.look_direction.y, self.look_direction.z
+            )),
+            ("Camera fov", self.fov.to_string()),
+            ("Samples per pixel", self.samples_per_pixel.to_string()),
+            ("Frames", self.current_frame.min(self.frame_limit()).to_string()),
+            ("Adaptive sampling", if self.adaptive {
+                format!("noise threshold {}", self.noise_threshold)
+            } else {
+                "off".to_string()
+            }),
+        ]
+    }
+}
+
+impl std::fmt::Display for Camera {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.adaptive {
+            write!(
+                f,
+                "Noise threshold: {}, RaysPerPixel: {}",
+                self.noise_threshold, self.samples_per_pixel
+            )
+        } else {
+            write!(
+                f,
+                "Frames: {}, RaysPerPixel: {}",
+                self.frames_to_render, self.samples_per_pixel
+            )
+        }
+    }
 }
 
 fn progress_bar(mut progress: f32, msg: &str) {
@@ -0,0 +1,210 @@
+use std::{f32::consts::PI, path::Path};
+
+use bytemuck::{bytes_of, cast_slice, Pod, Zeroable};
+use wgpu::{util::{BufferInitDescriptor, DeviceExt}, Buffer, BufferUsages, Texture, TextureView};
+use winit::keyboard::KeyCode;
+
+use crate::{common::FloatImage, render_env::RenderEnv};
+
+/// Equirectangular environment map lighting the scene on a ray miss
+pub struct Environment {
+    texture: Texture,
+    pub view: TextureView,
+    /// The sampling distribution built from the luminance of the map, laid out as
+    /// the marginal cdf over the rows, then the cdf of every row and then the
+    /// pdf of every pixel
+    pub distribution_buffer: Buffer,
+    pub params_buffer: Buffer,
+    width: u32,
+    height: u32,
+    enabled: bool,
+    /// Around the up axis in radians
+    rotation: f32,
+    intensity: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct EnvironmentRaw {
+    rotation: f32,
+    intensity: f32,
+    width: u32,
+    height: u32,
+    enabled: u32,
+    padding: [u32; 3],
+}
+
+impl Environment {
+    pub fn load(render_env: &RenderEnv, file: &Path) -> anyhow::Result<Self> {
+        let mut image = FloatImage::load(file)?;
+        let limits = render_env.device.limits();
+        let (width, height) = (image.width, image.height);
+        while !fits_limits(image.width, image.height, &limits) {
+            if image.width == 1 && image.height == 1 {
+                anyhow::bail!("{} doesn't fit on the GPU at any size", file.display());
+            }
+            image = image.half_size();
+        }
+        if image.width != width {
+            println!(
+                "\nWarning: {} is {}x{} which is more than the GPU allows, it was shrunk to {}x{}",
+                file.display(),
+                width,
+                height,
+                image.width,
+                image.height
+            );
+        }
+
+        let distribution = build_distribution(&image);
+        let enabled = distribution.is_some();
+        if !enabled {
+            println!("{} is completely black, ignoring it", file.display());
+        }
+
+        Ok(Self::new(render_env, &image, distribution.unwrap_or(vec![0.0]), enabled))
+    }
+
+    /// Without a map the kernel falls back to its sky gradient
+    pub fn none(render_env: &RenderEnv) -> Self {
+        let image = FloatImage {
+            width: 1,
+            height: 1,
+            pixels: vec![[0.0; 4]],
+        };
+        Self::new(render_env, &image, vec![0.0], false)
+    }
+
+    fn new(render_env: &RenderEnv, image: &FloatImage, distribution: Vec<f32>, enabled: bool) -> Self {
+        let device = &render_env.device;
+        let texture = image.to_texture(render_env);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let distribution_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Environment distribution buffer"),
+            contents: cast_slice(&distribution),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Environment buffer"),
+            size: std::mem::size_of::<EnvironmentRaw>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let environment = Self {
+            texture,
+            view,
+            distribution_buffer,
+            params_buffer,
+            width: image.width,
+            height: image.height,
+            enabled,
+            rotation: 0.0,
+            intensity: 1.0,
+        };
+        environment.update(render_env);
+        environment
+    }
+
+    fn to_raw(&self) -> EnvironmentRaw {
+        EnvironmentRaw {
+            rotation: self.rotation,
+            intensity: self.intensity,
+            width: self.width,
+            height: self.height,
+            enabled: self.enabled as u32,
+            padding: [0; 3],
+        }
+    }
+
+    fn update(&self, render_env: &RenderEnv) {
+        render_env.queue.write_buffer(&self.params_buffer, 0, bytes_of(&self.to_raw()));
+    }
+
+    pub fn set_rotation(&mut self, render_env: &RenderEnv, rotation: f32) {
+        self.rotation = rotation.rem_euclid(2.0 * PI);
+        self.update(render_env);
+    }
+
+    pub fn set_intensity(&mut self, render_env: &RenderEnv, intensity: f32) {
+        self.intensity = intensity.max(0.0);
+        self.update(render_env);
+    }
+
+    /// Returns true when the environment changed and the render needs to restart
+    pub fn key_press(&mut self, render_env: &RenderEnv, key: KeyCode) -> bool {
+        match key {
+            KeyCode::Comma => self.set_rotation(render_env, self.rotation - PI / 12.0),
+            KeyCode::Period => self.set_rotation(render_env, self.rotation + PI / 12.0),
+            KeyCode::Semicolon => self.set_intensity(render_env, self.intensity / 1.25),
+            KeyCode::Quote => self.set_intensity(render_env, self.intensity * 1.25),
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Whether the texture and the distribution of a map this size can be bound
+fn fits_limits(width: u32, height: u32, limits: &wgpu::Limits) -> bool {
+    let (width, height) = (width as u64, height as u64);
+    let floats = (height + 1) + height * (width + 1) + width * height;
+    let max_binding = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+    let max_dimension = limits.max_texture_dimension_2d as u64;
+    width <= max_dimension && height <= max_dimension && floats * 4 <= max_binding
+}
+
+/// Builds the piecewise constant 2D distribution proportional to the luminance,
+/// weighted by sin(theta) since the rows near the poles cover less of the sphere
+fn build_distribution(image: &FloatImage) -> Option<Vec<f32>> {
+    let width = image.width as usize;
+    let height = image.height as usize;
+
+    let func: Vec<f32> = image
+        .pixels
+        .chunks(width)
+        .enumerate()
+        .flat_map(|(y, row)| {
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            row.iter().map(move |p| (0.2126 * p[0] + 0.7152 * p[1] + 0.0722 * p[2]).max(0.0) * sin_theta)
+        })
+        .collect();
+
+    let mut row_cdfs = Vec::with_capacity(height * (width + 1));
+    let mut row_integrals = Vec::with_capacity(height);
+    for row in func.chunks(width) {
+        let (cdf, integral) = cdf(row);
+        row_cdfs.extend(cdf);
+        row_integrals.push(integral);
+    }
+
+    let (marginal_cdf, integral) = cdf(&row_integrals);
+    if integral <= 0.0 {
+        return None;
+    }
+
+    let mut distribution = marginal_cdf;
+    distribution.extend(row_cdfs);
+    distribution.extend(func.iter().map(|f| f / integral));
+    Some(distribution)
+}
+
+/// The normalized cdf of a piecewise constant function over [0, 1] and its integral
+fn cdf(func: &[f32]) -> (Vec<f32>, f32) {
+    let n = func.len() as f32;
+    let mut cdf = Vec::with_capacity(func.len() + 1);
+    cdf.push(0.0);
+    for f in func {
+        cdf.push(cdf.last().unwrap() + f / n);
+    }
+
+    let integral = *cdf.last().unwrap();
+    if integral > 0.0 {
+        cdf.iter_mut().for_each(|c| *c /= integral);
+    } else {
+        // Spread evenly so a black row can still be sampled
+        cdf.iter_mut().enumerate().for_each(|(i, c)| *c = i as f32 / n);
+    }
+    (cdf, integral)
+}
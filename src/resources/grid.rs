@@ -0,0 +1,78 @@
+use std::path::Path;
+
+use anyhow::Context;
+use glam::Vec3;
+
+/// Densities on a regular grid, x changes fastest and then y
+#[derive(Debug, Clone)]
+pub struct DensityGrid {
+    pub resolution: [u32; 3],
+    pub densities: Vec<f32>,
+}
+
+impl DensityGrid {
+    /// Loads a Mitsuba .vol file with float32 or uint8 voxels, keeping the
+    /// first channel, or raw little endian float32 voxels with the given resolution
+    pub fn load(file: &Path, resolution: Option<[u32; 3]>) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(file).with_context(|| format!("Unable to read {}", file.display()))?;
+        let grid = if bytes.starts_with(b"VOL\x03") {
+            Self::from_vol(&bytes)
+        } else {
+            let resolution = resolution.with_context(|| format!("{} is raw so it needs a resolution", file.display()))?;
+            Self::from_raw(&bytes, resolution)
+        };
+        grid.with_context(|| format!("Unable to load {}", file.display()))
+    }
+
+    fn from_vol(bytes: &[u8]) -> anyhow::Result<Self> {
+        let int = |index: usize| -> anyhow::Result<u32> {
+            let start = 4 + index * 4;
+            let field = bytes.get(start..start + 4).context("The header is cut short")?;
+            Ok(u32::from_le_bytes(field.try_into()?))
+        };
+        let encoding = int(0)?;
+        let resolution = [int(1)?, int(2)?, int(3)?];
+        let channels = int(4)? as usize;
+        // The bounding box after the header is left out, the scene places the grid
+        let data = &bytes[48.min(bytes.len())..];
+
+        let count = resolution.iter().map(|&r| r as usize).product::<usize>();
+        let densities = match encoding {
+            1 => data.chunks_exact(4).step_by(channels).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect(),
+            3 => data.iter().step_by(channels).map(|&d| d as f32 / 255.0).collect(),
+            _ => anyhow::bail!("Only float32 and uint8 volumes are supported, not encoding {}", encoding),
+        };
+        Self::new(resolution, densities, count)
+    }
+
+    fn from_raw(bytes: &[u8], resolution: [u32; 3]) -> anyhow::Result<Self> {
+        let count = resolution.iter().map(|&r| r as usize).product::<usize>();
+        let densities = bytes.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+        Self::new(resolution, densities, count)
+    }
+
+    fn new(resolution: [u32; 3], mut densities: Vec<f32>, count: usize) -> anyhow::Result<Self> {
+        if count == 0 || densities.len() < count {
+            anyhow::bail!("Expected {} voxels for a {:?} grid but found {}", count, resolution, densities.len());
+        }
+        densities.truncate(count);
+        densities.iter_mut().for_each(|d| *d = d.max(0.0));
+        Ok(Self { resolution, densities })
+    }
+
+    pub fn max_density(&self) -> f32 {
+        self.densities.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+/// A density grid stretched over a box in the scene
+#[derive(Debug, Clone)]
+pub struct GridVolume {
+    pub grid: DensityGrid,
+    pub center: Vec3,
+    pub size: Vec3,
+    /// Multiplies the densities in the grid
+    pub density: f32,
+    pub color: Vec3,
+    pub anisotropy: f32,
+}
@@ -0,0 +1,170 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec2, Vec3};
+
+use super::{build_bvh, build_bvh_over, BvhNode, Mesh, MeshVertex, Primitive, Sphere, Triangle};
+
+/// Without an override the instance keeps the materials of its object
+pub const NO_MATERIAL: u32 = u32::MAX;
+
+/// What an instance places a copy of, by index into the meshes, spheres or primitives of the scene
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstanceObject {
+    Mesh(usize),
+    Sphere(usize),
+    Primitive(usize),
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstanceKind {
+    Mesh = 0,
+    Sphere = 1,
+    Primitive = 2,
+    /// Stands in when there are no instances since bindings can't be empty
+    None = u32::MAX,
+}
+
+/// A copy of an object moved by `transform`, meshes are only ever drawn through instances
+#[derive(Debug, Clone, Copy)]
+pub struct Instance {
+    pub object: InstanceObject,
+    pub transform: Mat4,
+    pub material: Option<u32>,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct InstanceRaw {
+    pub world_to_object: Mat4,
+    pub object_to_world: Mat4,
+    pub kind: u32,
+    /// The root node of the mesh in the bvh, or the sphere or primitive
+    pub index: u32,
+    pub material: u32,
+    pub padding: u32,
+}
+
+/// The meshes and instances as the kernel sees them. The bvh over the
+/// instances comes first, then the bvh of every mesh
+pub struct InstanceData {
+    pub vertices: Vec<MeshVertex>,
+    pub triangles: Vec<Triangle>,
+    pub bvh: Vec<BvhNode>,
+    pub instances: Vec<InstanceRaw>,
+}
+
+impl InstanceData {
+    pub fn new(meshes: &[Mesh], spheres: &[Sphere], primitives: &[Primitive], instances: &[Instance]) -> Self {
+        let bounds: Vec<(Vec3, Vec3)> = instances
+            .iter()
+            .map(|instance| {
+                let (min, max) = match instance.object {
+                    InstanceObject::Mesh(i) => mesh_bounds(&meshes[i]),
+                    InstanceObject::Sphere(i) => spheres[i].bounds(),
+                    InstanceObject::Primitive(i) => primitives[i].bounds(),
+                };
+                transform_bounds(instance.transform, min, max)
+            })
+            .collect();
+
+        let (mut bvh, order) = if instances.is_empty() {
+            let leaf = BvhNode {
+                min: Vec3::ZERO,
+                left_or_first: 0,
+                max: Vec3::ZERO,
+                count: 1,
+            };
+            (vec![leaf], Vec::new())
+        } else {
+            build_bvh_over(&bounds)
+        };
+
+        // Every mesh gets its own bvh after the one over the instances
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        let mut roots = Vec::new();
+        // The triangles are copied in the order of the bvh so the meshes themselves stay as they are
+        for mesh in meshes {
+            let (nodes, order) = build_bvh(mesh);
+            let node_offset = bvh.len() as u32;
+            let triangle_offset = triangles.len() as u32;
+            let vertex_offset = vertices.len() as u32;
+            roots.push(node_offset);
+            bvh.extend(nodes.into_iter().map(|node| BvhNode {
+                left_or_first: node.left_or_first + if node.count > 0 { triangle_offset } else { node_offset },
+                ..node
+            }));
+            triangles.extend(order.iter().map(|&i| Triangle {
+                vertices: mesh.triangles[i].vertices.map(|v| v + vertex_offset),
+                material_index: mesh.triangles[i].material_index,
+            }));
+            vertices.extend_from_slice(&mesh.vertices);
+            // Bindings can't be empty and empty meshes need a triangle for their leaf
+            if mesh.triangles.is_empty() {
+                vertices.push(MeshVertex::new(Vec3::ZERO, Vec3::ZERO, Vec2::ZERO));
+                triangles.push(Triangle { vertices: [vertex_offset; 3], material_index: 0 });
+            }
+        }
+        if triangles.is_empty() {
+            vertices.push(MeshVertex::new(Vec3::ZERO, Vec3::ZERO, Vec2::ZERO));
+            triangles.push(Triangle { vertices: [0; 3], material_index: 0 });
+        }
+
+        let mut instances: Vec<InstanceRaw> = order
+            .iter()
+            .map(|&i| {
+                let instance = &instances[i];
+                let (kind, index) = match instance.object {
+                    InstanceObject::Mesh(i) => (InstanceKind::Mesh, roots[i]),
+                    InstanceObject::Sphere(i) => (InstanceKind::Sphere, i as u32),
+                    InstanceObject::Primitive(i) => (InstanceKind::Primitive, i as u32),
+                };
+                InstanceRaw {
+                    world_to_object: instance.transform.inverse(),
+                    object_to_world: instance.transform,
+                    kind: kind as u32,
+                    index,
+                    material: instance.material.unwrap_or(NO_MATERIAL),
+                    padding: 0,
+                }
+            })
+            .collect();
+        if instances.is_empty() {
+            instances.push(InstanceRaw {
+                world_to_object: Mat4::IDENTITY,
+                object_to_world: Mat4::IDENTITY,
+                kind: InstanceKind::None as u32,
+                index: 0,
+                material: NO_MATERIAL,
+                padding: 0,
+            });
+        }
+
+        Self {
+            vertices,
+            triangles,
+            bvh,
+            instances,
+        }
+    }
+}
+
+fn mesh_bounds(mesh: &Mesh) -> (Vec3, Vec3) {
+    mesh.vertices
+        .iter()
+        .fold((Vec3::MAX, Vec3::MIN), |(min, max), v| (min.min(v.position), max.max(v.position)))
+}
+
+/// The box around the transformed corners
+fn transform_bounds(transform: Mat4, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+    (0..8)
+        .map(|i| {
+            let corner = Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+            transform.transform_point3(corner)
+        })
+        .fold((Vec3::MAX, Vec3::MIN), |(min, max), p| (min.min(p), max.max(p)))
+}
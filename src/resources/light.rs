@@ -0,0 +1,213 @@
+use std::f32::consts::PI;
+
+use bytemuck::{cast_slice, Pod, Zeroable};
+use glam::{vec3, Quat, Vec3};
+use wgpu::{util::{BufferInitDescriptor, DeviceExt}, Buffer, BufferUsages};
+use winit::keyboard::KeyCode;
+
+use crate::render_env::RenderEnv;
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightKind {
+    Point = 0,
+    /// A point light limited to a cone
+    Spot = 1,
+    /// Infinitely far away, only the direction matters
+    Directional = 2,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct Light {
+    pub position: Vec3,
+    pub kind: u32,
+    /// The direction the light travels in for spot and directional lights
+    pub direction: Vec3,
+    pub intensity: f32,
+    pub color: Vec3,
+    /// The spot is at full strength inside the inner cone and fades out to the outer one
+    pub cos_inner: f32,
+    pub cos_outer: f32,
+    pub enabled: u32,
+    pub padding: [u32; 2],
+}
+
+impl Light {
+    pub fn point(position: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self::new(LightKind::Point, position, Vec3::NEG_Y, color, intensity)
+    }
+
+    /// The angles are the half angles of the cones in radians
+    pub fn spot(position: Vec3, direction: Vec3, color: Vec3, intensity: f32, inner: f32, outer: f32) -> Self {
+        let outer = outer.max(inner + 1e-3);
+        Self {
+            cos_inner: inner.cos(),
+            cos_outer: outer.cos(),
+            ..Self::new(LightKind::Spot, position, direction, color, intensity)
+        }
+    }
+
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self::new(LightKind::Directional, Vec3::ZERO, direction, color, intensity)
+    }
+
+    fn new(kind: LightKind, position: Vec3, direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            position,
+            kind: kind as u32,
+            direction: direction.normalize_or_zero(),
+            intensity,
+            color,
+            cos_inner: 1.0,
+            cos_outer: 1.0,
+            enabled: 1,
+            padding: [0; 2],
+        }
+    }
+
+    /// Scales the half angles of both cones, the outer one stays under 89 degrees
+    fn scale_cone(&mut self, factor: f32) {
+        let outer = (self.cos_outer.acos() * factor).clamp(0.01, 89f32.to_radians());
+        let inner = (self.cos_inner.acos() * factor).min(outer - 1e-3).max(0.0);
+        self.cos_outer = outer.cos();
+        self.cos_inner = inner.cos();
+    }
+
+    fn kind(&self) -> &'static str {
+        match self.kind {
+            0 => "point",
+            1 => "spot",
+            _ => "directional",
+        }
+    }
+}
+
+/// The colors F7 goes through
+const COLORS: [Vec3; 6] = [
+    Vec3::ONE,
+    vec3(1.0, 0.78, 0.56),
+    vec3(0.75, 0.85, 1.0),
+    vec3(1.0, 0.25, 0.2),
+    vec3(0.3, 1.0, 0.35),
+    vec3(0.3, 0.45, 1.0),
+];
+
+/// The point, spot and directional lights of the scene, one of them is
+/// selected for editing at runtime
+pub struct Lights {
+    lights: Vec<Light>,
+    pub buffer: Buffer,
+    selected: usize,
+}
+
+impl Lights {
+    pub fn new(render_env: &RenderEnv, lights: Vec<Light>) -> Self {
+        let buffer = render_env.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Lights buffer"),
+            contents: cast_slice(&Self::gpu_lights(&lights)),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+
+        Self {
+            lights,
+            buffer,
+            selected: 0,
+        }
+    }
+
+    /// Bindings can't be empty so a scene without lights gets a disabled one
+    fn gpu_lights(lights: &[Light]) -> Vec<Light> {
+        if lights.is_empty() {
+            let mut off = Light::point(Vec3::ZERO, Vec3::ZERO, 0.0);
+            off.enabled = 0;
+            return vec![off];
+        }
+        lights.to_vec()
+    }
+
+    fn update(&self, render_env: &RenderEnv) {
+        render_env.queue.write_buffer(&self.buffer, 0, cast_slice(&Self::gpu_lights(&self.lights)));
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// Changes a light in place, the number of lights can't change without a reload
+    pub fn set_light(&mut self, render_env: &RenderEnv, index: usize, light: Light) {
+        let Some(l) = self.lights.get_mut(index) else {
+            return;
+        };
+        *l = light;
+        self.update(render_env);
+    }
+
+    /// Keeps the light that was selected before a reload when there still is one
+    pub fn keep_selection(&mut self, previous: &Lights) {
+        self.selected = previous.selected.min(self.lights.len().saturating_sub(1));
+    }
+
+    /// C selects the next light, Z and X dim or brighten it, Q turns it on or
+    /// off and F7 changes its color. F8 and F9 narrow or widen a spot and the
+    /// left and right arrows turn spot and directional lights around the up
+    /// axis. Returns true when the render needs to restart
+    pub fn key_press(&mut self, render_env: &RenderEnv, key: KeyCode) -> bool {
+        if self.lights.is_empty() {
+            return false;
+        }
+
+        let mut light = self.lights[self.selected];
+        let spot = light.kind == LightKind::Spot as u32;
+        let has_direction = light.kind != LightKind::Point as u32;
+        match key {
+            KeyCode::KeyC => {
+                self.selected = (self.selected + 1) % self.lights.len();
+                println!("\nLight: {}", self);
+                return false;
+            }
+            KeyCode::KeyZ => light.intensity /= 1.25,
+            KeyCode::KeyX => light.intensity *= 1.25,
+            KeyCode::KeyQ => light.enabled = 1 - light.enabled,
+            KeyCode::F7 => {
+                let next = COLORS.iter().position(|c| *c == light.color).map_or(0, |i| i + 1);
+                light.color = COLORS[next % COLORS.len()];
+            }
+            KeyCode::F8 if spot => light.scale_cone(1.0 / 1.2),
+            KeyCode::F9 if spot => light.scale_cone(1.2),
+            KeyCode::ArrowLeft if has_direction => light.direction = Quat::from_rotation_y(PI / 12.0) * light.direction,
+            KeyCode::ArrowRight if has_direction => light.direction = Quat::from_rotation_y(-PI / 12.0) * light.direction,
+            _ => return false,
+        }
+        self.set_light(render_env, self.selected, light);
+        println!("\nLight: {}", self);
+        true
+    }
+}
+
+impl std::fmt::Display for Lights {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let light = &self.lights[self.selected];
+        let state = if light.enabled == 1 { "on" } else { "off" };
+        write!(
+            f,
+            "{}/{} {} ({}) intensity {:.2} color {:.2} {:.2} {:.2}",
+            self.selected + 1,
+            self.lights.len(),
+            light.kind(),
+            state,
+            light.intensity,
+            light.color.x,
+            light.color.y,
+            light.color.z
+        )?;
+        if light.kind != LightKind::Point as u32 {
+            let d = light.direction;
+            write!(f, " direction {:.2} {:.2} {:.2}", d.x, d.y, d.z)?;
+        }
+        if light.kind == LightKind::Spot as u32 {
+            write!(f, " cone {:.1} degrees", light.cos_outer.acos().to_degrees())?;
+        }
+        Ok(())
+    }
+}
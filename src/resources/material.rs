@@ -2,16 +2,54 @@ use bytemuck::{Pod, Zeroable, Contiguous};
 use glam::{Vec3, vec3, Vec4};
 use rand::{distributions::{self, Standard}, prelude::Distribution, rngs::ThreadRng, Rng, thread_rng};
 
+use super::{Palette, Scheme};
+
 #[repr(C, align(16))]
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
 pub struct Material {
     pub color: Vec4,
+    /// Light given off by the surface, zero for most materials
+    pub emission: Vec4,
     pub metallic: f32,
     pub roughness: f32,
     pub specular: f32,
-    pub padding: f32,
+    /// Indices into the texture table or [`NO_TEXTURE`]. The albedo and
+    /// emission textures multiply the color and emission, the green and blue
+    /// channels of the roughness metallic texture multiply those values like in glTF
+    pub albedo_texture: u32,
+    pub roughness_metallic_texture: u32,
+    pub emission_texture: u32,
+    /// Tangent space normals with green pointing up the image
+    pub normal_texture: u32,
+    /// Heights from the red channel, used when there is no normal texture
+    pub bump_texture: u32,
+    /// Scales how far the normal or bump texture tilts the normal
+    pub normal_strength: f32,
+    /// Index of refraction of dielectrics, for their reflections and refraction
+    pub ior: f32,
+    /// How much light goes through instead of being scattered by a dielectric
+    pub transmission: f32,
+    /// How much the reflection of a dielectric takes its color
+    pub specular_tint: f32,
+    /// Complex index of refraction (n + ik) of metals per channel. When k is
+    /// zero the color is the reflectance at every angle
+    pub eta: Vec4,
+    pub k: Vec4,
+    /// Stretches the highlights along the tangent, from 0 to 1
+    pub anisotropy: f32,
+    /// Turns the tangent around the normal, in turns
+    pub anisotropy_rotation: f32,
+    /// Cloth like reflection at grazing angles, tinted by the color with `sheen_tint`
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    /// A white coat on top with its own roughness
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub padding: [u32; 2],
 }
 
+pub const NO_TEXTURE: u32 = u32::MAX;
+
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy)]
@@ -19,20 +57,72 @@ pub enum MaterialType {
     Metal = 0,
 }
 
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            color: Vec4::ONE,
+            emission: Vec4::ZERO,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            albedo_texture: NO_TEXTURE,
+            roughness_metallic_texture: NO_TEXTURE,
+            emission_texture: NO_TEXTURE,
+            normal_texture: NO_TEXTURE,
+            bump_texture: NO_TEXTURE,
+            normal_strength: 1.0,
+            ior: 1.5,
+            transmission: 0.0,
+            specular_tint: 0.0,
+            eta: Vec4::ZERO,
+            k: Vec4::ZERO,
+            anisotropy: 0.0,
+            anisotropy_rotation: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.1,
+            padding: [0; 2],
+        }
+    }
+}
+
 impl Material {
     pub fn random_new() -> Material {
         thread_rng().gen()
     }
+
+    /// A metal from its measured complex index of refraction, the color is
+    /// its reflectance when seen straight on
+    pub fn conductor(eta: Vec3, k: Vec3, roughness: f32) -> Material {
+        let k2 = k * k;
+        let reflectance = ((eta - 1.0) * (eta - 1.0) + k2) / ((eta + 1.0) * (eta + 1.0) + k2);
+        Material {
+            color: reflectance.extend(1.0),
+            metallic: 1.0,
+            roughness,
+            eta: eta.extend(0.0),
+            k: k.extend(0.0),
+            ..Default::default()
+        }
+    }
+
+    /// A glass like material that `transmission` of the light goes through
+    pub fn dielectric(color: Vec3, ior: f32, roughness: f32, transmission: f32) -> Material {
+        Material {
+            color: color.extend(1.0),
+            roughness,
+            ior,
+            transmission,
+            ..Default::default()
+        }
+    }
 }
 
+/// A material from a palette of its own
 impl Distribution<Material> for Standard {
     fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> Material {
-        Material {
-            color: rng.gen(),
-            metallic: rng.gen(),
-            roughness: rng.gen(),
-            specular: rng.gen(),
-            padding: 0.0,
-        }
+        let scheme = Scheme::ALL[rng.gen_range(0..Scheme::ALL.len())];
+        Palette::new(scheme, rng).material(0, rng)
     }
 }
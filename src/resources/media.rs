@@ -0,0 +1,159 @@
+use bytemuck::{bytes_of, cast_slice, Pod, Zeroable};
+use glam::Vec3;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    Buffer, BufferUsages, TextureFormat, TextureView, TextureViewDescriptor,
+};
+
+use crate::{common::upload_texture_3d, render_env::RenderEnv};
+
+use super::GridVolume;
+
+/// The uniform buffer holds a fixed number of volumes
+pub const MAX_VOLUMES: usize = 16;
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeKind {
+    Sphere = 0,
+    Box = 1,
+}
+
+/// Fog that fills the whole scene and thins out going up
+#[derive(Debug, Clone, Copy)]
+pub struct Fog {
+    /// How much of the light it scatters instead of absorbing
+    pub color: Vec3,
+    /// Extinction per unit of distance at `height`
+    pub density: f32,
+    pub height: f32,
+    /// How fast the density falls off above `height`, zero for even fog
+    pub falloff: f32,
+    /// Henyey-Greenstein g, zero scatters the same way in every direction
+    pub anisotropy: f32,
+}
+
+/// A bounded region with a constant density, like smoke in a box
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct Volume {
+    pub center: Vec3,
+    pub kind: u32,
+    /// Radius in x for spheres and half the size for boxes
+    pub size: Vec3,
+    pub density: f32,
+    pub color: Vec3,
+    pub anisotropy: f32,
+}
+
+impl Volume {
+    pub fn sphere(center: Vec3, radius: f32, density: f32, color: Vec3, anisotropy: f32) -> Self {
+        Self {
+            center,
+            kind: VolumeKind::Sphere as u32,
+            size: Vec3::splat(radius),
+            density,
+            color,
+            anisotropy,
+        }
+    }
+
+    pub fn cuboid(center: Vec3, size: Vec3, density: f32, color: Vec3, anisotropy: f32) -> Self {
+        Self {
+            center,
+            kind: VolumeKind::Box as u32,
+            size: size * 0.5,
+            density,
+            color,
+            anisotropy,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+struct MediaRaw {
+    fog_color: Vec3,
+    fog_density: f32,
+    fog_height: f32,
+    fog_falloff: f32,
+    fog_anisotropy: f32,
+    volume_count: u32,
+    grid_min: Vec3,
+    /// Zero without a grid
+    grid_density: f32,
+    grid_max: Vec3,
+    /// The highest density in the grid, which delta tracking steps by
+    grid_majorant: f32,
+    grid_color: Vec3,
+    grid_anisotropy: f32,
+    volumes: [Volume; MAX_VOLUMES],
+}
+
+/// The fog, volumes and density grid the kernel samples scattering distances in
+pub struct Media {
+    pub buffer: Buffer,
+    grid_texture: wgpu::Texture,
+    pub grid_view: TextureView,
+}
+
+impl Media {
+    pub fn new(render_env: &RenderEnv, fog: Option<Fog>, volumes: &[Volume], grid: Option<&GridVolume>) -> Self {
+        let fog = fog.unwrap_or(Fog {
+            color: Vec3::ONE,
+            density: 0.0,
+            height: 0.0,
+            falloff: 0.0,
+            anisotropy: 0.0,
+        });
+        let mut raw = MediaRaw {
+            fog_color: fog.color,
+            fog_density: fog.density,
+            fog_height: fog.height,
+            fog_falloff: fog.falloff,
+            fog_anisotropy: fog.anisotropy.clamp(-0.99, 0.99),
+            volume_count: volumes.len().min(MAX_VOLUMES) as u32,
+            grid_min: Vec3::ZERO,
+            grid_density: 0.0,
+            grid_max: Vec3::ZERO,
+            grid_majorant: 0.0,
+            grid_color: Vec3::ONE,
+            grid_anisotropy: 0.0,
+            volumes: [Volume::zeroed(); MAX_VOLUMES],
+        };
+        if let Some(grid) = grid {
+            raw.grid_min = grid.center - grid.size * 0.5;
+            raw.grid_max = grid.center + grid.size * 0.5;
+            raw.grid_density = grid.density;
+            raw.grid_majorant = grid.density * grid.grid.max_density();
+            raw.grid_color = grid.color;
+            raw.grid_anisotropy = grid.anisotropy.clamp(-0.99, 0.99);
+        }
+        for (slot, volume) in raw.volumes.iter_mut().zip(volumes) {
+            *slot = Volume {
+                anisotropy: volume.anisotropy.clamp(-0.99, 0.99),
+                ..*volume
+            };
+        }
+
+        let buffer = render_env.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Media buffer"),
+            contents: bytes_of(&raw),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        // Bindings can't be empty
+        let (resolution, densities) = match grid {
+            Some(grid) => (grid.grid.resolution, grid.grid.densities.as_slice()),
+            None => ([1; 3], [0.0].as_slice()),
+        };
+        let grid_texture = upload_texture_3d(render_env, resolution, TextureFormat::R32Float, cast_slice(densities));
+        let grid_view = grid_texture.create_view(&TextureViewDescriptor::default());
+
+        Self {
+            buffer,
+            grid_texture,
+            grid_view,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use anyhow::Context;
+use bytemuck::{Pod, Zeroable};
+use glam::{vec2, Vec2, Vec3, Vec4};
+
+use crate::common::srgb_to_linear;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct MeshVertex {
+    pub position: Vec3,
+    pub u: f32,
+    /// Zero when the file has no normals, the kernel uses the face normal then
+    pub normal: Vec3,
+    pub v: f32,
+    /// Points along u, w is the sign of the bitangent pointing up the image.
+    /// Zero when the vertex has no texture coordinates
+    pub tangent: Vec4,
+    /// Multiplies the albedo of the material, white without vertex colors
+    pub color: Vec3,
+    pub padding: f32,
+}
+
+impl MeshVertex {
+    pub fn new(position: Vec3, normal: Vec3, tex_coords: Vec2) -> Self {
+        Self {
+            position,
+            u: tex_coords.x,
+            normal,
+            v: tex_coords.y,
+            tangent: Vec4::ZERO,
+            color: Vec3::ONE,
+            padding: 0.0,
+        }
+    }
+
+    fn tex_coords(&self) -> Vec2 {
+        vec2(self.u, self.v)
+    }
+}
+
+/// Indices into the vertices of all meshes and the material of the triangle
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct Triangle {
+    pub vertices: [u32; 3],
+    pub material_index: u32,
+}
+
+/// Triangles with their own vertices, the indices start at zero
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub vertices: Vec<MeshVertex>,
+    pub triangles: Vec<Triangle>,
+}
+
+impl Mesh {
+    /// Picks the loader from the extension, .obj, .ply or .stl
+    pub fn load(file: &Path, material_index: u32) -> anyhow::Result<Self> {
+        match file.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("obj") => Self::load_obj(file, material_index),
+            Some("ply") => Self::load_ply(file, material_index),
+            Some("stl") => Self::load_stl(file, material_index),
+            _ => anyhow::bail!("{} isn't an .obj, .ply or .stl file", file.display()),
+        }
+    }
+
+    /// Loads every model in a Wavefront .obj file with one material
+    pub fn load_obj(file: &Path, material_index: u32) -> anyhow::Result<Self> {
+        let (models, _) = tobj::load_obj(file, &tobj::GPU_LOAD_OPTIONS)
+            .with_context(|| format!("Unable to load {}", file.display()))?;
+
+        let mut mesh = Mesh::default();
+        for model in models {
+            let m = model.mesh;
+            let offset = mesh.vertices.len() as u32;
+            let vertex_count = m.positions.len() / 3;
+            mesh.vertices.extend((0..vertex_count).map(|i| {
+                let position = Vec3::from_slice(&m.positions[i * 3..i * 3 + 3]);
+                let normal = m
+                    .normals
+                    .get(i * 3..i * 3 + 3)
+                    .map(Vec3::from_slice)
+                    .unwrap_or(Vec3::ZERO);
+                // obj puts v = 0 at the bottom of the image
+                let tex_coords = m
+                    .texcoords
+                    .get(i * 2..i * 2 + 2)
+                    .map(|t| vec2(t[0], 1.0 - t[1]))
+                    .unwrap_or(Vec2::ZERO);
+                let mut vertex = MeshVertex::new(position, normal, tex_coords);
+                // sRGB like the colors of .ply files
+                if let Some(color) = m.vertex_color.get(i * 3..i * 3 + 3) {
+                    vertex.color = Vec3::from_slice(color).clamp(Vec3::ZERO, Vec3::ONE).to_array().map(srgb_to_linear).into();
+                }
+                vertex
+            }));
+
+            mesh.triangles.extend(m.indices.chunks_exact(3).map(|t| Triangle {
+                vertices: [t[0] + offset, t[1] + offset, t[2] + offset],
+                material_index,
+            }));
+        }
+
+        if mesh.triangles.is_empty() {
+            anyhow::bail!("{} has no triangles", file.display());
+        }
+        mesh.compute_tangents();
+        Ok(mesh)
+    }
+
+    /// Reads an ASCII or binary .stl file a triangle at a time. The triangles
+    /// don't share vertices and are shaded flat
+    pub fn load_stl(file: &Path, material_index: u32) -> anyhow::Result<Self> {
+        let mut reader = BufReader::new(File::open(file).with_context(|| format!("Unable to open {}", file.display()))?);
+        let triangles = stl_io::create_stl_reader(&mut reader).with_context(|| format!("Unable to read {}", file.display()))?;
+
+        let mut mesh = Mesh::default();
+        for triangle in triangles {
+            let triangle = triangle.with_context(|| format!("Unable to read {}", file.display()))?;
+            let offset = mesh.vertices.len() as u32;
+            mesh.vertices.extend(
+                triangle.vertices.iter().map(|v| MeshVertex::new(Vec3::from(v.0), Vec3::ZERO, Vec2::ZERO)),
+            );
+            mesh.triangles.push(Triangle {
+                vertices: [offset, offset + 1, offset + 2],
+                material_index,
+            });
+        }
+
+        if mesh.triangles.is_empty() {
+            anyhow::bail!("{} has no triangles", file.display());
+        }
+        Ok(mesh)
+    }
+
+    /// Scales the mesh and then moves it to `translation`
+    pub fn transform(&mut self, translation: Vec3, scale: f32) {
+        for vertex in &mut self.vertices {
+            vertex.position = vertex.position * scale + translation;
+        }
+    }
+
+    /// Adds the triangles of `other` after the ones of this mesh
+    pub fn append(&mut self, other: &Mesh) {
+        let offset = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&other.vertices);
+        self.triangles.extend(other.triangles.iter().map(|t| Triangle {
+            vertices: t.vertices.map(|v| v + offset),
+            material_index: t.material_index,
+        }));
+    }
+
+    /// Averages the tangents of the triangles around each vertex from how the
+    /// texture coordinates run across them
+    pub fn compute_tangents(&mut self) {
+        let mut tangents = vec![Vec3::ZERO; self.vertices.len()];
+        let mut bitangents = vec![Vec3::ZERO; self.vertices.len()];
+        for triangle in &self.triangles {
+            let [p0, p1, p2] = self.triangle_positions(triangle);
+            let [t0, t1, t2] = triangle.vertices.map(|v| self.vertices[v as usize].tex_coords());
+            let (edge1, edge2) = (p1 - p0, p2 - p0);
+            let (duv1, duv2) = (t1 - t0, t2 - t0);
+            let det = duv1.x * duv2.y - duv2.x * duv1.y;
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            let tangent = (edge1 * duv2.y - edge2 * duv1.y) / det;
+            // v grows down the image
+            let bitangent = (edge1 * duv2.x - edge2 * duv1.x) / det;
+            for v in triangle.vertices {
+                tangents[v as usize] += tangent;
+                bitangents[v as usize] += bitangent;
+            }
+        }
+
+        for ((vertex, tangent), bitangent) in self.vertices.iter_mut().zip(tangents).zip(bitangents) {
+            let normal = vertex.normal.normalize_or_zero();
+            let tangent = (tangent - normal * normal.dot(tangent)).normalize_or_zero();
+            let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 { 1.0 } else { -1.0 };
+            vertex.tangent = tangent.extend(handedness);
+        }
+    }
+
+    pub fn triangle_positions(&self, triangle: &Triangle) -> [Vec3; 3] {
+        triangle.vertices.map(|v| self.vertices[v as usize].position)
+    }
+}
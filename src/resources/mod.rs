@@ -1,7 +1,30 @@
 mod camera;
 mod objects;
 mod material;
+mod palette;
+mod presets;
+mod environment;
+mod sky;
+mod light;
+mod mesh;
+mod ply;
+mod bvh;
+mod textures;
+mod media;
+mod grid;
+mod instance;
 
-pub use camera::Camera;
+pub use camera::{Camera, Viewpoint};
 pub use objects::*;
 pub use material::*;
+pub use palette::*;
+pub use presets::*;
+pub use environment::Environment;
+pub use sky::Sky;
+pub use light::*;
+pub use mesh::*;
+pub use bvh::*;
+pub use textures::*;
+pub use media::*;
+pub use grid::*;
+pub use instance::*;
@@ -1,16 +1,29 @@
 use bytemuck::{Pod, Zeroable};
-use glam::{Vec3, Vec4, vec3, vec4, Vec3Swizzles};
+use glam::{Quat, Vec3, Vec4, vec2, vec3, vec4, Vec3Swizzles, Vec4Swizzles};
 use rand::Rng;
 
 use super::Material;
 
 pub struct ObjectData {
     pub spheres: Vec<Sphere>,
+    pub primitives: Vec<Primitive>,
 }
 
 impl ObjectData {
-    pub fn as_bytes<'a>(&'a self) -> &'a [u8] {
-        bytemuck::cast_slice(&self.spheres)
+    /// Bindings can't be empty so without spheres there is one too small to hit
+    pub fn as_bytes(&self) -> Vec<u8> {
+        if self.spheres.is_empty() {
+            return bytemuck::bytes_of(&Sphere::new(Vec3::ZERO, 0.0, 0)).to_vec();
+        }
+        bytemuck::cast_slice(&self.spheres).to_vec()
+    }
+
+    /// Same for the primitives, with a disk
+    pub fn primitives_as_bytes(&self) -> Vec<u8> {
+        if self.primitives.is_empty() {
+            return bytemuck::bytes_of(&Primitive::disk(Vec3::ZERO, Vec3::Y, 0.0, 0)).to_vec();
+        }
+        bytemuck::cast_slice(&self.primitives).to_vec()
     }
 }
 
@@ -24,19 +37,25 @@ pub struct Sphere {
 }
 
 impl Sphere {
-    pub fn random_bunch(amount: u32, materials_len: u32) -> Vec<Sphere> {
-        let from = vec3(-1.0, -1.0, -1.0) * (amount as f32).sqrt();
-        let to = vec3(1.0, 1.0, -2.0) * (amount as f32).sqrt();
+    /// Up to `amount` spheres resting on y = 0 that don't overlap, a place is
+    /// given up on after enough tries so crowded fields have fewer spheres
+    pub fn random_bunch(rng: &mut impl Rng, amount: u32, materials_len: u32) -> Vec<Sphere> {
+        const TRIES: u32 = 100;
+        let half_width = (amount as f32).sqrt();
 
-        let mut rng = rand::thread_rng();
-
-        (0..amount).into_iter().map(|_|
-            Sphere::new(
-                rng.gen::<Vec3>() * (to - from) + from,
-                1.0,
-                rng.gen_range(0..materials_len + 4)
-            )
-        ).collect()
+        let mut spheres: Vec<Sphere> = Vec::with_capacity(amount as usize);
+        for _ in 0..amount {
+            let radius = rng.gen_range(0.2..0.6);
+            let placed = (0..TRIES).map(|_| {
+                vec3(rng.gen_range(-half_width..half_width), radius, rng.gen_range(-half_width..half_width))
+            }).find(|&center| {
+                spheres.iter().all(|s| s.center.xyz().distance(center) > s.radius + radius)
+            });
+            if let Some(center) = placed {
+                spheres.push(Sphere::new(center, radius, rng.gen_range(0..materials_len)));
+            }
+        }
+        spheres
     }
 
     pub fn new(center: Vec3, radius: f32, material_index: u32) -> Self {
@@ -47,6 +66,139 @@ impl Sphere {
             padding: 0,
         }
     }
+
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        (self.center.xyz() - self.radius, self.center.xyz() + self.radius)
+    }
+}
+
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrimitiveKind {
+    /// Infinite, through the origin facing up its y axis
+    Plane = 0,
+    /// Parallelogram with a corner at the origin and the x and y axes as edges
+    Quad = 1,
+    /// Centered with `size` along its axes
+    Box = 2,
+    Disk = 3,
+    /// Centered along its y axis with caps at both ends
+    Cylinder = 4,
+    /// Base at the origin and the tip `size.y` up its y axis, with a cap at the base
+    Cone = 5,
+    /// Ring around its y axis
+    Torus = 6,
 }
 
+/// The shapes other than spheres, placed by an origin and local axes that
+/// the kernel intersects them in
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, Debug)]
+pub struct Primitive {
+    pub origin: Vec3,
+    pub kind: u32,
+    pub x_axis: Vec3,
+    pub material_index: u32,
+    pub y_axis: Vec3,
+    /// Disks, cylinders and cones, and the distance from the center to the tube for tori
+    pub radius: f32,
+    pub z_axis: Vec3,
+    /// The radius of the tube of a torus
+    pub minor_radius: f32,
+    /// Boxes use all of it, cylinders and cones only the height in y
+    pub size: Vec3,
+    pub padding: u32,
+}
+
+impl Primitive {
+    pub fn plane(point: Vec3, normal: Vec3, material_index: u32) -> Self {
+        Self::oriented(PrimitiveKind::Plane, point, Quat::from_rotation_arc(Vec3::Y, normal.normalize()), material_index)
+    }
+
+    pub fn quad(corner: Vec3, u: Vec3, v: Vec3, material_index: u32) -> Self {
+        Self {
+            x_axis: u,
+            y_axis: v,
+            z_axis: u.cross(v).normalize(),
+            ..Self::oriented(PrimitiveKind::Quad, corner, Quat::IDENTITY, material_index)
+        }
+    }
+
+    /// Axis aligned with the identity rotation
+    pub fn cuboid(center: Vec3, size: Vec3, rotation: Quat, material_index: u32) -> Self {
+        Self {
+            size,
+            ..Self::oriented(PrimitiveKind::Box, center, rotation, material_index)
+        }
+    }
+
+    pub fn disk(center: Vec3, normal: Vec3, radius: f32, material_index: u32) -> Self {
+        Self {
+            radius,
+            ..Self::oriented(PrimitiveKind::Disk, center, Quat::from_rotation_arc(Vec3::Y, normal.normalize()), material_index)
+        }
+    }
+
+    pub fn cylinder(center: Vec3, axis: Vec3, radius: f32, height: f32, material_index: u32) -> Self {
+        Self {
+            radius,
+            size: vec3(0.0, height, 0.0),
+            ..Self::oriented(PrimitiveKind::Cylinder, center, Quat::from_rotation_arc(Vec3::Y, axis.normalize()), material_index)
+        }
+    }
+
+    pub fn cone(base: Vec3, axis: Vec3, radius: f32, height: f32, material_index: u32) -> Self {
+        Self {
+            radius,
+            size: vec3(0.0, height, 0.0),
+            ..Self::oriented(PrimitiveKind::Cone, base, Quat::from_rotation_arc(Vec3::Y, axis.normalize()), material_index)
+        }
+    }
 
+    pub fn torus(center: Vec3, axis: Vec3, radius: f32, minor_radius: f32, material_index: u32) -> Self {
+        Self {
+            radius,
+            minor_radius,
+            ..Self::oriented(PrimitiveKind::Torus, center, Quat::from_rotation_arc(Vec3::Y, axis.normalize()), material_index)
+        }
+    }
+
+    /// Planes are cut off far away, the round shapes get the box around their bounding sphere
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        const FAR: f32 = 1e6;
+        let sphere_bounds = |center: Vec3, radius: f32| (center - radius, center + radius);
+        match self.kind {
+            k if k == PrimitiveKind::Plane as u32 => (Vec3::splat(-FAR), Vec3::splat(FAR)),
+            k if k == PrimitiveKind::Quad as u32 => {
+                let corners = [Vec3::ZERO, self.x_axis, self.y_axis, self.x_axis + self.y_axis].map(|c| self.origin + c);
+                (corners.into_iter().fold(Vec3::MAX, Vec3::min), corners.into_iter().fold(Vec3::MIN, Vec3::max))
+            }
+            k if k == PrimitiveKind::Box as u32 => {
+                let extent = (self.x_axis * self.size.x).abs() + (self.y_axis * self.size.y).abs() + (self.z_axis * self.size.z).abs();
+                (self.origin - extent * 0.5, self.origin + extent * 0.5)
+            }
+            k if k == PrimitiveKind::Disk as u32 => sphere_bounds(self.origin, self.radius),
+            k if k == PrimitiveKind::Cylinder as u32 => sphere_bounds(self.origin, vec2(self.radius, self.size.y * 0.5).length()),
+            k if k == PrimitiveKind::Cone as u32 => sphere_bounds(self.origin + self.y_axis * self.size.y * 0.5, vec2(self.radius, self.size.y * 0.5).length()),
+            k if k == PrimitiveKind::Torus as u32 => sphere_bounds(self.origin, self.radius + self.minor_radius),
+            // Anything unknown is never culled
+            _ => (Vec3::splat(-FAR), Vec3::splat(FAR)),
+        }
+    }
+
+    fn oriented(kind: PrimitiveKind, origin: Vec3, rotation: Quat, material_index: u32) -> Self {
+        Self {
+            origin,
+            kind: kind as u32,
+            x_axis: rotation * Vec3::X,
+            material_index,
+            y_axis: rotation * Vec3::Y,
+            radius: 0.0,
+            z_axis: rotation * Vec3::Z,
+            minor_radius: 0.0,
+            size: Vec3::ZERO,
+            padding: 0,
+        }
+    }
+}
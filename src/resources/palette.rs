@@ -0,0 +1,92 @@
+use glam::{vec3, Vec3};
+use rand::Rng;
+
+use super::Material;
+
+/// How the hues of a palette are spread around the OKLCH hue circle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// Neighbouring hues
+    Analogous,
+    /// Two groups of hues opposite each other
+    Complementary,
+    /// Three hues a third of the circle apart
+    Triadic,
+}
+
+impl Scheme {
+    pub const ALL: [Scheme; 3] = [Scheme::Analogous, Scheme::Complementary, Scheme::Triadic];
+
+    /// Turns away from the base hue in degrees
+    fn hue_offsets(self) -> &'static [f32] {
+        match self {
+            Scheme::Analogous => &[-30.0, -15.0, 0.0, 15.0, 30.0],
+            Scheme::Complementary => &[-15.0, 0.0, 15.0, 165.0, 180.0, 195.0],
+            Scheme::Triadic => &[0.0, 120.0, 240.0],
+        }
+    }
+}
+
+/// Linear colors at controlled lightness and chroma so they go together
+#[derive(Debug, Clone)]
+pub struct Palette {
+    pub scheme: Scheme,
+    pub colors: Vec<Vec3>,
+}
+
+impl Palette {
+    pub fn new<R: Rng + ?Sized>(scheme: Scheme, rng: &mut R) -> Self {
+        let base_hue: f32 = rng.gen_range(0.0..360.0);
+        let colors = scheme
+            .hue_offsets()
+            .iter()
+            .map(|offset| {
+                let hue = (base_hue + offset + rng.gen_range(-5.0..5.0)).to_radians();
+                oklch_to_linear(rng.gen_range(0.45..0.85), rng.gen_range(0.06..0.16), hue)
+            })
+            .collect();
+        Self { scheme, colors }
+    }
+
+    /// The `index`th material goes around the colors so every hue is used.
+    /// Most materials are dielectrics, metals are smoother than them
+    pub fn material<R: Rng + ?Sized>(&self, index: usize, rng: &mut R) -> Material {
+        let color = self.colors[index % self.colors.len()];
+        let (metallic, roughness) = if rng.gen_bool(0.25) {
+            (1.0, rng.gen::<f32>().powi(2) * 0.5)
+        } else {
+            (0.0, rng.gen_range(0.3..1.0))
+        };
+        Material {
+            color: color.extend(1.0),
+            metallic,
+            roughness,
+            specular: 0.5,
+            ..Default::default()
+        }
+    }
+}
+
+/// Same as `oklch_to_srgb` in color.wgsl, the chroma is lowered until the
+/// color fits in linear sRGB
+#[allow(clippy::excessive_precision)]
+pub fn oklch_to_linear(lightness: f32, chroma: f32, hue: f32) -> Vec3 {
+    let mut chroma = chroma;
+    loop {
+        let (a, b) = (chroma * hue.cos(), chroma * hue.sin());
+        let l = lightness + 0.3963377774 * a + 0.2158037573 * b;
+        let m = lightness - 0.1055613458 * a - 0.0638541728 * b;
+        let s = lightness - 0.0894841775 * a - 1.2914855480 * b;
+        let (l, m, s) = (l * l * l, m * m * m, s * s * s);
+        let color = vec3(
+            4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+            -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+            -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+        );
+        if chroma <= 0.0 || (color.min_element() >= 0.0 && color.max_element() <= 1.0) {
+            return color.clamp(Vec3::ZERO, Vec3::ONE);
+        }
+        chroma -= 0.01;
+    }
+}
+
@@ -0,0 +1,327 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use anyhow::Context;
+use glam::{vec2, Vec2, Vec3};
+
+use crate::common::srgb_to_linear;
+
+use super::{Mesh, MeshVertex, Triangle};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => anyhow::bail!("Unknown property type {}", name),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    /// Colors stored as integers go from zero to the largest value of the type
+    fn color_scale(self) -> f64 {
+        match self {
+            Self::U8 => 1.0 / 255.0,
+            Self::U16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PropertyKind {
+    Scalar(Scalar),
+    List { count: Scalar, item: Scalar },
+}
+
+/// Where a vertex property ends up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Position(usize),
+    Normal(usize),
+    TexCoord(usize),
+    Color(usize),
+    FaceIndices,
+    Ignored,
+}
+
+#[derive(Debug)]
+struct Property {
+    kind: PropertyKind,
+    slot: Slot,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Mesh {
+    /// Reads an ASCII or binary .ply file straight into the vertices and
+    /// triangles, polygons are split into fans. Vertex colors are taken as sRGB
+    pub fn load_ply(file: &Path, material_index: u32) -> anyhow::Result<Self> {
+        let reader = BufReader::new(File::open(file).with_context(|| format!("Unable to open {}", file.display()))?);
+        read_ply(reader, material_index).with_context(|| format!("Unable to load {}", file.display()))
+    }
+}
+
+fn read_ply(mut reader: impl BufRead, material_index: u32) -> anyhow::Result<Mesh> {
+    let (format, elements) = read_header(&mut reader)?;
+    let mut values = ValueReader { reader, format, line: String::new(), position: 0 };
+
+    let mut mesh = Mesh::default();
+    let mut has_tex_coords = false;
+    let mut face_indices = Vec::new();
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => {
+                let has = |f: fn(Slot) -> bool| element.properties.iter().any(|p| f(p.slot));
+                has_tex_coords = has(|s| matches!(s, Slot::TexCoord(_)));
+                let has_colors = has(|s| matches!(s, Slot::Color(_)));
+
+                // The count comes from the file so it isn't trusted with all of the memory up front
+                mesh.vertices.reserve(element.count.min(1 << 24));
+                for _ in 0..element.count {
+                    let (mut position, mut normal, mut tex_coords) = (Vec3::ZERO, Vec3::ZERO, Vec2::ZERO);
+                    let mut color = Vec3::ONE;
+                    for property in &element.properties {
+                        let PropertyKind::Scalar(scalar) = property.kind else {
+                            values.skip(property.kind)?;
+                            continue;
+                        };
+                        let value = values.read(scalar)?;
+                        match property.slot {
+                            Slot::Position(i) => position[i] = value as f32,
+                            Slot::Normal(i) => normal[i] = value as f32,
+                            Slot::TexCoord(i) => tex_coords[i] = value as f32,
+                            Slot::Color(i) => color[i] = (value * scalar.color_scale()) as f32,
+                            Slot::FaceIndices | Slot::Ignored => (),
+                        }
+                    }
+                    let mut vertex = MeshVertex::new(position, normal, vec2(tex_coords.x, 1.0 - tex_coords.y));
+                    if has_colors {
+                        vertex.color = color.clamp(Vec3::ZERO, Vec3::ONE).to_array().map(srgb_to_linear).into();
+                    }
+                    mesh.vertices.push(vertex);
+                }
+            }
+            "face" => {
+                mesh.triangles.reserve(element.count.min(1 << 24));
+                for face in 0..element.count {
+                    for property in &element.properties {
+                        match (property.kind, property.slot) {
+                            (PropertyKind::List { count, item }, Slot::FaceIndices) => {
+                                face_indices.clear();
+                                for _ in 0..values.read(count)? as usize {
+                                    let index = values.read(item)?;
+                                    if index < 0.0 || index.fract() != 0.0 || index > u32::MAX as f64 {
+                                        anyhow::bail!("Face {} has the index {} which isn't a whole number from 0 up", face, index);
+                                    }
+                                    face_indices.push(index as u32);
+                                }
+                                if let Some(&index) = face_indices.iter().find(|&&i| i as usize >= mesh.vertices.len()) {
+                                    anyhow::bail!("A face uses vertex {} of {}", index, mesh.vertices.len());
+                                }
+                                for i in 2..face_indices.len() {
+                                    mesh.triangles.push(Triangle {
+                                        vertices: [face_indices[0], face_indices[i - 1], face_indices[i]],
+                                        material_index,
+                                    });
+                                }
+                            }
+                            (kind, _) => values.skip(kind)?,
+                        }
+                    }
+                }
+            }
+            // Nothing after the faces is needed
+            _ if !mesh.triangles.is_empty() => break,
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        values.skip(property.kind)?;
+                    }
+                }
+            }
+        }
+    }
+
+    if mesh.triangles.is_empty() {
+        anyhow::bail!("There are no faces, point clouds can't be rendered");
+    }
+    if has_tex_coords {
+        mesh.compute_tangents();
+    }
+    Ok(mesh)
+}
+
+fn read_header(reader: &mut impl BufRead) -> anyhow::Result<(Format, Vec<Element>)> {
+    let mut line = String::new();
+    let mut next_line = |line: &mut String| -> anyhow::Result<()> {
+        line.clear();
+        if reader.read_line(line)? == 0 {
+            anyhow::bail!("The header ends before end_header");
+        }
+        Ok(())
+    };
+
+    next_line(&mut line)?;
+    if line.trim() != "ply" {
+        anyhow::bail!("Not a .ply file");
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        next_line(&mut line)?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["end_header"] => break,
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::LittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BigEndian),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().context("Element counts have to be whole numbers")?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let element = elements.last_mut().context("A property comes before any element")?;
+                let slot = match (element.name.as_str(), *name) {
+                    ("face", "vertex_indices" | "vertex_index") => Slot::FaceIndices,
+                    _ => Slot::Ignored,
+                };
+                let kind = PropertyKind::List { count: Scalar::parse(count)?, item: Scalar::parse(item)? };
+                element.properties.push(Property { kind, slot });
+            }
+            ["property", scalar, name] => {
+                let element = elements.last_mut().context("A property comes before any element")?;
+                let slot = if element.name == "vertex" { vertex_slot(name) } else { Slot::Ignored };
+                element.properties.push(Property { kind: PropertyKind::Scalar(Scalar::parse(scalar)?), slot });
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => (),
+            _ => anyhow::bail!("Unknown header line {}", line.trim()),
+        }
+    }
+
+    Ok((format.context("The header has no format")?, elements))
+}
+
+fn vertex_slot(name: &str) -> Slot {
+    match name {
+        "x" => Slot::Position(0),
+        "y" => Slot::Position(1),
+        "z" => Slot::Position(2),
+        "nx" => Slot::Normal(0),
+        "ny" => Slot::Normal(1),
+        "nz" => Slot::Normal(2),
+        "u" | "s" | "texture_u" | "texture_s" => Slot::TexCoord(0),
+        "v" | "t" | "texture_v" | "texture_t" => Slot::TexCoord(1),
+        "red" | "r" | "diffuse_red" => Slot::Color(0),
+        "green" | "g" | "diffuse_green" => Slot::Color(1),
+        "blue" | "b" | "diffuse_blue" => Slot::Color(2),
+        _ => Slot::Ignored,
+    }
+}
+
+/// Reads one value at a time in the format of the file, ASCII values are
+/// read a line at a time
+struct ValueReader<R> {
+    reader: R,
+    format: Format,
+    line: String,
+    /// How far into the line the values have been read
+    position: usize,
+}
+
+impl<R: BufRead> ValueReader<R> {
+    fn read(&mut self, scalar: Scalar) -> anyhow::Result<f64> {
+        if self.format == Format::Ascii {
+            loop {
+                let rest = &self.line[self.position..];
+                let start = rest.len() - rest.trim_start().len();
+                let rest = &rest[start..];
+                if !rest.is_empty() {
+                    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    self.position += start + end;
+                    let token = &rest[..end];
+                    return token.parse().with_context(|| format!("{} isn't a number", token));
+                }
+                self.line.clear();
+                self.position = 0;
+                if self.reader.read_line(&mut self.line)? == 0 {
+                    anyhow::bail!("The file ends early");
+                }
+            }
+        }
+
+        let mut bytes = [0; 8];
+        let bytes = &mut bytes[..scalar.size()];
+        self.reader.read_exact(bytes).context("The file ends early")?;
+        if self.format == Format::BigEndian {
+            bytes.reverse();
+        }
+        let bytes: &[u8] = bytes;
+        Ok(match scalar {
+            Scalar::I8 => bytes[0] as i8 as f64,
+            Scalar::U8 => bytes[0] as f64,
+            Scalar::I16 => i16::from_le_bytes(bytes.try_into()?) as f64,
+            Scalar::U16 => u16::from_le_bytes(bytes.try_into()?) as f64,
+            Scalar::I32 => i32::from_le_bytes(bytes.try_into()?) as f64,
+            Scalar::U32 => u32::from_le_bytes(bytes.try_into()?) as f64,
+            Scalar::F32 => f32::from_le_bytes(bytes.try_into()?) as f64,
+            Scalar::F64 => f64::from_le_bytes(bytes.try_into()?),
+        })
+    }
+
+    fn skip(&mut self, kind: PropertyKind) -> anyhow::Result<()> {
+        match kind {
+            PropertyKind::Scalar(scalar) => {
+                self.read(scalar)?;
+            }
+            PropertyKind::List { count, item } => {
+                for _ in 0..self.read(count)? as usize {
+                    self.read(item)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use glam::{vec3, Vec3};
+
+use super::Material;
+
+/// The names scene files can use for `preset`
+pub const PRESET_NAMES: &[&str] = &[
+    "gold", "silver", "copper", "aluminium", "iron", "chromium", "titanium", "platinum", "nickel", "brass",
+    "glass", "water", "ice", "diamond", "sapphire", "plastic", "rubber", "ceramic",
+];
+
+impl Material {
+    /// Common materials from measured optical constants. Metals use n and k
+    /// at 650, 550 and 450 nm for red, green and blue
+    pub fn preset(name: &str) -> Option<Material> {
+        let conductor = |eta: [f32; 3], k: [f32; 3], roughness| Material::conductor(Vec3::from(eta), Vec3::from(k), roughness);
+        Some(match name {
+            "gold" => conductor([0.143, 0.374, 1.442], [3.983, 2.385, 1.603], 0.05),
+            "silver" => conductor([0.155, 0.117, 0.138], [4.828, 3.122, 2.147], 0.05),
+            "copper" => conductor([0.200, 0.924, 1.102], [3.912, 2.452, 2.142], 0.05),
+            "aluminium" | "aluminum" => conductor([1.657, 0.880, 0.521], [9.224, 6.270, 4.837], 0.1),
+            "iron" => conductor([2.912, 2.950, 2.584], [3.077, 2.930, 2.767], 0.2),
+            "chromium" => conductor([3.196, 3.182, 2.223], [3.300, 3.330, 3.115], 0.05),
+            "titanium" => conductor([2.741, 2.542, 2.267], [3.814, 3.435, 3.039], 0.2),
+            "platinum" => conductor([2.376, 2.085, 1.845], [4.266, 3.715, 3.137], 0.05),
+            "nickel" => conductor([1.987, 1.809, 1.649], [3.744, 3.356, 2.920], 0.1),
+            "brass" => conductor([0.444, 0.527, 1.094], [3.695, 2.765, 1.829], 0.1),
+
+            // Polished rather than perfectly smooth so point lights show a highlight
+            "glass" => Material::dielectric(Vec3::ONE, 1.5, 0.05, 1.0),
+            "water" => Material::dielectric(Vec3::ONE, 1.333, 0.05, 1.0),
+            "ice" => Material::dielectric(vec3(0.95, 0.98, 1.0), 1.31, 0.05, 1.0),
+            "diamond" => Material::dielectric(Vec3::ONE, 2.418, 0.05, 1.0),
+            "sapphire" => Material::dielectric(vec3(0.8, 0.85, 1.0), 1.77, 0.05, 1.0),
+            "plastic" => Material::dielectric(Vec3::splat(0.8), 1.5, 0.3, 0.0),
+            "rubber" => Material::dielectric(Vec3::splat(0.05), 1.52, 0.8, 0.0),
+            "ceramic" => Material::dielectric(Vec3::splat(0.9), 1.5, 0.1, 0.0),
+            _ => return None,
+        })
+    }
+}
@@ -0,0 +1,252 @@
+use std::f32::consts::PI;
+
+use bytemuck::{bytes_of, Pod, Zeroable};
+use glam::{vec3, Vec3};
+use wgpu::{Buffer, BufferUsages};
+use winit::keyboard::KeyCode;
+
+use crate::{common::Time, render_env::RenderEnv};
+
+/// Brings the sky luminance from kcd/m^2 to the range of the old gradient
+const SKY_SCALE: f32 = 0.05;
+/// Irradiance of the sun before the atmosphere
+const SUN_IRRADIANCE: f32 = 3.0;
+/// About twice the real sun so the disk is easier to see
+const SUN_ANGULAR_RADIUS: f32 = 0.01;
+const MAX_SUN_ELEVATION: f32 = 70.0 * PI / 180.0;
+/// Hours of the day per unit of [`Time`] when sweeping
+const SWEEP_SPEED: f32 = 2.0;
+
+/// Preetham sky with a sun disk, the sun is also sampled as a directional
+/// light. When enabled it replaces the environment map
+pub struct Sky {
+    pub params_buffer: Buffer,
+    enabled: bool,
+    sweeping: bool,
+    /// Hour of the day from 0 to 24, the sun rises at 6 and sets at 18
+    hour: f32,
+    /// Above the horizon in radians
+    elevation: f32,
+    /// Around the up axis in radians, 0 is straight ahead
+    azimuth: f32,
+    turbidity: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct SkyRaw {
+    sun_direction: [f32; 3],
+    enabled: u32,
+    sun_irradiance: [f32; 3],
+    sun_cos_radius: f32,
+    /// Zenith Yxy divided by the Perez function at the zenith
+    zenith: [f32; 3],
+    /// Darkens the sky after sunset
+    fade: f32,
+    /// The A to E Perez coefficients for Y, x and y
+    perez: [[f32; 4]; 5],
+}
+
+impl Sky {
+    pub fn new(render_env: &RenderEnv, enabled: bool) -> Self {
+        let params_buffer = render_env.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sky buffer"),
+            size: std::mem::size_of::<SkyRaw>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut sky = Self {
+            params_buffer,
+            enabled,
+            sweeping: false,
+            hour: 0.0,
+            elevation: 0.0,
+            azimuth: 0.0,
+            turbidity: 2.5,
+        };
+        sky.set_time_of_day(render_env, 15.0);
+        sky
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        let (sin_e, cos_e) = self.elevation.sin_cos();
+        let (sin_a, cos_a) = self.azimuth.sin_cos();
+        vec3(cos_e * sin_a, sin_e, -cos_e * cos_a)
+    }
+
+    fn to_raw(&self) -> SkyRaw {
+        let t = self.turbidity;
+        // Preetham's fit breaks down below the horizon
+        let theta_s = (PI / 2.0 - self.elevation).min(PI / 2.0 - 0.01);
+        let coefficients = perez_coefficients(t);
+
+        let zenith = zenith_yxy(t, theta_s);
+        let at_zenith = |i: usize| perez(&coefficients, i, 0.0, theta_s);
+        let zenith = [
+            zenith[0] * SKY_SCALE / at_zenith(0),
+            zenith[1] / at_zenith(1),
+            zenith[2] / at_zenith(2),
+        ];
+
+        let mut perez = [[0.0; 4]; 5];
+        for (c, row) in perez.iter_mut().enumerate() {
+            for (i, value) in row.iter_mut().take(3).enumerate() {
+                *value = coefficients[i][c];
+            }
+        }
+
+        let sun_up = smoothstep(-0.01, 0.02, self.elevation);
+        SkyRaw {
+            sun_direction: self.sun_direction().to_array(),
+            enabled: self.enabled as u32,
+            sun_irradiance: (sun_transmittance(t, self.elevation) * SUN_IRRADIANCE * sun_up).to_array(),
+            sun_cos_radius: SUN_ANGULAR_RADIUS.cos(),
+            zenith,
+            fade: smoothstep(-0.1, 0.05, self.elevation).max(0.02),
+            perez,
+        }
+    }
+
+    fn update(&self, render_env: &RenderEnv) {
+        render_env.queue.write_buffer(&self.params_buffer, 0, bytes_of(&self.to_raw()));
+    }
+
+    pub fn set_sun(&mut self, render_env: &RenderEnv, elevation: f32, azimuth: f32) {
+        self.elevation = elevation.clamp(-PI / 2.0, PI / 2.0);
+        self.azimuth = azimuth.rem_euclid(2.0 * PI);
+        self.update(render_env);
+    }
+
+    /// Moves the sun along its path from sunrise on the right to sunset on the left
+    pub fn set_time_of_day(&mut self, render_env: &RenderEnv, hour: f32) {
+        self.hour = hour.rem_euclid(24.0);
+        let day = (self.hour - 6.0) / 12.0;
+        let elevation = MAX_SUN_ELEVATION * (PI * day).sin();
+        self.set_sun(render_env, elevation, PI / 2.0 + PI * day);
+    }
+
+    pub fn set_turbidity(&mut self, render_env: &RenderEnv, turbidity: f32) {
+        self.turbidity = turbidity.clamp(1.7, 10.0);
+        self.update(render_env);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns true when the sky changed and the render needs to restart
+    pub fn key_press(&mut self, render_env: &RenderEnv, key: KeyCode) -> bool {
+        match key {
+            KeyCode::KeyY => {
+                self.enabled = !self.enabled;
+                self.update(render_env);
+            }
+            KeyCode::KeyG => {
+                self.sweeping = !self.sweeping;
+                return false;
+            }
+            KeyCode::KeyV => self.set_turbidity(render_env, self.turbidity - 0.5),
+            KeyCode::KeyB => self.set_turbidity(render_env, self.turbidity + 0.5),
+            _ => return false,
+        }
+        println!("\nSky: {}", self);
+        true
+    }
+
+    /// Advances the time of day while sweeping, returns true when the sun moved
+    pub fn update_time(&mut self, render_env: &RenderEnv, time: &Time) -> bool {
+        if !self.sweeping || !self.enabled {
+            return false;
+        }
+
+        self.set_time_of_day(render_env, self.hour + time.delta() * SWEEP_SPEED);
+        true
+    }
+}
+
+impl std::fmt::Display for Sky {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = if self.enabled { "on" } else { "off" };
+        write!(
+            f,
+            "{} {:02}:{:02} turbidity {:.1}",
+            state,
+            self.hour as u32,
+            (self.hour.fract() * 60.0) as u32,
+            self.turbidity
+        )
+    }
+}
+
+/// The A to E coefficients of the Perez function for Y, x and y
+fn perez_coefficients(t: f32) -> [[f32; 5]; 3] {
+    [
+        [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ],
+        [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ],
+        [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ],
+    ]
+}
+
+/// theta is the angle from the zenith and gamma the angle to the sun
+fn perez(coefficients: &[[f32; 5]; 3], i: usize, theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = coefficients[i];
+    (1.0 + a * (b / theta.cos()).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+/// Luminance and chromaticity straight up for the sun at theta_s from the zenith
+fn zenith_yxy(t: f32, theta_s: f32) -> [f32; 3] {
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+    let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+    let theta = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+    let chromaticity = |rows: [[f32; 4]; 3]| {
+        let [t2, t1, t0] = rows.map(|row| row.iter().zip(theta).map(|(k, th)| k * th).sum::<f32>());
+        t * t * t2 + t * t1 + t0
+    };
+    let x = chromaticity([
+        [0.00166, -0.00375, 0.00209, 0.0],
+        [-0.02903, 0.06377, -0.03202, 0.00394],
+        [0.11693, -0.21196, 0.06052, 0.25886],
+    ]);
+    let y = chromaticity([
+        [0.00275, -0.00610, 0.00317, 0.0],
+        [-0.04214, 0.08970, -0.04153, 0.00516],
+        [0.15346, -0.26756, 0.06670, 0.26688],
+    ]);
+    [luminance.max(0.0), x, y]
+}
+
+/// How much sunlight makes it through the air, rayleigh scattering takes the
+/// blue out near the horizon and the haze dims everything
+fn sun_transmittance(t: f32, elevation: f32) -> Vec3 {
+    let zenith_degrees = 90.0 - elevation.max(0.0).to_degrees();
+    let air_mass = 1.0 / (zenith_degrees.to_radians().cos() + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+    let rayleigh = vec3(0.05, 0.1, 0.23);
+    let haze = vec3(0.8, 1.0, 1.3) * 0.03 * (t - 1.0);
+    let depth = (rayleigh + haze) * -air_mass;
+    vec3(depth.x.exp(), depth.y.exp(), depth.z.exp())
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
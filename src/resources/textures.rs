@@ -0,0 +1,139 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use glam::Vec3;
+use image::{imageops::FilterType, RgbaImage};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    AddressMode, Buffer, BufferUsages, FilterMode, Sampler, SamplerDescriptor, TextureFormat, TextureView,
+    TextureViewDescriptor, TextureViewDimension,
+};
+
+use crate::{common::upload_texture_array, render_env::RenderEnv};
+
+use super::NO_TEXTURE;
+
+/// The layers can't be bigger than this
+const MAX_TEXTURE_SIZE: u32 = 2048;
+
+/// The procedural kinds blend between two colors, or the sub textures in
+/// their place, by a pattern in world space
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureKind {
+    Image = 0,
+    /// 3D cells of size 1 / scale
+    Checker = 1,
+    /// Perlin noise with `octaves` layers of fBm
+    Noise = 2,
+    Turbulence = 3,
+    /// Stripes along z bent by turbulence
+    Marble = 4,
+    /// Distance to the closest random point
+    Worley = 5,
+    /// From `color_a` at y = -1 / scale to `color_b` at y = 1 / scale
+    Gradient = 6,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+pub struct MaterialTexture {
+    pub kind: u32,
+    /// Layer in the texture array for images
+    pub layer: u32,
+    /// Used instead of the colors when they aren't [`NO_TEXTURE`]
+    pub sub_textures: [u32; 2],
+    pub color_a: Vec3,
+    pub scale: f32,
+    pub color_b: Vec3,
+    pub octaves: u32,
+}
+
+impl MaterialTexture {
+    pub fn image(layer: u32) -> Self {
+        Self {
+            kind: TextureKind::Image as u32,
+            layer,
+            ..Self::procedural(TextureKind::Image, 1.0, Vec3::ZERO, Vec3::ONE)
+        }
+    }
+
+    pub fn procedural(kind: TextureKind, scale: f32, color_a: Vec3, color_b: Vec3) -> Self {
+        Self {
+            kind: kind as u32,
+            layer: 0,
+            sub_textures: [NO_TEXTURE; 2],
+            color_a,
+            scale,
+            color_b,
+            octaves: 4,
+        }
+    }
+}
+
+/// Every image used by the materials as layers of one texture array so
+/// the compute pass can pick them by index, and the table of textures the
+/// materials point to
+pub struct MaterialTextures {
+    texture: wgpu::Texture,
+    pub view: TextureView,
+    pub sampler: Sampler,
+    pub table_buffer: Buffer,
+}
+
+impl MaterialTextures {
+    /// The images are stretched to the size of the largest one. They are
+    /// uploaded as they are, the kernel decodes the sRGB color textures itself
+    pub fn new(render_env: &RenderEnv, images: &[RgbaImage], textures: &[MaterialTexture]) -> Self {
+        let width = images.iter().map(|i| i.width()).max().unwrap_or(1).min(MAX_TEXTURE_SIZE);
+        let height = images.iter().map(|i| i.height()).max().unwrap_or(1).min(MAX_TEXTURE_SIZE);
+
+        let mut layers: Vec<RgbaImage> = images
+            .iter()
+            .map(|image| {
+                if image.dimensions() == (width, height) {
+                    image.clone()
+                } else {
+                    image::imageops::resize(image, width, height, FilterType::Triangle)
+                }
+            })
+            .collect();
+        // Bindings can't be empty, and the GL backend sees a single layer as a
+        // plain 2D texture which the kernel can't sample as an array
+        if layers.is_empty() {
+            layers.push(RgbaImage::from_pixel(1, 1, image::Rgba([255; 4])));
+        }
+        if layers.len() == 1 {
+            layers.push(layers[0].clone());
+        }
+
+        let pixels: Vec<&[u8]> = layers.iter().map(|l| l.as_raw().as_slice()).collect();
+        let texture = upload_texture_array(render_env, width, height, TextureFormat::Rgba8Unorm, &pixels);
+        let view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let sampler = render_env.device.create_sampler(&SamplerDescriptor {
+            label: Some("Material texture sampler"),
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::Repeat,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        // Bindings can't be empty
+        let table = if textures.is_empty() { vec![MaterialTexture::image(0)] } else { textures.to_vec() };
+        let table_buffer = render_env.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Material texture table buffer"),
+            contents: cast_slice(&table),
+            usage: BufferUsages::STORAGE,
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            table_buffer,
+        }
+    }
+}
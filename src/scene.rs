@@ -0,0 +1,869 @@
+use std::{
+    collections::HashMap,
+    f32::consts::PI,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use glam::{vec3, EulerRot, Mat4, Quat, Vec3};
+use image::RgbaImage;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+
+use crate::resources::{
+    DensityGrid, Fog, GridVolume, InstanceObject, Light, Material, MaterialTexture, Mesh, Palette, Primitive, Scheme, Sphere,
+    TextureKind, Viewpoint, Volume, MAX_VOLUMES, NO_TEXTURE, PRESET_NAMES,
+};
+use crate::scene_graph::{NodeId, SceneGraph};
+use crate::generators::Generator;
+
+/// Everything that is in the world, either from a scene file or the default spheres
+pub struct Scene {
+    pub materials: Vec<Material>,
+    pub spheres: Vec<Sphere>,
+    /// Planes, quads, boxes, disks, cylinders, cones and tori
+    pub primitives: Vec<Primitive>,
+    /// Every mesh file once, in its own space
+    pub meshes: Vec<Mesh>,
+    /// Places every object, meshes and copies of objects are only drawn through it
+    pub graph: SceneGraph,
+    pub lights: Vec<Light>,
+    /// Image and procedural textures the materials refer to by index
+    pub textures: Vec<MaterialTexture>,
+    /// The images of the image textures by layer
+    pub images: Vec<RgbaImage>,
+    pub fog: Option<Fog>,
+    pub volumes: Vec<Volume>,
+    pub grid: Option<GridVolume>,
+    /// Where the camera starts, the scene can leave it where it is
+    pub camera: Option<Viewpoint>,
+    /// An environment map the scene asks for, the one given on the command line wins
+    pub environment: Option<PathBuf>,
+}
+
+impl Scene {
+    /// Three random materials on the spheres the project started with
+    pub fn default_scene() -> Self {
+        let spheres = vec![
+            Sphere::new(vec3(0.0, -100.5, 0.0), 100.0, 0),
+            Sphere::new(vec3(-1.0, 0.0, -1.0), 0.5, 1),
+            Sphere::new(vec3(1.0, 0.0, -1.0), 0.5, 2),
+            Sphere::new(vec3(0.0, 0.0, -1.0), 0.5, 0),
+        ];
+        let mut graph = SceneGraph::new();
+        for i in 0..spheres.len() {
+            graph.add(format!("sphere {}", i), Mat4::IDENTITY, Some(InstanceObject::Sphere(i)), None).unwrap();
+        }
+
+        Self {
+            materials: vec![Material::random_new(), Material::random_new(), Material::random_new()],
+            spheres,
+            primitives: Vec::new(),
+            meshes: Vec::new(),
+            graph,
+            lights: Vec::new(),
+            textures: Vec::new(),
+            images: Vec::new(),
+            fog: None,
+            volumes: Vec::new(),
+            grid: None,
+            camera: None,
+            environment: None,
+        }
+    }
+
+    /// Reads a TOML scene file, anything left out of it comes from the default
+    /// scene. Paths in the file are relative to the file. glTF files are read
+    /// by `load_gltf` and pbrt files by `load_pbrt`
+    pub fn load(file: &Path) -> anyhow::Result<Self> {
+        match file.extension().and_then(|e| e.to_str()) {
+            Some("gltf" | "glb") => return Self::load_gltf(file),
+            Some("pbrt") => return Self::load_pbrt(file),
+            _ => (),
+        }
+
+        let text = std::fs::read_to_string(file).with_context(|| format!("Unable to read {}", file.display()))?;
+        let scene_file: SceneFile = toml::from_str(&text).with_context(|| format!("Unable to parse {}", file.display()))?;
+        let directory = file.parent().unwrap_or(Path::new(""));
+
+        let has_objects = !(scene_file.materials.is_empty()
+            && scene_file.spheres.is_empty()
+            && scene_file.primitives.is_empty()
+            && scene_file.meshes.is_empty()
+            && scene_file.instances.is_empty()
+            && scene_file.nodes.is_empty());
+        if scene_file.generator.is_some() && has_objects {
+            anyhow::bail!("A generated scene can only add lights, fog and volumes");
+        }
+
+        let default = Self::default_scene();
+        let mut textures = TextureTable::new(directory);
+        let materials = if scene_file.materials.is_empty() {
+            default.materials
+        } else {
+            scene_file
+                .materials
+                .into_iter()
+                .map(|desc| desc.into_material(&mut textures))
+                .collect::<anyhow::Result<Vec<_>>>()?
+        };
+        let images = textures
+            .image_paths
+            .iter()
+            .map(|path| {
+                image::open(path)
+                    .map(|i| i.to_rgba8())
+                    .with_context(|| format!("Unable to load texture {}", path.display()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // What the instances can refer to with its placement, material and the point it turns around
+        let mut names = HashMap::new();
+        let mut add_name = |name: &str, object, transform, material, pivot: Vec3| {
+            names.insert(name.to_string(), (object, transform, material, pivot));
+        };
+        // Every object is a node, the ones without a name get one from their place in the file
+        let mut graph = SceneGraph::new();
+        let mut node_fields = Vec::new();
+        let mut add_node = |graph: &mut SceneGraph, id: NodeId, fields: NodeFields| {
+            graph.set_visible(id, fields.visible);
+            graph.set_tags(id, fields.tags);
+            node_fields.push((id, fields.parent));
+        };
+        for desc in scene_file.nodes {
+            let transform = match desc.transform {
+                Some(rows) => Mat4::from_cols_array_2d(&rows).transpose(),
+                None => transform_from(desc.position, desc.rotation, desc.scale),
+            };
+            let id = graph.add(desc.name, transform, None, None)?;
+            add_node(&mut graph, id, NodeFields { parent: desc.parent, visible: desc.visible, tags: desc.tags });
+        }
+
+        let mut mesh_paths = Vec::new();
+        let mut meshes = Vec::new();
+        for (i, desc) in scene_file.meshes.into_iter().enumerate() {
+            let path = directory.join(&desc.file);
+            let index = match mesh_paths.iter().position(|p| *p == path) {
+                Some(index) => index,
+                None => {
+                    meshes.push(Mesh::load(&path, desc.material)?);
+                    mesh_paths.push(path);
+                    meshes.len() - 1
+                }
+            };
+            let object = InstanceObject::Mesh(index);
+            let transform = transform_from(desc.position, desc.rotation, ScaleDesc::Uniform(desc.scale));
+            let name = desc.name.unwrap_or_else(|| format!("mesh {}", i));
+            add_name(&name, object, transform, Some(desc.material), desc.position.into());
+            let id = graph.add(name, transform, Some(object), Some(desc.material))?;
+            add_node(&mut graph, id, NodeFields { parent: desc.parent, visible: desc.visible, tags: desc.tags });
+        }
+
+        let mut primitives = Vec::new();
+        for desc in scene_file.primitives {
+            let primitive = Primitive::from(desc.shape);
+            let object = InstanceObject::Primitive(primitives.len());
+            let name = desc.name.unwrap_or_else(|| format!("primitive {}", primitives.len()));
+            add_name(&name, object, Mat4::IDENTITY, None, primitive.origin);
+            let id = graph.add(name, Mat4::IDENTITY, Some(object), None)?;
+            add_node(&mut graph, id, desc.node);
+            primitives.push(primitive);
+        }
+
+        let spheres = if scene_file.spheres.is_empty() && primitives.is_empty() && meshes.is_empty() {
+            for i in 0..default.spheres.len() {
+                graph.add(format!("sphere {}", i), Mat4::IDENTITY, Some(InstanceObject::Sphere(i)), None)?;
+            }
+            default.spheres
+        } else {
+            let mut spheres = Vec::new();
+            for desc in scene_file.spheres {
+                let center = Vec3::from(desc.center);
+                let object = InstanceObject::Sphere(spheres.len());
+                let name = desc.name.unwrap_or_else(|| format!("sphere {}", spheres.len()));
+                add_name(&name, object, Mat4::IDENTITY, None, center);
+                let id = graph.add(name, Mat4::IDENTITY, Some(object), None)?;
+                add_node(&mut graph, id, NodeFields { parent: desc.parent, visible: desc.visible, tags: desc.tags });
+                spheres.push(Sphere::new(center, desc.radius, desc.material));
+            }
+            spheres
+        };
+
+        // Copies are moved from where the object already is and turn around its position
+        for (i, desc) in scene_file.instances.into_iter().enumerate() {
+            let &(object, base, material, pivot) =
+                names.get(&desc.object).with_context(|| format!("No object is named {}", desc.object))?;
+            let transform = match desc.transform {
+                Some(rows) => Mat4::from_cols_array_2d(&rows).transpose(),
+                None => transform_from(desc.position, desc.rotation, desc.scale),
+            };
+            let transform = Mat4::from_translation(pivot) * transform * Mat4::from_translation(-pivot) * base;
+            let name = desc.name.unwrap_or_else(|| format!("instance {}", i));
+            let id = graph.add(name, transform, Some(object), desc.material.or(material))?;
+            add_node(&mut graph, id, NodeFields { parent: desc.parent, visible: desc.visible, tags: desc.tags });
+        }
+
+        // Parents can come after their children in the file
+        for (id, parent) in node_fields {
+            if let Some(parent) = parent {
+                let parent = graph.find(&parent).with_context(|| format!("No parent is named {}", parent))?;
+                graph.set_parent(id, Some(parent))?;
+            }
+        }
+
+        let used_materials = spheres
+            .iter()
+            .map(|s| s.material_index)
+            .chain(primitives.iter().map(|p| p.material_index))
+            .chain(meshes.iter().flat_map(|m| m.triangles.iter().map(|t| t.material_index)))
+            .chain(graph.nodes().filter_map(|(_, node)| node.material));
+        if let Some(index) = used_materials.into_iter().find(|&i| i as usize >= materials.len()) {
+            anyhow::bail!("Material {} is used but there are only {} materials", index, materials.len());
+        }
+        if scene_file.volumes.len() > MAX_VOLUMES {
+            anyhow::bail!("{} volumes but at most {} are supported", scene_file.volumes.len(), MAX_VOLUMES);
+        }
+
+        let grid = scene_file
+            .grid
+            .map(|desc| {
+                anyhow::Ok(GridVolume {
+                    grid: DensityGrid::load(&directory.join(&desc.file), desc.resolution)?,
+                    center: desc.center.into(),
+                    size: desc.size.into(),
+                    density: desc.density,
+                    color: desc.color.into(),
+                    anisotropy: desc.anisotropy,
+                })
+            })
+            .transpose()?;
+
+        let lights: Vec<Light> = scene_file.lights.into_iter().map(Light::from).collect();
+        let fog = scene_file.fog.map(Fog::from);
+        let volumes = scene_file.volumes.into_iter().map(Volume::from).collect();
+        if let Some(desc) = scene_file.generator {
+            let mut scene = Self::generate(desc.name, desc.seed);
+            scene.lights.extend(lights);
+            scene.fog = fog;
+            scene.volumes = volumes;
+            scene.grid = grid;
+            return Ok(scene);
+        }
+
+        Ok(Self {
+            materials,
+            spheres,
+            primitives,
+            meshes,
+            graph,
+            lights,
+            textures: textures.textures,
+            images,
+            fog,
+            volumes,
+            grid,
+            camera: None,
+            environment: None,
+        })
+    }
+
+    /// What the R key does, new colors from one palette and new metallic and
+    /// roughness. Textures and emission stay
+    pub fn randomize_materials(&mut self, scheme: Scheme, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let palette = Palette::new(scheme, &mut rng);
+        for (i, material) in self.materials.iter_mut().enumerate() {
+            let new = palette.material(i, &mut rng);
+            material.color = new.color;
+            material.metallic = new.metallic;
+            material.roughness = new.roughness;
+            material.specular = new.specular;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    materials: Vec<MaterialDesc>,
+    #[serde(default)]
+    spheres: Vec<SphereDesc>,
+    #[serde(default)]
+    primitives: Vec<NamedPrimitiveDesc>,
+    #[serde(default)]
+    instances: Vec<InstanceDesc>,
+    #[serde(default)]
+    nodes: Vec<NodeDesc>,
+    #[serde(default)]
+    meshes: Vec<MeshDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+    fog: Option<FogDesc>,
+    #[serde(default)]
+    volumes: Vec<VolumeDesc>,
+    grid: Option<GridDesc>,
+    generator: Option<GeneratorDesc>,
+}
+
+/// Builds the objects and materials from code, the file can still add lights and media
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GeneratorDesc {
+    name: Generator,
+    /// A random seed is printed when there is none
+    seed: Option<u64>,
+}
+
+/// Either everything by hand or a named preset like `preset = "gold"`, the
+/// values given next to a preset replace its own
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    preset: Option<String>,
+    color: Option<[f32; 3]>,
+    metallic: Option<f32>,
+    roughness: Option<f32>,
+    specular: Option<f32>,
+    ior: Option<f32>,
+    transmission: Option<f32>,
+    specular_tint: Option<f32>,
+    anisotropy: Option<f32>,
+    anisotropy_rotation: Option<f32>,
+    sheen: Option<f32>,
+    sheen_tint: Option<f32>,
+    clearcoat: Option<f32>,
+    clearcoat_roughness: Option<f32>,
+    #[serde(default)]
+    emission: [f32; 3],
+    albedo_texture: Option<TextureDesc>,
+    /// Roughness in green and metallic in blue like glTF
+    roughness_metallic_texture: Option<TextureDesc>,
+    emission_texture: Option<TextureDesc>,
+    normal_texture: Option<TextureDesc>,
+    bump_texture: Option<TextureDesc>,
+    #[serde(default = "one")]
+    normal_strength: f32,
+}
+
+impl MaterialDesc {
+    fn into_material(self, textures: &mut TextureTable) -> anyhow::Result<Material> {
+        let mut texture_index = |desc: Option<TextureDesc>| match desc {
+            Some(desc) => textures.add(desc, true),
+            None => Ok(NO_TEXTURE),
+        };
+
+        let mut material = match &self.preset {
+            Some(name) => Material::preset(name).with_context(|| {
+                format!("There is no material preset named {}, there are {}", name, PRESET_NAMES.join(", "))
+            })?,
+            None => Material {
+                color: Vec3::from(self.color.context("Materials need a color or a preset")?).extend(1.0),
+                ..Default::default()
+            },
+        };
+        if let Some(color) = self.color {
+            material.color = Vec3::from(color).extend(1.0);
+        }
+        material.metallic = self.metallic.unwrap_or(material.metallic);
+        material.roughness = self.roughness.unwrap_or(material.roughness);
+        material.specular = self.specular.unwrap_or(material.specular);
+        material.ior = self.ior.unwrap_or(material.ior);
+        material.transmission = self.transmission.unwrap_or(material.transmission);
+        material.specular_tint = self.specular_tint.unwrap_or(material.specular_tint);
+        material.anisotropy = self.anisotropy.unwrap_or(material.anisotropy);
+        material.anisotropy_rotation = self.anisotropy_rotation.unwrap_or(material.anisotropy_rotation);
+        material.sheen = self.sheen.unwrap_or(material.sheen);
+        material.sheen_tint = self.sheen_tint.unwrap_or(material.sheen_tint);
+        material.clearcoat = self.clearcoat.unwrap_or(material.clearcoat);
+        material.clearcoat_roughness = self.clearcoat_roughness.unwrap_or(material.clearcoat_roughness);
+
+        Ok(Material {
+            emission: Vec3::from(self.emission).extend(0.0),
+            albedo_texture: texture_index(self.albedo_texture)?,
+            roughness_metallic_texture: texture_index(self.roughness_metallic_texture)?,
+            emission_texture: texture_index(self.emission_texture)?,
+            normal_texture: texture_index(self.normal_texture)?,
+            bump_texture: texture_index(self.bump_texture)?,
+            normal_strength: self.normal_strength,
+            ..material
+        })
+    }
+}
+
+/// Either a path to an image or a procedural texture like
+/// `{ kind = "checker", scale = 4.0, a = [0, 0, 0], b = "image.png" }`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureDesc {
+    Image(PathBuf),
+    Procedural(ProceduralDesc),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProceduralDesc {
+    kind: ProceduralKind,
+    #[serde(default = "one")]
+    scale: f32,
+    #[serde(default = "black_input")]
+    a: TextureInput,
+    #[serde(default = "white_input")]
+    b: TextureInput,
+    #[serde(default = "octaves")]
+    octaves: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ProceduralKind {
+    Checker,
+    Noise,
+    Turbulence,
+    Marble,
+    Worley,
+    Gradient,
+}
+
+impl From<ProceduralKind> for TextureKind {
+    fn from(kind: ProceduralKind) -> Self {
+        match kind {
+            ProceduralKind::Checker => TextureKind::Checker,
+            ProceduralKind::Noise => TextureKind::Noise,
+            ProceduralKind::Turbulence => TextureKind::Turbulence,
+            ProceduralKind::Marble => TextureKind::Marble,
+            ProceduralKind::Worley => TextureKind::Worley,
+            ProceduralKind::Gradient => TextureKind::Gradient,
+        }
+    }
+}
+
+/// What a procedural texture blends between
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureInput {
+    Color([f32; 3]),
+    Texture(Box<TextureDesc>),
+}
+
+/// Collects the textures of the materials, images used more than once are only loaded once
+struct TextureTable<'a> {
+    directory: &'a Path,
+    textures: Vec<MaterialTexture>,
+    image_paths: Vec<PathBuf>,
+}
+
+impl<'a> TextureTable<'a> {
+    fn new(directory: &'a Path) -> Self {
+        Self {
+            directory,
+            textures: Vec::new(),
+            image_paths: Vec::new(),
+        }
+    }
+
+    /// The kernel only looks one level deep so sub textures can't have their own
+    fn add(&mut self, desc: TextureDesc, allow_sub_textures: bool) -> anyhow::Result<u32> {
+        let texture = match desc {
+            TextureDesc::Image(path) => {
+                let path = self.directory.join(path);
+                let layer = self.image_paths.iter().position(|p| *p == path).unwrap_or_else(|| {
+                    self.image_paths.push(path);
+                    self.image_paths.len() - 1
+                });
+                MaterialTexture::image(layer as u32)
+            }
+            TextureDesc::Procedural(desc) => {
+                let mut texture = MaterialTexture::procedural(desc.kind.into(), desc.scale, Vec3::ZERO, Vec3::ONE);
+                texture.octaves = desc.octaves.clamp(1, 8);
+                for (i, input) in [desc.a, desc.b].into_iter().enumerate() {
+                    match input {
+                        TextureInput::Color(color) if i == 0 => texture.color_a = color.into(),
+                        TextureInput::Color(color) => texture.color_b = color.into(),
+                        TextureInput::Texture(_) if !allow_sub_textures => {
+                            anyhow::bail!("Sub textures can't have sub textures of their own")
+                        }
+                        TextureInput::Texture(sub) => texture.sub_textures[i] = self.add(*sub, false)?,
+                    }
+                }
+                texture
+            }
+        };
+
+        self.textures.push(texture);
+        Ok(self.textures.len() as u32 - 1)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    /// For instances to refer to
+    name: Option<String>,
+    center: [f32; 3],
+    radius: f32,
+    #[serde(default)]
+    material: u32,
+    parent: Option<String>,
+    #[serde(default = "yes")]
+    visible: bool,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// A Wavefront .obj, .ply or .stl file scaled, rotated and then moved to `position`.
+/// Meshes from the same file share their triangles
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    name: Option<String>,
+    file: PathBuf,
+    #[serde(default)]
+    material: u32,
+    #[serde(default)]
+    position: [f32; 3],
+    /// Euler angles around x, y and z in degrees
+    #[serde(default)]
+    rotation: [f32; 3],
+    #[serde(default = "one")]
+    scale: f32,
+    parent: Option<String>,
+    #[serde(default = "yes")]
+    visible: bool,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// A copy of the sphere, primitive or mesh called `object`, moved from it by
+/// a row major `transform` or by `position`, `rotation` and `scale`. It turns
+/// and scales around the center of spheres, the position of meshes and the
+/// origin of primitives
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InstanceDesc {
+    name: Option<String>,
+    object: String,
+    transform: Option<[[f32; 4]; 4]>,
+    #[serde(default)]
+    position: [f32; 3],
+    #[serde(default)]
+    rotation: [f32; 3],
+    #[serde(default = "uniform_scale")]
+    scale: ScaleDesc,
+    /// Replaces the materials of the object
+    material: Option<u32>,
+    parent: Option<String>,
+    #[serde(default = "yes")]
+    visible: bool,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// A group that moves the nodes under it, objects are put under it with
+/// their `parent` and are then placed relative to it
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NodeDesc {
+    name: String,
+    parent: Option<String>,
+    transform: Option<[[f32; 4]; 4]>,
+    #[serde(default)]
+    position: [f32; 3],
+    #[serde(default)]
+    rotation: [f32; 3],
+    #[serde(default = "uniform_scale")]
+    scale: ScaleDesc,
+    #[serde(default = "yes")]
+    visible: bool,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// Where an object sits in the scene graph
+#[derive(Deserialize)]
+struct NodeFields {
+    parent: Option<String>,
+    #[serde(default = "yes")]
+    visible: bool,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f32),
+    PerAxis([f32; 3]),
+}
+
+fn transform_from(position: [f32; 3], rotation: [f32; 3], scale: ScaleDesc) -> Mat4 {
+    let scale = match scale {
+        ScaleDesc::Uniform(scale) => Vec3::splat(scale),
+        ScaleDesc::PerAxis(scale) => scale.into(),
+    };
+    let [x, y, z] = rotation.map(f32::to_radians);
+    Mat4::from_scale_rotation_translation(scale, Quat::from_euler(EulerRot::XYZ, x, y, z), position.into())
+}
+
+#[derive(Deserialize)]
+struct NamedPrimitiveDesc {
+    name: Option<String>,
+    #[serde(flatten)]
+    node: NodeFields,
+    #[serde(flatten)]
+    shape: PrimitiveDesc,
+}
+
+/// Spot angles are the half angles of the cones in degrees
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+enum LightDesc {
+    Point {
+        position: [f32; 3],
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default = "one")]
+        intensity: f32,
+    },
+    Spot {
+        position: [f32; 3],
+        direction: [f32; 3],
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default = "one")]
+        intensity: f32,
+        #[serde(default = "inner_angle")]
+        inner_angle: f32,
+        #[serde(default = "outer_angle")]
+        outer_angle: f32,
+    },
+    Directional {
+        direction: [f32; 3],
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default = "one")]
+        intensity: f32,
+    },
+}
+
+impl From<LightDesc> for Light {
+    fn from(desc: LightDesc) -> Self {
+        match desc {
+            LightDesc::Point { position, color, intensity } => {
+                Light::point(position.into(), color.into(), intensity)
+            }
+            LightDesc::Spot { position, direction, color, intensity, inner_angle, outer_angle } => Light::spot(
+                position.into(),
+                direction.into(),
+                color.into(),
+                intensity,
+                inner_angle * PI / 180.0,
+                outer_angle * PI / 180.0,
+            ),
+            LightDesc::Directional { direction, color, intensity } => {
+                Light::directional(direction.into(), color.into(), intensity)
+            }
+        }
+    }
+}
+
+/// Like the spheres every primitive has a `material`, angles are in degrees
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+enum PrimitiveDesc {
+    Plane {
+        point: [f32; 3],
+        #[serde(default = "up")]
+        normal: [f32; 3],
+        material: u32,
+    },
+    Quad {
+        corner: [f32; 3],
+        u: [f32; 3],
+        v: [f32; 3],
+        material: u32,
+    },
+    Box {
+        center: [f32; 3],
+        size: [f32; 3],
+        /// Euler angles around x, y and z
+        #[serde(default)]
+        rotation: [f32; 3],
+        material: u32,
+    },
+    Disk {
+        center: [f32; 3],
+        #[serde(default = "up")]
+        normal: [f32; 3],
+        radius: f32,
+        material: u32,
+    },
+    Cylinder {
+        center: [f32; 3],
+        #[serde(default = "up")]
+        axis: [f32; 3],
+        radius: f32,
+        height: f32,
+        material: u32,
+    },
+    Cone {
+        /// The center of the base
+        base: [f32; 3],
+        #[serde(default = "up")]
+        axis: [f32; 3],
+        radius: f32,
+        height: f32,
+        material: u32,
+    },
+    Torus {
+        center: [f32; 3],
+        #[serde(default = "up")]
+        axis: [f32; 3],
+        radius: f32,
+        minor_radius: f32,
+        material: u32,
+    },
+}
+
+impl From<PrimitiveDesc> for Primitive {
+    fn from(desc: PrimitiveDesc) -> Self {
+        match desc {
+            PrimitiveDesc::Plane { point, normal, material } => Primitive::plane(point.into(), normal.into(), material),
+            PrimitiveDesc::Quad { corner, u, v, material } => Primitive::quad(corner.into(), u.into(), v.into(), material),
+            PrimitiveDesc::Box { center, size, rotation, material } => {
+                let [x, y, z] = rotation.map(f32::to_radians);
+                Primitive::cuboid(center.into(), size.into(), Quat::from_euler(EulerRot::XYZ, x, y, z), material)
+            }
+            PrimitiveDesc::Disk { center, normal, radius, material } => {
+                Primitive::disk(center.into(), normal.into(), radius, material)
+            }
+            PrimitiveDesc::Cylinder { center, axis, radius, height, material } => {
+                Primitive::cylinder(center.into(), axis.into(), radius, height, material)
+            }
+            PrimitiveDesc::Cone { base, axis, radius, height, material } => {
+                Primitive::cone(base.into(), axis.into(), radius, height, material)
+            }
+            PrimitiveDesc::Torus { center, axis, radius, minor_radius, material } => {
+                Primitive::torus(center.into(), axis.into(), radius, minor_radius, material)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FogDesc {
+    density: f32,
+    #[serde(default = "white")]
+    color: [f32; 3],
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    falloff: f32,
+    #[serde(default)]
+    anisotropy: f32,
+}
+
+impl From<FogDesc> for Fog {
+    fn from(desc: FogDesc) -> Self {
+        Fog {
+            color: desc.color.into(),
+            density: desc.density,
+            height: desc.height,
+            falloff: desc.falloff,
+            anisotropy: desc.anisotropy,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+enum VolumeDesc {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        density: f32,
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default)]
+        anisotropy: f32,
+    },
+    Box {
+        center: [f32; 3],
+        size: [f32; 3],
+        density: f32,
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default)]
+        anisotropy: f32,
+    },
+}
+
+impl From<VolumeDesc> for Volume {
+    fn from(desc: VolumeDesc) -> Self {
+        match desc {
+            VolumeDesc::Sphere { center, radius, density, color, anisotropy } => {
+                Volume::sphere(center.into(), radius, density, color.into(), anisotropy)
+            }
+            VolumeDesc::Box { center, size, density, color, anisotropy } => {
+                Volume::cuboid(center.into(), size.into(), density, color.into(), anisotropy)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GridDesc {
+    file: PathBuf,
+    /// Only needed for raw files
+    resolution: Option<[u32; 3]>,
+    center: [f32; 3],
+    size: [f32; 3],
+    #[serde(default = "one")]
+    density: f32,
+    #[serde(default = "white")]
+    color: [f32; 3],
+    #[serde(default)]
+    anisotropy: f32,
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn uniform_scale() -> ScaleDesc {
+    ScaleDesc::Uniform(1.0)
+}
+
+fn up() -> [f32; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn white() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn inner_angle() -> f32 {
+    20.0
+}
+
+fn outer_angle() -> f32 {
+    30.0
+}
+
+fn octaves() -> u32 {
+    4
+}
+
+fn black_input() -> TextureInput {
+    TextureInput::Color([0.0; 3])
+}
+
+fn white_input() -> TextureInput {
+    TextureInput::Color([1.0; 3])
+}
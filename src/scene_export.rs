@@ -0,0 +1,267 @@
+use std::{
+    f32::consts::{PI, TAU},
+    fmt::Write as _,
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use glam::{Mat3, Mat4, Vec3, Vec3Swizzles};
+
+use crate::{
+    common::linear_to_srgb,
+    resources::{InstanceObject, Primitive, PrimitiveKind, Sphere},
+    scene::Scene,
+    screenshot::{timestamp, SCREENSHOT_DIR},
+};
+
+/// How many pieces make up a full turn of the round shapes
+const SEGMENTS: u32 = 48;
+/// Planes are infinite so they are written as a square this wide
+const PLANE_SIZE: f32 = 100.0;
+
+/// Writes every visible object of the scene where the scene graph places it
+/// as a Wavefront .obj with the material colors in a .mtl next to it.
+/// Spheres and primitives are cut into triangles
+pub fn save_obj(scene: &Scene) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(SCREENSHOT_DIR)?;
+    let name = format!("scene_{}", timestamp());
+    let path = PathBuf::from(SCREENSHOT_DIR).join(format!("{}.obj", name));
+
+    let mut obj = String::new();
+    writeln!(obj, "mtllib {}.mtl", name)?;
+    // OBJ indices count from one across the whole file
+    let mut vertex_count = 1;
+    let mut normal_count = 1;
+    for (i, instance) in scene.graph.flatten().iter().enumerate() {
+        let (kind, surface) = match instance.object {
+            InstanceObject::Sphere(index) => ("sphere", Surface::sphere(&scene.spheres[index])),
+            InstanceObject::Primitive(index) => ("primitive", Surface::primitive(&scene.primitives[index])),
+            InstanceObject::Mesh(index) => ("mesh", Surface::mesh(scene, index)),
+        };
+        writeln!(obj, "o {} {}", kind, i)?;
+
+        let normal_transform = Mat3::from_mat4(instance.transform).inverse().transpose();
+        for vertex in &surface.vertices {
+            let p = instance.transform.transform_point3(vertex.position);
+            match vertex.color {
+                Some(c) => {
+                    let [r, g, b] = c.to_array().map(linear_to_srgb);
+                    writeln!(obj, "v {} {} {} {} {} {}", p.x, p.y, p.z, r, g, b)?
+                }
+                None => writeln!(obj, "v {} {} {}", p.x, p.y, p.z)?,
+            }
+        }
+        // Vertices without a normal are shaded flat like in the renderer
+        let mut normals = Vec::with_capacity(surface.vertices.len());
+        for vertex in &surface.vertices {
+            let normal = (normal_transform * vertex.normal).normalize_or_zero();
+            if normal == Vec3::ZERO {
+                normals.push(None);
+                continue;
+            }
+            writeln!(obj, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+            normals.push(Some(normal_count));
+            normal_count += 1;
+        }
+
+        let mut material = None;
+        for triangle in &surface.triangles {
+            let material_index = instance.material.unwrap_or(triangle.material);
+            if material != Some(material_index) {
+                writeln!(obj, "usemtl material_{}", material_index)?;
+                material = Some(material_index);
+            }
+            let corners = triangle.vertices.map(|v| (vertex_count + v, normals[v as usize]));
+            match corners {
+                [(a, Some(na)), (b, Some(nb)), (c, Some(nc))] => writeln!(obj, "f {}//{} {}//{} {}//{}", a, na, b, nb, c, nc)?,
+                [(a, _), (b, _), (c, _)] => writeln!(obj, "f {} {} {}", a, b, c)?,
+            }
+        }
+        vertex_count += surface.vertices.len() as u32;
+    }
+    std::fs::write(&path, obj).with_context(|| format!("Unable to write {}", path.display()))?;
+
+    let mut mtl = String::new();
+    for (i, material) in scene.materials.iter().enumerate() {
+        let specular = Vec3::splat(0.04).lerp(material.color.truncate(), material.metallic);
+        writeln!(mtl, "newmtl material_{}", i)?;
+        writeln!(mtl, "Kd {} {} {}", material.color.x, material.color.y, material.color.z)?;
+        writeln!(mtl, "Ks {} {} {}", specular.x, specular.y, specular.z)?;
+        writeln!(mtl, "Ke {} {} {}", material.emission.x, material.emission.y, material.emission.z)?;
+        // Rough surfaces have wide highlights
+        writeln!(mtl, "Ns {}", (1.0 - material.roughness).powi(2) * 1000.0)?;
+        // The PBR extension most tools read
+        writeln!(mtl, "Pr {}", material.roughness)?;
+        writeln!(mtl, "Pm {}", material.metallic)?;
+        writeln!(mtl, "Ni {}", material.ior)?;
+        writeln!(mtl, "d {}\n", material.color.w * (1.0 - material.transmission))?;
+    }
+    let mtl_path = path.with_extension("mtl");
+    std::fs::write(&mtl_path, mtl).with_context(|| format!("Unable to write {}", mtl_path.display()))?;
+
+    Ok(path)
+}
+
+struct Vertex {
+    position: Vec3,
+    /// Zero for flat shading
+    normal: Vec3,
+    color: Option<Vec3>,
+}
+
+struct SurfaceTriangle {
+    vertices: [u32; 3],
+    material: u32,
+}
+
+/// Triangles of one object in its own space
+#[derive(Default)]
+struct Surface {
+    vertices: Vec<Vertex>,
+    triangles: Vec<SurfaceTriangle>,
+}
+
+impl Surface {
+    fn sphere(sphere: &Sphere) -> Self {
+        let mut surface = Self::default();
+        let center = sphere.center.truncate();
+        surface.grid(SEGMENTS, SEGMENTS / 2, sphere.material_index, |u, v| {
+            let (theta, phi) = (u * TAU, v * PI);
+            let normal = Vec3::new(phi.sin() * theta.cos(), phi.cos(), -phi.sin() * theta.sin());
+            (center + normal * sphere.radius, normal)
+        });
+        surface
+    }
+
+    /// Made in the local axes of the primitive and then moved into place
+    fn primitive(primitive: &Primitive) -> Self {
+        let mut surface = Self::default();
+        let material = primitive.material_index;
+        let size = primitive.size;
+        let (radius, height) = (primitive.radius, size.y);
+        let circle = |angle: f32| Vec3::new(angle.cos(), 0.0, -angle.sin());
+        match primitive.kind {
+            k if k == PrimitiveKind::Plane as u32 => {
+                let h = PLANE_SIZE * 0.5;
+                surface.quad(Vec3::new(-h, 0.0, h), Vec3::X * PLANE_SIZE, Vec3::NEG_Z * PLANE_SIZE, material);
+            }
+            k if k == PrimitiveKind::Quad as u32 => surface.quad(Vec3::ZERO, Vec3::X, Vec3::Y, material),
+            k if k == PrimitiveKind::Box as u32 => {
+                let h = size * 0.5;
+                for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+                    for sign in [1.0, -1.0] {
+                        let normal = axis * sign;
+                        let u = axis.yzx() * sign;
+                        let v = normal.cross(u);
+                        let (u, v) = (u * size, v * size);
+                        surface.quad(normal * h - (u + v) * 0.5, u, v, material);
+                    }
+                }
+            }
+            k if k == PrimitiveKind::Disk as u32 => surface.disk(Vec3::ZERO, radius, Vec3::Y, material),
+            k if k == PrimitiveKind::Cylinder as u32 => {
+                surface.grid(SEGMENTS, 1, material, |u, v| {
+                    let normal = circle(u * TAU);
+                    (normal * radius + Vec3::Y * height * (0.5 - v), normal)
+                });
+                surface.disk(Vec3::Y * height * 0.5, radius, Vec3::Y, material);
+                surface.disk(Vec3::NEG_Y * height * 0.5, radius, Vec3::NEG_Y, material);
+            }
+            k if k == PrimitiveKind::Cone as u32 => {
+                let slope = radius / height.max(f32::EPSILON);
+                surface.grid(SEGMENTS, 1, material, |u, v| {
+                    let side = circle(u * TAU);
+                    let normal = (side + Vec3::Y * slope).normalize();
+                    (side * radius * v + Vec3::Y * height * (1.0 - v), normal)
+                });
+                surface.disk(Vec3::ZERO, radius, Vec3::NEG_Y, material);
+            }
+            _ => {
+                let minor_radius = primitive.minor_radius;
+                surface.grid(SEGMENTS, SEGMENTS / 2, material, |u, v| {
+                    let around = circle(u * TAU);
+                    let (sin, cos) = (v * TAU).sin_cos();
+                    let normal = around * cos - Vec3::Y * sin;
+                    (around * radius + normal * minor_radius, normal)
+                });
+            }
+        }
+
+        let to_world = Mat4::from_cols(
+            primitive.x_axis.extend(0.0),
+            primitive.y_axis.extend(0.0),
+            primitive.z_axis.extend(0.0),
+            primitive.origin.extend(1.0),
+        );
+        let normal_transform = Mat3::from_mat4(to_world).inverse().transpose();
+        for vertex in &mut surface.vertices {
+            vertex.position = to_world.transform_point3(vertex.position);
+            vertex.normal = (normal_transform * vertex.normal).normalize_or_zero();
+        }
+        surface
+    }
+
+    fn mesh(scene: &Scene, index: usize) -> Self {
+        let mesh = &scene.meshes[index];
+        let has_colors = mesh.vertices.iter().any(|v| v.color != Vec3::ONE);
+        Self {
+            vertices: mesh
+                .vertices
+                .iter()
+                .map(|v| Vertex {
+                    position: v.position,
+                    normal: v.normal,
+                    color: has_colors.then_some(v.color),
+                })
+                .collect(),
+            triangles: mesh
+                .triangles
+                .iter()
+                .map(|t| SurfaceTriangle { vertices: t.vertices, material: t.material_index })
+                .collect(),
+        }
+    }
+
+    /// A surface over u and v from zero to one, `point` gives the position and
+    /// normal. The triangles face along the v direction crossed with the u one
+    fn grid(&mut self, u_segments: u32, v_segments: u32, material: u32, point: impl Fn(f32, f32) -> (Vec3, Vec3)) {
+        let first = self.vertices.len() as u32;
+        for j in 0..=v_segments {
+            for i in 0..=u_segments {
+                let (position, normal) = point(i as f32 / u_segments as f32, j as f32 / v_segments as f32);
+                self.vertices.push(Vertex { position, normal, color: None });
+            }
+        }
+        let row = u_segments + 1;
+        for j in 0..v_segments {
+            for i in 0..u_segments {
+                let a = first + j * row + i;
+                let (b, c, d) = (a + 1, a + row, a + row + 1);
+                self.triangles.push(SurfaceTriangle { vertices: [a, c, b], material });
+                self.triangles.push(SurfaceTriangle { vertices: [b, c, d], material });
+            }
+        }
+    }
+
+    /// Facing along u cross v
+    fn quad(&mut self, corner: Vec3, u: Vec3, v: Vec3, material: u32) {
+        let normal = u.cross(v).normalize_or_zero();
+        self.grid(1, 1, material, |s, t| (corner + v * s + u * t, normal));
+    }
+
+    fn disk(&mut self, center: Vec3, radius: f32, normal: Vec3, material: u32) {
+        let first = self.vertices.len() as u32;
+        self.vertices.push(Vertex { position: center, normal, color: None });
+        for i in 0..SEGMENTS {
+            let angle = i as f32 / SEGMENTS as f32 * TAU;
+            let offset = Vec3::new(angle.cos(), 0.0, -angle.sin()) * radius;
+            self.vertices.push(Vertex { position: center + offset, normal, color: None });
+        }
+        for i in 0..SEGMENTS {
+            let (a, b) = (first + 1 + i, first + 1 + (i + 1) % SEGMENTS);
+            // Counter clockwise seen from the side the normal points to
+            let vertices = if normal.y > 0.0 { [first, a, b] } else { [first, b, a] };
+            self.triangles.push(SurfaceTriangle { vertices, material });
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use glam::Mat4;
+
+use crate::resources::{Instance, InstanceObject};
+
+/// Index of a node in its graph, they stay the same as nodes are added
+pub type NodeId = usize;
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    /// Relative to the parent
+    pub transform: Mat4,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+    /// Hiding a node hides everything under it
+    pub visible: bool,
+    pub tags: Vec<String>,
+    /// Groups have no object and only move their children
+    pub object: Option<InstanceObject>,
+    /// Replaces the materials of the object
+    pub material: Option<u32>,
+}
+
+/// Named nodes with transforms relative to their parents. The kernel only
+/// sees the flattened instances so the world is rebuilt when the graph changes
+#[derive(Debug, Clone, Default)]
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+    names: HashMap<String, NodeId>,
+    changed: bool,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node without a parent, names have to be unique
+    pub fn add(
+        &mut self,
+        name: String,
+        transform: Mat4,
+        object: Option<InstanceObject>,
+        material: Option<u32>,
+    ) -> anyhow::Result<NodeId> {
+        if self.names.contains_key(&name) {
+            anyhow::bail!("The name {} is used twice", name);
+        }
+
+        let id = self.nodes.len();
+        self.names.insert(name.clone(), id);
+        self.nodes.push(Node {
+            name,
+            transform,
+            parent: None,
+            children: Vec::new(),
+            visible: true,
+            tags: Vec::new(),
+            object,
+            material,
+        });
+        self.changed = true;
+        Ok(id)
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes.iter().enumerate()
+    }
+
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = NodeId> + 'a {
+        self.nodes().filter(move |(_, node)| node.tags.iter().any(|t| t == tag)).map(|(id, _)| id)
+    }
+
+    /// Moves the node under `parent`, or to the top with `None`. It keeps its
+    /// local transform so it moves along with the new parent
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> anyhow::Result<()> {
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            if a == id {
+                anyhow::bail!("{} can't be under itself", self.nodes[id].name);
+            }
+            ancestor = self.nodes[a].parent;
+        }
+
+        if let Some(old) = self.nodes[id].parent {
+            self.nodes[old].children.retain(|&c| c != id);
+        }
+        if let Some(parent) = parent {
+            self.nodes[parent].children.push(id);
+        }
+        self.nodes[id].parent = parent;
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Like `set_parent` by names
+    pub fn attach(&mut self, name: &str, parent: &str) -> anyhow::Result<()> {
+        let id = self.find(name).with_context(|| format!("Nothing is named {}", name))?;
+        let parent = self.find(parent).with_context(|| format!("Nothing is named {}", parent))?;
+        self.set_parent(id, Some(parent))
+    }
+
+    pub fn set_transform(&mut self, id: NodeId, transform: Mat4) {
+        self.nodes[id].transform = transform;
+        self.changed = true;
+    }
+
+    pub fn set_visible(&mut self, id: NodeId, visible: bool) {
+        self.nodes[id].visible = visible;
+        self.changed = true;
+    }
+
+    pub fn set_material(&mut self, id: NodeId, material: Option<u32>) {
+        self.nodes[id].material = material;
+        self.changed = true;
+    }
+
+    pub fn set_tags(&mut self, id: NodeId, tags: Vec<String>) {
+        self.nodes[id].tags = tags;
+    }
+
+    /// Where the node ends up after the transforms of all its parents
+    pub fn world_transform(&self, id: NodeId) -> Mat4 {
+        let node = &self.nodes[id];
+        match node.parent {
+            Some(parent) => self.world_transform(parent) * node.transform,
+            None => node.transform,
+        }
+    }
+
+    /// Visible when it and all its parents are
+    pub fn is_visible(&self, id: NodeId) -> bool {
+        let node = &self.nodes[id];
+        node.visible && node.parent.is_none_or(|parent| self.is_visible(parent))
+    }
+
+    /// True once after any change to the nodes
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// An instance for every visible node with an object, in world space
+    pub fn flatten(&self) -> Vec<Instance> {
+        let mut instances = Vec::new();
+        let mut stack: Vec<(NodeId, Mat4)> = (0..self.nodes.len())
+            .rev()
+            .filter(|&id| self.nodes[id].parent.is_none())
+            .map(|id| (id, Mat4::IDENTITY))
+            .collect();
+
+        while let Some((id, parent_transform)) = stack.pop() {
+            let node = &self.nodes[id];
+            if !node.visible {
+                continue;
+            }
+            let transform = parent_transform * node.transform;
+            if let Some(object) = node.object {
+                instances.push(Instance { object, transform, material: node.material });
+            }
+            stack.extend(node.children.iter().rev().map(|&child| (child, transform)));
+        }
+        instances
+    }
+}
@@ -1,23 +1,118 @@
+use bytemuck::{bytes_of, Pod, Zeroable};
 use wgpu::*;
 
+use winit::keyboard::KeyCode;
+
 use crate::{
-    common::{Shape, Triangles, Vertex},
+    common::{Shape, Triangles, Vertex, UNIFORM_BUFFER_BINDING},
     render_env::RenderEnv,
 };
 
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViewMode {
+    Color = 0,
+    /// Shows how many samples each pixel has taken
+    SampleHeatmap = 1,
+}
+
+/// Maps the unbounded radiance of the render into the displayable range
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMapper {
+    Clamp = 0,
+    Reinhard = 1,
+    AcesFilmic = 2,
+    AgX = 3,
+}
+
+impl ToneMapper {
+    fn next(self) -> Self {
+        match self {
+            ToneMapper::Clamp => ToneMapper::Reinhard,
+            ToneMapper::Reinhard => ToneMapper::AcesFilmic,
+            ToneMapper::AcesFilmic => ToneMapper::AgX,
+            ToneMapper::AgX => ToneMapper::Clamp,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct ScreenSettings {
+    view_mode: u32,
+    max_samples: f32,
+    tone_mapper: u32,
+    exposure: f32,
+    encode_srgb: u32,
+    padding: [u32; 3],
+}
+
 pub struct Screen {
     pipeline: RenderPipeline,
     screen_quad: Triangles,
+    view_mode: ViewMode,
+    tone_mapper: ToneMapper,
+    /// In stops, every +1 doubles the brightness
+    exposure: f32,
+    /// sRGB surfaces do the encoding in hardware, otherwise the shader does it
+    encode_srgb: bool,
+    settings_buffer: Buffer,
+    settings_bind_group: BindGroup,
 }
 
 impl Screen {
-    pub fn new(render_env: &RenderEnv, sampler_bind_layout: &BindGroupLayout) -> Self {
+    pub fn new(render_env: &RenderEnv, sampler_bind_layout: &BindGroupLayout, stats_view: &TextureView) -> Self {
         let device = &render_env.device;
         let shader = device.create_shader_module(include_wgsl!("./shaders/screen_shader.wgsl"));
 
+        let settings_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Screen settings buffer"),
+            size: std::mem::size_of::<ScreenSettings>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let settings_bind_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Screen settings bind group layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    ty: UNIFORM_BUFFER_BINDING,
+                    visibility: ShaderStages::FRAGMENT,
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    visibility: ShaderStages::FRAGMENT,
+                    count: None,
+                },
+            ],
+        });
+
+        let settings_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Screen settings bind group"),
+            layout: &settings_bind_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(stats_view),
+                },
+            ],
+        });
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Screen Pipeline Layout"),
-            bind_group_layouts: &[sampler_bind_layout],
+            bind_group_layouts: &[sampler_bind_layout, &settings_bind_layout],
             push_constant_ranges: &[],
         });
 
@@ -51,28 +146,89 @@ impl Screen {
 
         let screen_quad = Triangles::new(render_env, &[Shape::unit_square()]);
 
-        Self {
+        let screen = Self {
             pipeline,
             screen_quad,
+            view_mode: ViewMode::Color,
+            tone_mapper: ToneMapper::Clamp,
+            exposure: 0.0,
+            encode_srgb: !render_env.surface_config.format.is_srgb(),
+            settings_buffer,
+            settings_bind_group,
+        };
+        screen.update(render_env, 1);
+        screen
+    }
+
+    pub fn key_press(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::KeyH => {
+                self.view_mode = match self.view_mode {
+                    ViewMode::Color => ViewMode::SampleHeatmap,
+                    ViewMode::SampleHeatmap => ViewMode::Color,
+                };
+            }
+            KeyCode::KeyO => {
+                self.tone_mapper = self.tone_mapper.next();
+                println!("\nTone mapper: {:?}", self.tone_mapper);
+            }
+            KeyCode::Equal => self.exposure = (self.exposure + 0.5).min(10.0),
+            KeyCode::Minus => self.exposure = (self.exposure - 0.5).max(-10.0),
+            _ => (),
         }
     }
 
+    /// `max_samples` is what the heatmap is normalized against
+    pub fn update(&self, render_env: &RenderEnv, max_samples: u32) {
+        let settings = ScreenSettings {
+            view_mode: self.view_mode as u32,
+            max_samples: max_samples.max(1) as f32,
+            tone_mapper: self.tone_mapper as u32,
+            exposure: self.exposure,
+            encode_srgb: self.encode_srgb as u32,
+            padding: [0; 3],
+        };
+        render_env.queue.write_buffer(&self.settings_buffer, 0, bytes_of(&settings));
+    }
+
+    /// Draws the image into a new texture with the surface format, this is
+    /// exactly what ends up on screen so it can be saved
+    pub fn render_to_texture(&self, render_env: &RenderEnv, sampler_bind_group: &BindGroup) -> Texture {
+        let device = &render_env.device;
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Offscreen screen texture"),
+            size: Extent3d {
+                width: render_env.surface_config.width,
+                height: render_env.surface_config.height,
+                ..Default::default()
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: render_env.surface_config.format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+        self.render(&mut encoder, &view, sampler_bind_group);
+        render_env.queue.submit(Some(encoder.finish()));
+        texture
+    }
+
     pub fn render(
         &self,
         encoder: &mut CommandEncoder,
-        current_texture: &SurfaceTexture,
+        view: &TextureView,
         sampler_bind_group: &BindGroup,
     ) {
-        let view = current_texture
-            .texture
-            .create_view(&TextureViewDescriptor::default());
-
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("My render pass"),
             color_attachments: &[
                 // This is what @location(0) in the fragment shader targets
                 Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -90,6 +246,7 @@ impl Screen {
 
         render_pass.set_pipeline(&self.pipeline);
         render_pass.set_bind_group(0, sampler_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.settings_bind_group, &[]);
         self.screen_quad.draw(&mut render_pass);
     }
 }
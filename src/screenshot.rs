@@ -0,0 +1,57 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use wgpu::{BindGroup, TextureFormat};
+
+use crate::{common::read_texture, render_env::RenderEnv, resources::Camera, screen::Screen};
+
+pub const SCREENSHOT_DIR: &str = "screenshots";
+
+/// Saves what is currently on screen as a png, with the camera and the
+/// render settings stored in the png text chunks
+pub fn save_screenshot(
+    render_env: &RenderEnv,
+    screen: &Screen,
+    image_bind_group: &BindGroup,
+    camera: &Camera,
+) -> anyhow::Result<PathBuf> {
+    let texture = screen.render_to_texture(render_env, image_bind_group);
+    let mut pixels = read_texture(render_env, &texture);
+
+    match texture.format() {
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => {
+            pixels.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+        }
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => (),
+        format => anyhow::bail!("Can't save a screenshot from a {:?} surface", format),
+    }
+
+    std::fs::create_dir_all(SCREENSHOT_DIR)?;
+    let path = PathBuf::from(SCREENSHOT_DIR).join(format!("screenshot_{}.png", timestamp()));
+    let file = File::create(&path).with_context(|| format!("Unable to create {}", path.display()))?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), texture.width(), texture.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+    for (keyword, text) in camera.metadata() {
+        encoder.add_text_chunk(keyword.to_string(), text)?;
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    Ok(path)
+}
+
+/// Milliseconds since the unix epoch, so the screenshots sort by when they were taken
+pub fn timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
@@ -1,77 +1,108 @@
-struct VertexInput {
-  @location(0) position: vec3<f32>,
-  @location(1) uv: vec2<f32>,
-};
+// Post processing effects, each pass runs one effect from input to output
 
 const PI = 3.141592;
 
-struct VertexOutput {
-  @builtin(position) clip_position: vec4<f32>,
-  @location(0) position: vec3<f32>,
-  @location(1) uv: vec2<f32>,
-};
+// Effects
+const EFFECT_GRADE: u32 = 0u;
+const EFFECT_VIGNETTE: u32 = 1u;
+const EFFECT_SHARPEN: u32 = 2u;
+const EFFECT_WAVE: u32 = 3u;
+
+// What the values mean depends on the effect, see `post_process.rs`
+struct Effect {
+  kind: u32,
+  values: vec4<f32>,
+}
 
 @group(0) @binding(0)
-var<uniform> time: f32;
+var texture: texture_2d<f32>;
 @group(0) @binding(1)
+var samplr: sampler;
+@group(1) @binding(0)
+var output: texture_storage_2d<rgba32float, write>;
+@group(2) @binding(0)
+var<uniform> effect: Effect;
+@group(3) @binding(0)
+var<uniform> time: f32;
+@group(3) @binding(1)
 var<uniform> deltatime: f32;
 
-@vertex
-fn vs_main(model: VertexInput) -> VertexOutput {
-  var out: VertexOutput;
-  let pos = model.position;
-  out.clip_position = vec4<f32>(pos, 1.0);
-  out.position = model.position;
-  out.uv = vec2<f32>(model.uv.x, 1.0 - model.uv.y);
-  return out;
+struct Oklch { L: f32, C: f32, h: f32 }
+
+var<private> dim: vec2<u32>;
+
+@compute @workgroup_size(16,16,1)
+fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+  dim = textureDimensions(texture);
+  let pixel = global_invocation_id.xy;
+  if pixel.x >= dim.x || pixel.y >= dim.y {
+    return;
+  }
+
+  let color = textureLoad(texture, pixel, 0);
+  // -1 to 1 across the screen like the old fragment position
+  let position = (vec2<f32>(pixel) + 0.5) / vec2<f32>(dim) * 2.0 - 1.0;
+  var result: vec3<f32>;
+  switch effect.kind {
+    case EFFECT_GRADE: { result = grade(color.xyz); }
+    case EFFECT_VIGNETTE: { result = vignette(color.xyz, position); }
+    case EFFECT_SHARPEN: { result = sharpen(pixel, color.xyz); }
+    case EFFECT_WAVE: { result = wave(color.xyz, position); }
+    default: { result = color.xyz; }
+  }
+
+  textureStore(output, pixel, vec4<f32>(result, color.w));
 }
 
-struct Oklch { L: f32, C: f32, h: f32 }
+// values: saturation scale, hue shift in radians, lightness scale
+fn grade(color: vec3<f32>) -> vec3<f32> {
+  var c = srgb_to_oklch(color);
+  c.C *= effect.values.x;
+  c.h += effect.values.y;
+  c.L *= effect.values.z;
+  return max(oklch_to_srgb(c), vec3<f32>(0.0));
+}
 
-@group(1) @binding(0)
-var texture: texture_2d<f32>;
-@group(1) @binding(1)
-var samplr: sampler;
+// values: strength, radius where it starts, softness of the falloff
+fn vignette(color: vec3<f32>, position: vec2<f32>) -> vec3<f32> {
+  let dist = length(position) / sqrt(2.0);
+  let radius = effect.values.y;
+  let falloff = smoothstep(radius, radius + effect.values.z, dist);
+  return color * (1.0 - effect.values.x * falloff);
+}
+
+// values: amount, distance to the neighbouring taps in pixels
+fn sharpen(pixel: vec2<u32>, color: vec3<f32>) -> vec3<f32> {
+  let blurred = supersample(vec2<i32>(pixel), effect.values.y).xyz;
+  return max(color + (color - blurred) * effect.values.x, vec3<f32>(0.0));
+}
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-  // let color_sample = textureSample(texture, samplr, in.uv);
-  let color_sample = supersample(in.uv, 1.0);
-  var color = srgb_to_oklch(color_sample.xyz);
-  color.C = dist_wave(in.position.xy, 0.2, time / 10.0) * color.C;
-  // color.L = dist_wave(in.position.xy, 0.2, time / 10.0) * color.L;
-  // color.h = dist_wave(in.position.xy, 0.2, time / 10.0) * color.h;
-  return oklch_to_srgba(color);
+// values: amount, period, speed
+fn wave(color: vec3<f32>, position: vec2<f32>) -> vec3<f32> {
+  var c = srgb_to_oklch(color);
+  let w = dist_wave(position, effect.values.y, time * effect.values.z);
+  c.C = mix(c.C, w * c.C, effect.values.x);
+  return max(oklch_to_srgb(c), vec3<f32>(0.0));
 }
 
 fn dist_wave(pos: vec2<f32>, period: f32, offset: f32) -> f32 {
-  let dim = textureDimensions(texture);
-  let scale = vec2<f32>(1.0 / f32(dim.x), 1.0/ f32(dim.y)) * f32(dim.y); 
+  let scale = vec2<f32>(1.0 / f32(dim.x), 1.0/ f32(dim.y)) * f32(dim.y);
   let l = length(pos / scale);
   let x = (l + offset) * 2.0 * PI / period;
   return (sin(x) + 1.0) / 2.0;
 }
 
-fn supersample(coords: vec2<f32>, dist: f32) -> vec4<f32> {
-  let dim = textureDimensions(texture);
-  let offset_x = vec2<f32>(1.0 / f32(dim.x), 0.0) * dist;
-  let offset_y = vec2<f32>(0.0, 1.0/ f32(dim.y)) * dist;
-  let l = textureSample(texture, samplr, coords - offset_x);
-  let r = textureSample(texture, samplr, coords + offset_x);
-  let t = textureSample(texture, samplr, coords + offset_y);
-  let b = textureSample(texture, samplr, coords - offset_y);
-  let c = textureSample(texture, samplr, coords);
+fn supersample(coords: vec2<i32>, dist: f32) -> vec4<f32> {
+  let d = i32(max(dist, 1.0));
+  let max_coord = vec2<i32>(dim) - 1;
+  let l = textureLoad(texture, clamp(coords - vec2<i32>(d, 0), vec2<i32>(0), max_coord), 0);
+  let r = textureLoad(texture, clamp(coords + vec2<i32>(d, 0), vec2<i32>(0), max_coord), 0);
+  let t = textureLoad(texture, clamp(coords + vec2<i32>(0, d), vec2<i32>(0), max_coord), 0);
+  let b = textureLoad(texture, clamp(coords - vec2<i32>(0, d), vec2<i32>(0), max_coord), 0);
+  let c = textureLoad(texture, coords, 0);
   return (l + r + t + b + c) / 5.0;
 }
 
-fn hue_circle(pos: vec2<f32>, radius: f32, L: f32) -> Oklch {
-  if length(pos) > radius {
-    discard;
-  }
-
-  return oklab_to_oklch(L, pos.y, pos.x);
-}
-
 fn oklab_to_oklch(L: f32, a: f32, b: f32) -> Oklch {
   let C = sqrt(pow(a,2.0) + pow(b, 2.0));
   let h = atan2(b,a);
@@ -95,8 +126,9 @@ fn srgb_to_oklch(c: vec3<f32>) -> Oklch {
   return oklab_to_oklch(L,a,b);
 }
 
+// pow is undefined for negative numbers
 fn cbrt(f: f32) -> f32 {
-  return pow(f, 1.0/3.0);
+  return sign(f) * pow(abs(f), 1.0/3.0);
 }
 
 fn oklch_to_srgb(c: Oklch) -> vec3<f32> {
@@ -119,8 +151,3 @@ fn oklch_to_srgb(c: Oklch) -> vec3<f32> {
     -0.0041960863f * l - 0.7034186147f * m + 1.7076147010f * s,
   );
 }
-
-fn oklch_to_srgba(c: Oklch) -> vec4<f32> {
-  let c_srgb = oklch_to_srgb(c);
-  return vec4<f32>(c_srgb.xyz, 1.0);
-}
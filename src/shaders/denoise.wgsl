@@ -0,0 +1,109 @@
+// Edge aware a-trous wavelet filter, each pass spreads the 5x5 kernel
+// further apart and is stopped at edges found in the feature buffers
+
+struct Params {
+  step_size: i32,
+  first_pass: u32,
+  last_pass: u32,
+  padding: u32,
+}
+
+// How quickly the weights fall off when the features differ
+const SIGMA_LUMINANCE: f32 = 4.0;
+const SIGMA_NORMAL: f32 = 128.0;
+const SIGMA_DEPTH: f32 = 1.0;
+const EPSILON: f32 = 0.0001;
+
+@group(0) @binding(0) var input: texture_2d<f32>;
+@group(0) @binding(1) var output: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(2) var albedo: texture_2d<f32>;
+@group(0) @binding(3) var normal_depth: texture_2d<f32>;
+@group(0) @binding(4) var stats: texture_2d<f32>;
+@group(0) @binding(5) var<uniform> params: Params;
+
+var<private> size: vec2<i32>;
+
+@compute @workgroup_size(16,16,1)
+fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+  size = vec2<i32>(textureDimensions(output));
+  let pixel = vec2<i32>(global_invocation_id.xy);
+  if pixel.x >= size.x || pixel.y >= size.y {
+    return;
+  }
+
+  let center_color = load_color(pixel);
+  let center_nd = textureLoad(normal_depth, pixel, 0);
+
+  // Background pixels have nothing to smooth over
+  if center_nd.w < 0.0 {
+    textureStore(output, pixel, finish(pixel, center_color));
+    return;
+  }
+
+  let center_lum = luminance(center_color.xyz);
+  let lum_sigma = SIGMA_LUMINANCE * sqrt(mean_variance(pixel)) + EPSILON;
+  // Depth changes more per pixel the further apart the taps are
+  let depth_sigma = SIGMA_DEPTH * f32(params.step_size) * max(fwidth_depth(pixel, center_nd.w), EPSILON);
+
+  var kernel = array<f32, 3>(3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+  var sum = vec4<f32>(0.0);
+  var weight_sum = 0.0;
+  for (var y = -2; y <= 2; y++) {
+    for (var x = -2; x <= 2; x++) {
+      let tap = clamp(pixel + vec2<i32>(x, y) * params.step_size, vec2<i32>(0), size - 1);
+      let nd = textureLoad(normal_depth, tap, 0);
+      if nd.w < 0.0 {
+        continue;
+      }
+
+      let color = load_color(tap);
+
+      let w_lum = exp(-abs(luminance(color.xyz) - center_lum) / lum_sigma);
+      let w_normal = pow(max(dot(nd.xyz, center_nd.xyz), 0.0), SIGMA_NORMAL);
+      let w_depth = exp(-abs(nd.w - center_nd.w) / depth_sigma);
+      let weight = kernel[abs(x)] * kernel[abs(y)] * w_lum * w_normal * w_depth;
+
+      sum += color * weight;
+      weight_sum += weight;
+    }
+  }
+
+  textureStore(output, pixel, finish(pixel, sum / max(weight_sum, EPSILON)));
+}
+
+// The first pass divides out the albedo so textures aren't blurred away
+fn load_color(pixel: vec2<i32>) -> vec4<f32> {
+  let color = textureLoad(input, pixel, 0);
+  if params.first_pass == 1u {
+    return vec4<f32>(color.xyz / max(textureLoad(albedo, pixel, 0).xyz, vec3<f32>(0.01)), color.w);
+  }
+  return color;
+}
+
+// And the last pass puts it back
+fn finish(pixel: vec2<i32>, color: vec4<f32>) -> vec4<f32> {
+  if params.last_pass == 1u {
+    return vec4<f32>(color.xyz * max(textureLoad(albedo, pixel, 0).xyz, vec3<f32>(0.01)), 1.0);
+  }
+  return color;
+}
+
+// Variance of the pixel's mean luminance from the adaptive sampling statistics
+fn mean_variance(pixel: vec2<i32>) -> f32 {
+  let s = textureLoad(stats, pixel, 0);
+  let variance = max(s.y - s.x * s.x, 0.0);
+  return variance / max(s.z, 1.0);
+}
+
+fn fwidth_depth(pixel: vec2<i32>, depth: f32) -> f32 {
+  var right = textureLoad(normal_depth, min(pixel + vec2<i32>(1, 0), size - 1), 0).w;
+  var down = textureLoad(normal_depth, min(pixel + vec2<i32>(0, 1), size - 1), 0).w;
+  // the background has no depth to compare against
+  if right < 0.0 { right = depth; }
+  if down < 0.0 { down = depth; }
+  return abs(right - depth) + abs(down - depth);
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+  return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
@@ -5,11 +5,123 @@ struct Sphere {
   material_index: u32,
 }
 
+// Kinds of primitives
+const PRIMITIVE_PLANE: u32 = 0u;
+const PRIMITIVE_QUAD: u32 = 1u;
+const PRIMITIVE_BOX: u32 = 2u;
+const PRIMITIVE_DISK: u32 = 3u;
+const PRIMITIVE_CYLINDER: u32 = 4u;
+const PRIMITIVE_CONE: u32 = 5u;
+const PRIMITIVE_TORUS: u32 = 6u;
+
+// Intersected in the space of its axes, quads use x and y as their edges
+struct Primitive {
+  origin: vec3<f32>,
+  kind: u32,
+  x_axis: vec3<f32>,
+  material_index: u32,
+  y_axis: vec3<f32>,
+  radius: f32,
+  z_axis: vec3<f32>,
+  minor_radius: f32,
+  size: vec3<f32>,
+}
+
+// The closest hit on a primitive so far, in its local space
+struct LocalHit {
+  t: f32,
+  normal: vec3<f32>,
+  tex_coords: vec2<f32>,
+  tangent: vec3<f32>,
+}
+
 struct Material {
   color: vec4<f32>,
+  emission: vec4<f32>,
   metallic: f32,
-  specular: f32, 
   roughness: f32,
+  specular: f32, 
+  // indices into textures or NO_TEXTURE
+  albedo_texture: u32,
+  roughness_metallic_texture: u32,
+  emission_texture: u32,
+  normal_texture: u32,
+  bump_texture: u32,
+  normal_strength: f32,
+  ior: f32,
+  transmission: f32,
+  // how much the dielectric reflection takes the color
+  specular_tint: f32,
+  // complex index of refraction of metals, zero k leaves the color as it is
+  eta: vec4<f32>,
+  k: vec4<f32>,
+  // stretches the highlights along the tangent, turned by rotation in turns
+  anisotropy: f32,
+  anisotropy_rotation: f32,
+  sheen: f32,
+  sheen_tint: f32,
+  clearcoat: f32,
+  clearcoat_roughness: f32,
+}
+
+// Kinds of material textures
+const TEXTURE_IMAGE: u32 = 0u;
+const TEXTURE_CHECKER: u32 = 1u;
+const TEXTURE_NOISE: u32 = 2u;
+const TEXTURE_TURBULENCE: u32 = 3u;
+const TEXTURE_MARBLE: u32 = 4u;
+const TEXTURE_WORLEY: u32 = 5u;
+const TEXTURE_GRADIENT: u32 = 6u;
+
+struct MaterialTexture {
+  kind: u32,
+  // layer in material_textures for images
+  layer: u32,
+  // used instead of the colors when they aren't NO_TEXTURE
+  sub_textures: vec2<u32>,
+  color_a: vec3<f32>,
+  scale: f32,
+  color_b: vec3<f32>,
+  octaves: u32,
+}
+
+struct MeshVertex {
+  position: vec3<f32>,
+  u: f32,
+  normal: vec3<f32>,
+  v: f32,
+  // w is the sign of the bitangent pointing up the image
+  tangent: vec4<f32>,
+  color: vec3<f32>,
+}
+
+struct Triangle {
+  vertices: vec3<u32>,
+  material_index: u32,
+}
+
+// A leaf when count isn't zero, then the triangles start at left_or_first.
+// Otherwise the children are at left_or_first and the one after
+// Kinds of instances
+const INSTANCE_MESH: u32 = 0u;
+const INSTANCE_SPHERE: u32 = 1u;
+const INSTANCE_PRIMITIVE: u32 = 2u;
+
+struct Instance {
+  world_to_object: mat4x4<f32>,
+  object_to_world: mat4x4<f32>,
+  kind: u32,
+  // the root of the mesh in bvh, or the sphere or primitive
+  index: u32,
+  // replaces the material of the object unless it is NO_MATERIAL
+  material: u32,
+}
+
+struct BvhNode {
+  min: vec3<f32>,
+  left_or_first: u32,
+  max: vec3<f32>,
+  count: u32,
 }
 
 struct Ray {
@@ -25,6 +137,11 @@ struct HitRecord {
   front_face: bool,
   hit: bool,
   material_index: u32,
+  tex_coords: vec2<f32>,
+  // along u on the outward side like the mesh vertices, zero when there are no texture coordinates
+  tangent: vec4<f32>,
+  // the vertex color of meshes, white on everything else
+  color: vec3<f32>,
 }
 
 struct Camera {
@@ -36,12 +153,142 @@ struct Camera {
   samples_per_pixel: u32,
   frames_to_render: u32,
   current_frame: u32,
+  noise_threshold: f32,
+  min_samples: u32,
+}
+
+// Per pixel sampling statistics used to stop sampling converged pixels
+struct PixelStats {
+  mean: f32,
+  mean_sq: f32,
+  samples: f32,
+}
+
+struct SampleResult {
+  color: vec3<f32>,
+  luminance_sq: f32,
 }
 
 struct ObjectData {
   spheres: array<Sphere>,
 }
 
+struct Environment {
+  // around the up axis in radians
+  rotation: f32,
+  intensity: f32,
+  width: u32,
+  height: u32,
+  enabled: u32,
+}
+
+struct Sky {
+  sun_direction: vec3<f32>,
+  enabled: u32,
+  sun_irradiance: vec3<f32>,
+  sun_cos_radius: f32,
+  // zenith Yxy over the perez function at the zenith
+  zenith: vec3<f32>,
+  fade: f32,
+  // A to E for Y, x and y
+  perez: array<vec4<f32>, 5>,
+}
+
+// Kinds of punctual lights
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_SPOT: u32 = 1u;
+const LIGHT_DIRECTIONAL: u32 = 2u;
+
+struct Light {
+  position: vec3<f32>,
+  kind: u32,
+  // where the light travels for spot and directional lights
+  direction: vec3<f32>,
+  intensity: f32,
+  color: vec3<f32>,
+  cos_inner: f32,
+  cos_outer: f32,
+  enabled: u32,
+}
+
+// Kinds of volumes
+const VOLUME_SPHERE: u32 = 0u;
+const VOLUME_BOX: u32 = 1u;
+
+struct Volume {
+  center: vec3<f32>,
+  kind: u32,
+  // radius in x for spheres and half the size for boxes
+  size: vec3<f32>,
+  density: f32,
+  color: vec3<f32>,
+  anisotropy: f32,
+}
+
+// Fog everywhere that thins out above fog_height, bounded volumes and a density grid
+struct Media {
+  fog_color: vec3<f32>,
+  fog_density: f32,
+  fog_height: f32,
+  fog_falloff: f32,
+  fog_anisotropy: f32,
+  volume_count: u32,
+  grid_min: vec3<f32>,
+  // zero without a grid
+  grid_density: f32,
+  grid_max: vec3<f32>,
+  // the highest density in the grid
+  grid_majorant: f32,
+  grid_color: vec3<f32>,
+  grid_anisotropy: f32,
+  volumes: array<Volume, 16>,
+}
+
+// Where a ray scattered in a medium before reaching max_distance
+struct MediumEvent {
+  scattered: bool,
+  distance: f32,
+  color: vec3<f32>,
+  anisotropy: f32,
+}
+
+// Where light sampling looks from, either a surface or a point in a medium
+struct ScatterPoint {
+  point: vec3<f32>,
+  // zero inside media
+  normal: vec3<f32>,
+  // the color of the medium
+  albedo: vec3<f32>,
+  // the direction the path arrived in
+  incoming: vec3<f32>,
+  anisotropy: f32,
+  // the surface and its shading frame, unused in media
+  material: Material,
+  frame: Frame,
+  front_face: bool,
+}
+
+// What a surface does with an incoming ray
+struct Scatter {
+  direction: vec3<f32>,
+  attenuation: vec3<f32>,
+  // of every lobe together, zero for mirror bounces which light sampling can't find
+  pdf: f32,
+  absorbed: bool,
+}
+
+struct LightSample {
+  direction: vec3<f32>,
+  radiance: vec3<f32>,
+  pdf: f32,
+}
+
+struct CdfSample {
+  index: u32,
+  // where in the bucket the sample landed from 0 to 1
+  offset: f32,
+}
+
 // Vectors
 const ZERO: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
 const ONE: vec3<f32> = vec3<f32>(1.0, 1.0, 1.0);
@@ -58,15 +305,52 @@ const CACHE_ON: bool = true;
 // Other constants
 const PI: f32 = 3.1415926535897932385;
 const MAX_U32: u32 = 4294967295u;
+const NO_TEXTURE: u32 = 4294967295u;
+const NO_MATERIAL: u32 = 4294967295u;
+const BVH_STACK_SIZE: u32 = 32u;
+const BUMP_STEP: f32 = 0.01;
+// Delta and ratio tracking give up after this many steps through the grid
+const GRID_MAX_STEPS: u32 = 256u;
+// ggx lobes this smooth are mirrors which light sampling can't find
+const MIN_ALPHA: f32 = 1e-3;
 
 var<private> uv: vec2<u32>;
 var<private> size: vec2<u32>;
+// Albedo of the sheen lobe, roughness 0.25, 0.5, 0.75 and 1 down and the
+// cosine of the view from 0 to 1 in eighths across
+var<private> sheen_albedo_table: array<f32, 36> = array<f32, 36>(
+  0.929, 0.596, 0.431, 0.316, 0.230, 0.162, 0.108, 0.065, 0.031,
+  0.744, 0.525, 0.414, 0.332, 0.267, 0.212, 0.164, 0.122, 0.083,
+  0.672, 0.492, 0.402, 0.335, 0.281, 0.234, 0.192, 0.154, 0.118,
+  0.632, 0.473, 0.394, 0.335, 0.287, 0.246, 0.208, 0.174, 0.141,
+);
 
-@group(0) @binding(0) var color_buffer: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(0) var color_buffer: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(1) var stats_buffer: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(2) var stats_cache: texture_2d<f32>;
+@group(0) @binding(3) var<storage, read_write> active_pixels: atomic<u32>;
+@group(0) @binding(4) var albedo_buffer: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(5) var normal_depth_buffer: texture_storage_2d<rgba32float, write>;
 @group(1) @binding(0) var color_cache: texture_2d<f32>;
 @group(2) @binding(0) var<uniform> camera: Camera;
 @group(2) @binding(1) var<storage, read> objects: ObjectData;
 @group(2) @binding(2) var<storage, read> materials: array<Material>;
+@group(2) @binding(3) var env_texture: texture_2d<f32>;
+@group(2) @binding(4) var<storage, read> env_distribution: array<f32>;
+@group(2) @binding(5) var<uniform> environment: Environment;
+@group(2) @binding(6) var<uniform> sky: Sky;
+@group(2) @binding(7) var<storage, read> lights: array<Light>;
+@group(2) @binding(8) var<storage, read> vertices: array<MeshVertex>;
+@group(2) @binding(9) var<storage, read> triangles: array<Triangle>;
+@group(2) @binding(10) var<storage, read> bvh: array<BvhNode>;
+@group(2) @binding(11) var material_textures: texture_2d_array<f32>;
+@group(2) @binding(12) var texture_sampler: sampler;
+@group(2) @binding(13) var<storage, read> textures: array<MaterialTexture>;
+@group(2) @binding(14) var<uniform> media: Media;
+@group(2) @binding(15) var density_grid: texture_3d<f32>;
+@group(2) @binding(16) var<storage, read> primitives: array<Primitive>;
+// bvh starts with the nodes over these, the leaves point into them
+@group(2) @binding(17) var<storage, read> instances: array<Instance>;
 @group(3) @binding(0) var<uniform> time: f32;
 
 @compute @workgroup_size(16,16,1)
@@ -77,9 +361,27 @@ fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
     return;
   }
 
+  var stats = load_stats();
+  if is_converged(stats) {
+    // Converged pixels still need to be written since the buffer is copied to the cache
+    textureStore(color_buffer, uv, textureLoad(color_cache, uv, 0));
+    store_stats(stats);
+    return;
+  }
+
+  if camera.current_frame == 0u {
+    store_first_hit();
+  }
+
   initialize_rng();
-  let pixel_color = send_rays();
-  store_color(to_vec4(pixel_color));
+  let result = send_rays();
+  store_color(to_vec4(result.color), stats);
+  stats = add_samples(stats, result);
+  store_stats(stats);
+
+  if !is_converged(stats) {
+    atomicAdd(&active_pixels, 1u);
+  }
 }
 
 fn pixel_to_world(pixel_uv: vec2<u32>) -> vec3<f32> {
@@ -93,80 +395,1045 @@ fn get_pixel_delta() -> vec3<f32> {
   return uvnext - center;
 }
 
-fn store_color(pixel_color: vec4<f32>) {
+fn store_color(pixel_color: vec4<f32>, stats: PixelStats) {
   var color = pixel_color;
   if CACHE_ON {
     let cached_color = textureLoad(color_cache, uv, 0);
-    color = combine_pixel_cache_color(pixel_color, cached_color);
+    color = combine_pixel_cache_color(pixel_color, cached_color, stats.samples);
   }
 
   textureStore(color_buffer, uv, color);
 }
 
-fn combine_pixel_cache_color(pixel_color: vec4<f32>, cached_color: vec4<f32>) -> vec4<f32> {
-  let frame = camera.current_frame;
-  let next_frame = f32(frame + 1u);
-  var cw = f32(frame) / next_frame;
+// Weights the cache by how many samples it holds, since converged
+// pixels stop sampling the pixels don't all have the same amount
+fn combine_pixel_cache_color(pixel_color: vec4<f32>, cached_color: vec4<f32>, cached_samples: f32) -> vec4<f32> {
+  let new_samples = f32(camera.samples_per_pixel);
+  let cw = cached_samples / (cached_samples + new_samples);
   let rw = 1.0 - cw;
   return pixel_color*rw + cached_color*cw; 
 }
 
-fn send_rays() -> vec3<f32> {
+fn load_stats() -> PixelStats {
+  if camera.current_frame == 0u {
+    return PixelStats(0.0, 0.0, 0.0);
+  }
+
+  let raw = textureLoad(stats_cache, uv, 0);
+  return PixelStats(raw.x, raw.y, raw.z);
+}
+
+fn store_stats(stats: PixelStats) {
+  textureStore(stats_buffer, uv, vec4<f32>(stats.mean, stats.mean_sq, stats.samples, 0.0));
+}
+
+fn add_samples(stats: PixelStats, result: SampleResult) -> PixelStats {
+  let new_samples = f32(camera.samples_per_pixel);
+  let total = stats.samples + new_samples;
+  let lum = luminance(result.color);
+  let mean = (stats.mean * stats.samples + lum * new_samples) / total;
+  let mean_sq = (stats.mean_sq * stats.samples + result.luminance_sq) / total;
+  return PixelStats(mean, mean_sq, total);
+}
+
+// A pixel is converged once the relative standard error of its
+// mean luminance drops below the noise threshold
+fn is_converged(stats: PixelStats) -> bool {
+  if camera.noise_threshold <= 0.0 || stats.samples < f32(camera.min_samples) {
+    return false;
+  }
+
+  let variance = max(stats.mean_sq - stats.mean * stats.mean, 0.0);
+  let std_error = sqrt(variance / stats.samples);
+  // the small floor keeps very dark pixels from never converging
+  return std_error / (stats.mean + 0.01) < camera.noise_threshold;
+}
+
+// Writes the feature buffers the denoiser uses to find edges. They
+// come from the pixel center so they only need to be written once
+fn store_first_hit() {
+  let ray = Ray(camera.pos, pixel_to_world(uv));
+  let rec = trace(ray);
+  if !rec.hit {
+    textureStore(albedo_buffer, uv, to_vec4(miss(ray, 0.0)));
+    textureStore(normal_depth_buffer, uv, vec4<f32>(ZERO, -1.0));
+    return;
+  }
+
+  let material = surface_material(rec);
+  let depth = rec.t * length(ray.direction);
+  textureStore(albedo_buffer, uv, to_vec4(material.color.xyz));
+  textureStore(normal_depth_buffer, uv, vec4<f32>(shading_normal(rec, material), depth));
+}
+
+fn send_rays() -> SampleResult {
   let pixel_center = pixel_to_world(uv);
   let pixel_delta = get_pixel_delta();
   var color = ZERO;
+  var luminance_sq = 0.0;
   for(var sample = 0u; sample < camera.samples_per_pixel; sample++) {
     let ray = get_random_ray(pixel_center, pixel_delta, sample);
-    color += ray_color(ray);
+    let sample_color = ray_color(ray);
+    let lum = luminance(sample_color);
+    color += sample_color;
+    luminance_sq += lum * lum;
   }
-  return color / f32(camera.samples_per_pixel);
+  return SampleResult(color / f32(camera.samples_per_pixel), luminance_sq);
 }
 
 fn ray_color(start_ray: Ray) -> vec3<f32> {
-  var rec: HitRecord;
-  var ray = start_ray;
-  var color: vec3<f32> = ZERO;
-  var reflectance = 1.0;
-  var depth: i32;
-  for (depth = 0; depth <= MAX_RAY_DEPTH; depth++) {
-    rec = trace(ray);
+  // media measure distances along the ray
+  var ray = Ray(start_ray.origin, normalize(start_ray.direction));
+  var color = ZERO;
+  var throughput = ONE;
+  // pdf of the last bounce, for weighting environment hits against light sampling
+  var bounce_pdf = 0.0;
+  for (var depth = 0; depth <= MAX_RAY_DEPTH; depth++) {
+    var rec = trace(ray);
+    let medium = sample_media(ray, select(RAY_TMAX, rec.t, rec.hit));
+    if medium.scattered {
+      let point = ray_at(ray, medium.distance);
+      let at = ScatterPoint(point, ZERO, medium.color, ray.direction, medium.anisotropy, Material(), Frame(), false);
+      color += throughput * sample_lights(at);
+      let direction = sample_phase(ray.direction, medium.anisotropy);
+      throughput *= medium.color;
+      bounce_pdf = phase(dot(ray.direction, direction), medium.anisotropy);
+      ray = Ray(point, direction);
+      continue;
+    }
+
     if !rec.hit {
-      color += miss(ray) * reflectance;
+      color += throughput * miss(ray, bounce_pdf) * environment_mis_weight(ray.direction, bounce_pdf);
+      break;
+    }
+
+    let material = surface_material(rec);
+    rec.normal = shading_normal(rec, material);
+    color += throughput * material.emission.xyz;
+    let frame = shading_frame(rec, material.anisotropy_rotation);
+    let at = ScatterPoint(rec.point, rec.normal, ZERO, ray.direction, 0.0, material, frame, rec.front_face);
+    // mirror lobes give nothing here so only their shadow rays are skipped
+    color += throughput * sample_lights(at);
+    let bounce = scatter(at);
+    if bounce.absorbed {
       break;
-    } else {
-      color += scatter(&ray, rec, &reflectance);
     }
+
+    throughput *= bounce.attenuation;
+    bounce_pdf = bounce.pdf;
+    ray = Ray(rec.point, bounce.direction);
   }
 
   return color;
 }
 
-fn miss(ray: Ray) -> vec3<f32> {
-  let a = 0.5 * (1.0 - ray.direction.y);
+// The material at the hit with its textures applied
+fn surface_material(rec: HitRecord) -> Material {
+  var material = materials[rec.material_index];
+  material.color *= vec4<f32>(rec.color, 1.0);
+  if material.albedo_texture != NO_TEXTURE {
+    material.color *= texture_color(material.albedo_texture, rec, true);
+  }
+  if material.roughness_metallic_texture != NO_TEXTURE {
+    let texel = texture_color(material.roughness_metallic_texture, rec, false);
+    material.roughness *= texel.y;
+    material.metallic *= texel.z;
+  }
+  if material.emission_texture != NO_TEXTURE {
+    material.emission *= vec4<f32>(texture_color(material.emission_texture, rec, true).xyz, 1.0);
+  }
+  return material;
+}
+
+// The normal tilted by the normal or bump texture of the material
+fn shading_normal(rec: HitRecord, material: Material) -> vec3<f32> {
+  if material.normal_texture == NO_TEXTURE && material.bump_texture == NO_TEXTURE {
+    return rec.normal;
+  }
+
+  // Back faces see the tangent frame mirrored
+  let normal = rec.normal;
+  var tangent = rec.tangent.xyz - normal * dot(rec.tangent.xyz, normal);
+  if length_squared(tangent) < 1e-12 {
+    tangent = any_perpendicular(normal);
+  }
+  tangent = normalize(tangent);
+  let handedness = select(-rec.tangent.w, rec.tangent.w, rec.front_face);
+  let bitangent = cross(normal, tangent) * select(1.0, handedness, rec.tangent.w != 0.0);
+
+  var tilted: vec3<f32>;
+  if material.normal_texture != NO_TEXTURE {
+    let texel = texture_color(material.normal_texture, rec, false).xyz * 2.0 - 1.0;
+    let local = vec3<f32>(texel.xy * material.normal_strength, max(texel.z, 1e-3));
+    tilted = local.x * tangent + local.y * bitangent + local.z * normal;
+  } else {
+    let slope = bump_slope(material.bump_texture, rec, tangent, bitangent);
+    tilted = normal - material.normal_strength * (slope.x * tangent + slope.y * bitangent);
+  }
+
+  // Keep the normal on the side the ray came from
+  tilted = normalize(tilted);
+  if dot(tilted, normal) < 0.05 {
+    return normal;
+  }
+  return tilted;
+}
+
+// How much the height rises along the tangent and bitangent, per texel for
+// images and per BUMP_STEP for procedural textures
+fn bump_slope(index: u32, rec: HitRecord, tangent: vec3<f32>, bitangent: vec3<f32>) -> vec2<f32> {
+  let texel = 1.0 / vec2<f32>(textureDimensions(material_textures));
+  let height = texture_color(index, rec, false).x;
+
+  var along_u = rec;
+  along_u.tex_coords.x += texel.x;
+  along_u.point += tangent * BUMP_STEP;
+  var along_v = rec;
+  // up the image
+  along_v.tex_coords.y -= texel.y;
+  along_v.point += bitangent * BUMP_STEP;
+  return vec2<f32>(
+    texture_color(index, along_u, false).x - height,
+    texture_color(index, along_v, false).x - height,
+  );
+}
+
+// Procedural textures blend between their colors or sub textures, images
+// holding colors are stored as sRGB and decoded when `srgb` is set
+fn texture_color(index: u32, rec: HitRecord, srgb: bool) -> vec4<f32> {
+  let texture = textures[index];
+  if texture.kind == TEXTURE_IMAGE {
+    return image_color(texture.layer, rec.tex_coords, srgb);
+  }
+  let a = leaf_color(texture.sub_textures.x, texture.color_a, rec, srgb);
+  let b = leaf_color(texture.sub_textures.y, texture.color_b, rec, srgb);
+  return mix(a, b, texture_pattern(texture, rec.point));
+}
+
+// Sub textures can't have sub textures of their own, the scene loader makes sure of it
+fn leaf_color(index: u32, color: vec3<f32>, rec: HitRecord, srgb: bool) -> vec4<f32> {
+  if index == NO_TEXTURE {
+    return vec4<f32>(color, 1.0);
+  }
+  let texture = textures[index];
+  if texture.kind == TEXTURE_IMAGE {
+    return image_color(texture.layer, rec.tex_coords, srgb);
+  }
+  return vec4<f32>(mix(texture.color_a, texture.color_b, texture_pattern(texture, rec.point)), 1.0);
+}
+
+fn image_color(layer: u32, tex_coords: vec2<f32>, srgb: bool) -> vec4<f32> {
+  let texel = textureSampleLevel(material_textures, texture_sampler, tex_coords, layer, 0.0);
+  if srgb {
+    return vec4<f32>(srgb_to_linear(texel.xyz), texel.w);
+  }
+  return texel;
+}
+
+// How far from color_a to color_b the texture is at `point`
+fn texture_pattern(texture: MaterialTexture, point: vec3<f32>) -> f32 {
+  let p = point * texture.scale;
+  switch texture.kind {
+    case TEXTURE_CHECKER: {
+      let cell = vec3<i32>(floor(p));
+      return f32((cell.x + cell.y + cell.z) & 1);
+    }
+    case TEXTURE_NOISE: {
+      return clamp(0.5 + 0.5 * fbm(p, texture.octaves), 0.0, 1.0);
+    }
+    case TEXTURE_TURBULENCE: {
+      return clamp(turbulence(p, texture.octaves), 0.0, 1.0);
+    }
+    case TEXTURE_MARBLE: {
+      return 0.5 + 0.5 * sin(p.z + 10.0 * turbulence(p, texture.octaves));
+    }
+    case TEXTURE_WORLEY: {
+      return clamp(worley(p), 0.0, 1.0);
+    }
+    case TEXTURE_GRADIENT: {
+      return clamp(p.y * 0.5 + 0.5, 0.0, 1.0);
+    }
+    default: {
+      return 0.0;
+    }
+  }
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+  let low = color / 12.92;
+  let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+  return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+fn miss(ray: Ray, bounce_pdf: f32) -> vec3<f32> {
+  let direction = normalize(ray.direction);
+  if sky.enabled == 1u {
+    var color = sky_radiance(direction);
+    // the sun is found by light sampling so it is only seen directly or in mirror reflections
+    if bounce_pdf == 0.0 {
+      color += sun_radiance(direction);
+    }
+    return color;
+  }
+  if environment.enabled == 1u {
+    return environment_radiance(direction);
+  }
+
+  let a = 0.5 * (1.0 - direction.y);
   let color = (1.0 - a) * ONE + a * vec3(0.5, 0.7, 1.0);
   return color;
 }
 
-fn scatter(ray: ptr<function, Ray>, rec: HitRecord, reflectance: ptr<function, f32>) -> vec3<f32> {
-  let material = materials[rec.material_index];
-  var color: vec3<f32> = material.color.xyz;
-  let local_reflectance = 0.90;
-  color *= (1.0 - local_reflectance) * (*reflectance);
-  *reflectance *= local_reflectance;
+// A principled bsdf of a clearcoat over a metal, transmission or the
+// dielectric base which reflects some light off its surface and scatters the
+// rest diffusely with a sheen. One lobe is picked by its weight so the weight
+// cancels, and the glossy lobes sample the visible normals of ggx so what is
+// left of f * cos / pdf is G2 / G1
+fn scatter(at: ScatterPoint) -> Scatter {
+  let material = at.material;
+  let color = material.color.xyz;
+  let wo = frame_to_local(at.frame, -at.incoming);
+  let lobes = lobe_weights(material, clamp(wo.z, 0.0, 1.0));
+  let coat_alpha = clearcoat_alpha(material);
+  let alpha = anisotropic_alpha(material.roughness, material.anisotropy);
 
-  let b = sin(4.0 * rec.normal.x * PI);
-  let c = cos(4.0 * rec.normal.y * PI);
-  let d = cos(4.0 * rec.normal.y * PI) + sin(4.0 * rec.normal.x * PI);
-
-  (*ray).origin = rec.point;
-  if rec.index % 2u == 0u {
-    (*ray).direction = reflection((*ray).direction, rec.normal);
+  var wi: vec3<f32>;
+  var weight: vec3<f32>;
+  var mirror = is_mirror(alpha);
+  let u = rand();
+  if u < lobes.clearcoat {
+    wi = reflect(-wo, sample_ggx_visible_normal(wo, coat_alpha));
+    weight = ONE * ggx_reflection_weight(wo, wi, coat_alpha);
+    mirror = is_mirror(coat_alpha);
+  } else if u < lobes.clearcoat + lobes.metal {
+    let h = sample_ggx_visible_normal(wo, alpha);
+    wi = reflect(-wo, h);
+    weight = color * conductor_tint(clamp(dot(wo, h), 0.0, 1.0), material) * ggx_reflection_weight(wo, wi, alpha);
+  } else if u < lobes.clearcoat + lobes.metal + lobes.transmission {
+    // reflects or refracts through the microfacet by its fresnel reflectance
+    let eta = dielectric_eta(at);
+    let h = sample_ggx_visible_normal(wo, alpha);
+    if rand() < fresnel_dielectric(clamp(dot(wo, h), 0.0, 1.0), eta) {
+      wi = reflect(-wo, h);
+      weight = ONE * ggx_reflection_weight(wo, wi, alpha);
+    } else {
+      wi = refract(-wo, h, 1.0 / eta);
+      let through = wi.z < 0.0 && any(wi != ZERO);
+      weight = color * select(0.0, smith_g2(wo, wi, alpha) / smith_g1(wo, alpha), through);
+    }
+  } else if u < 1.0 - lobes.diffuse {
+    wi = reflect(-wo, sample_ggx_visible_normal(wo, alpha));
+    weight = specular_color(material) * ggx_reflection_weight(wo, wi, alpha);
   } else {
-    (*ray).direction = rec.normal + random_unit_vector();
+    wi = vec3<f32>(0.0, 0.0, 1.0) + random_unit_vector();
+    // the random vector can cancel out the normal
+    if length_squared(wi) < 1e-8 {
+      wi = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    wi = normalize(wi);
+    weight = diffuse_brdf(material, wo, wi) * PI;
+    mirror = false;
+  }
+
+  let direction = frame_to_world(at.frame, wi);
+  let pdf = select(bsdf_pdf(at, direction), 0.0, mirror);
+  return Scatter(direction, weight, pdf, all(weight == ZERO));
+}
+
+// How often scatter picks each lobe, they add up to one
+struct Lobes {
+  clearcoat: f32,
+  metal: f32,
+  transmission: f32,
+  specular: f32,
+  diffuse: f32,
+}
+
+fn lobe_weights(material: Material, cos_theta: f32) -> Lobes {
+  let clearcoat = material.clearcoat * fresnel_dielectric(cos_theta, 1.5);
+  let metal = (1.0 - clearcoat) * material.metallic;
+  let dielectric = 1.0 - clearcoat - metal;
+  let transmission = dielectric * material.transmission;
+  let opaque = dielectric - transmission;
+  // specular 0.5 gives the reflectance of the index of refraction
+  let specular = opaque * min(fresnel_dielectric(cos_theta, material.ior) * material.specular * 2.0, 1.0);
+  return Lobes(clearcoat, metal, transmission, specular, opaque - specular);
+}
+
+// f * cos of every lobe that isn't a mirror, for light sampling
+fn bsdf_response(at: ScatterPoint, direction: vec3<f32>) -> vec3<f32> {
+  let material = at.material;
+  let wo = frame_to_local(at.frame, -at.incoming);
+  let wi = frame_to_local(at.frame, direction);
+  if wo.z <= 0.0 {
+    return ZERO;
+  }
+  let lobes = lobe_weights(material, wo.z);
+  let coat_alpha = clearcoat_alpha(material);
+  let alpha = anisotropic_alpha(material.roughness, material.anisotropy);
+  let eta = dielectric_eta(at);
+  if wi.z <= 0.0 {
+    if is_mirror(alpha) {
+      return ZERO;
+    }
+    return lobes.transmission * material.color.xyz * ggx_transmission(wo, wi, eta, alpha);
+  }
+
+  var response = lobes.diffuse * diffuse_brdf(material, wo, wi) * wi.z;
+  if !is_mirror(coat_alpha) {
+    response += lobes.clearcoat * ggx_reflection(wo, wi, coat_alpha);
+  }
+  if !is_mirror(alpha) {
+    let reflection = ggx_reflection(wo, wi, alpha);
+    let cos_h = dot(wo, normalize(wo + wi));
+    response += lobes.metal * material.color.xyz * conductor_tint(cos_h, material) * reflection;
+    response += lobes.transmission * fresnel_dielectric(cos_h, eta) * reflection;
+    response += lobes.specular * specular_color(material) * reflection;
+  }
+  return response;
+}
+
+// The pdf of scatter going in direction, the lobes weighted by how often
+// they are picked
+fn bsdf_pdf(at: ScatterPoint, direction: vec3<f32>) -> f32 {
+  let material = at.material;
+  let wo = frame_to_local(at.frame, -at.incoming);
+  let wi = frame_to_local(at.frame, direction);
+  if wo.z <= 0.0 {
+    return 0.0;
+  }
+  let lobes = lobe_weights(material, wo.z);
+  let coat_alpha = clearcoat_alpha(material);
+  let alpha = anisotropic_alpha(material.roughness, material.anisotropy);
+  let eta = dielectric_eta(at);
+  if wi.z <= 0.0 {
+    if is_mirror(alpha) {
+      return 0.0;
+    }
+    return lobes.transmission * ggx_transmission_pdf(wo, wi, eta, alpha);
+  }
+
+  let h = normalize(wo + wi);
+  var pdf = lobes.diffuse * wi.z / PI;
+  if !is_mirror(coat_alpha) {
+    pdf += lobes.clearcoat * ggx_reflection_pdf(wo, h, coat_alpha);
+  }
+  if !is_mirror(alpha) {
+    let reflected = lobes.transmission * fresnel_dielectric(dot(wo, h), eta);
+    pdf += (lobes.metal + lobes.specular + reflected) * ggx_reflection_pdf(wo, h, alpha);
+  }
+  return pdf;
+}
+
+// Lambertian with a Charlie sheen (Estevez and Kulla 2017) and the
+// visibility of Neubelt and Pettineo. The diffuse gives up the light the
+// sheen reflects so the albedo stays below one
+fn diffuse_brdf(material: Material, wo: vec3<f32>, wi: vec3<f32>) -> vec3<f32> {
+  let color = material.color.xyz;
+  let sheen = material.sheen * mix(ONE, normalized_color(color), material.sheen_tint);
+  let roughness = clamp(material.roughness, 0.25, 1.0);
+  let h = normalize(wo + wi);
+  let sin_h = sqrt(max(1.0 - h.z * h.z, 1e-8));
+  let distribution = (2.0 + 1.0 / roughness) * pow(sin_h, 1.0 / roughness) / (2.0 * PI);
+  let visibility = 1.0 / max(4.0 * (wi.z + wo.z - wi.z * wo.z), 1e-4);
+  let diffuse = color / PI * (ONE - sheen * sheen_albedo(wo.z, roughness));
+  return diffuse + sheen * distribution * visibility;
+}
+
+fn sheen_albedo(cos_theta: f32, roughness: f32) -> f32 {
+  let x = clamp(cos_theta, 0.0, 1.0) * 8.0;
+  let y = (roughness - 0.25) * 4.0;
+  let column = min(u32(x), 7u);
+  let row = min(u32(y), 2u);
+  let i = row * 9u + column;
+  let top = mix(sheen_albedo_table[i], sheen_albedo_table[i + 1u], x - f32(column));
+  let bottom = mix(sheen_albedo_table[i + 9u], sheen_albedo_table[i + 10u], x - f32(column));
+  return mix(top, bottom, y - f32(row));
+}
+
+// The color at a brightest channel of one, tints stay below one with it
+fn normalized_color(color: vec3<f32>) -> vec3<f32> {
+  return color / max(max(color.x, max(color.y, color.z)), 1e-4);
+}
+
+fn specular_color(material: Material) -> vec3<f32> {
+  return mix(ONE, normalized_color(material.color.xyz), material.specular_tint);
+}
+
+// The ratio of the index of refraction on the far side to the near side
+fn dielectric_eta(at: ScatterPoint) -> f32 {
+  return select(1.0 / at.material.ior, at.material.ior, at.front_face);
+}
+
+// Tangent, bitangent and normal with the tangent turned around the normal
+struct Frame {
+  tangent: vec3<f32>,
+  bitangent: vec3<f32>,
+  normal: vec3<f32>,
+}
+
+fn shading_frame(rec: HitRecord, rotation: f32) -> Frame {
+  let normal = rec.normal;
+  var tangent = rec.tangent.xyz - normal * dot(rec.tangent.xyz, normal);
+  if length_squared(tangent) < 1e-12 {
+    tangent = any_perpendicular(normal);
+  }
+  tangent = normalize(tangent);
+  let bitangent = cross(normal, tangent);
+  let angle = rotation * 2.0 * PI;
+  let turned = cos(angle) * tangent + sin(angle) * bitangent;
+  return Frame(turned, cross(normal, turned), normal);
+}
+
+fn frame_to_local(frame: Frame, v: vec3<f32>) -> vec3<f32> {
+  return vec3<f32>(dot(v, frame.tangent), dot(v, frame.bitangent), dot(v, frame.normal));
+}
+
+fn frame_to_world(frame: Frame, v: vec3<f32>) -> vec3<f32> {
+  return v.x * frame.tangent + v.y * frame.bitangent + v.z * frame.normal;
+}
+
+// ggx alphas along the tangent and bitangent like the Disney bsdf
+fn anisotropic_alpha(roughness: f32, anisotropy: f32) -> vec2<f32> {
+  let aspect = sqrt(1.0 - 0.9 * clamp(anisotropy, 0.0, 1.0));
+  let alpha = roughness * roughness;
+  return max(vec2<f32>(alpha / aspect, alpha * aspect), vec2<f32>(MIN_ALPHA));
+}
+
+fn clearcoat_alpha(material: Material) -> vec2<f32> {
+  return vec2<f32>(max(material.clearcoat_roughness * material.clearcoat_roughness, MIN_ALPHA));
+}
+
+fn is_mirror(alpha: vec2<f32>) -> bool {
+  return max(alpha.x, alpha.y) <= MIN_ALPHA;
+}
+
+// Anisotropic ggx distribution of microfacet normals in the local frame
+fn ggx_distribution(h: vec3<f32>, alpha: vec2<f32>) -> f32 {
+  let stretched = vec3<f32>(h.x / alpha.x, h.y / alpha.y, h.z);
+  let d = dot(stretched, stretched);
+  return 1.0 / (PI * alpha.x * alpha.y * d * d);
+}
+
+// f * cos of a ggx reflection without the fresnel, which the lobes apply
+fn ggx_reflection(wo: vec3<f32>, wi: vec3<f32>, alpha: vec2<f32>) -> f32 {
+  let h = normalize(wo + wi);
+  return ggx_distribution(h, alpha) * smith_g2(wo, wi, alpha) / (4.0 * wo.z);
+}
+
+// The visible normal pdf D_v(h) / (4 |wo.h|), where D_v(h) is G1 |wo.h| D / cos
+fn ggx_reflection_pdf(wo: vec3<f32>, h: vec3<f32>, alpha: vec2<f32>) -> f32 {
+  return smith_g1(wo, alpha) * ggx_distribution(h, alpha) / (4.0 * wo.z);
+}
+
+// f * cos / pdf of a sampled reflection, zero below the surface
+fn ggx_reflection_weight(wo: vec3<f32>, wi: vec3<f32>, alpha: vec2<f32>) -> f32 {
+  if wi.z <= 0.0 {
+    return 0.0;
+  }
+  return smith_g2(wo, wi, alpha) / smith_g1(wo, alpha);
+}
+
+// The microfacet that refracts wo into wi, facing up
+fn refraction_half_vector(wo: vec3<f32>, wi: vec3<f32>, eta: f32) -> vec3<f32> {
+  let h = normalize(wo + eta * wi);
+  return select(h, -h, h.z < 0.0);
+}
+
+// f * cos of the light refracted through a rough dielectric (Walter et al.
+// 2007) with its fresnel transmittance. Like the sampling it isn't scaled by
+// eta squared
+fn ggx_transmission(wo: vec3<f32>, wi: vec3<f32>, eta: f32, alpha: vec2<f32>) -> f32 {
+  let h = refraction_half_vector(wo, wi, eta);
+  let cos_o = dot(wo, h);
+  let cos_i = dot(wi, h);
+  if cos_o <= 0.0 || cos_i >= 0.0 {
+    return 0.0;
+  }
+  let denominator = cos_i + cos_o / eta;
+  let transmitted = 1.0 - fresnel_dielectric(cos_o, eta);
+  return transmitted * ggx_distribution(h, alpha) * smith_g2(wo, wi, alpha) * -cos_i * cos_o / (wo.z * denominator * denominator);
+}
+
+// The visible normal pdf times the jacobian |wi.h| / (wi.h + wo.h / eta)^2 of refraction
+fn ggx_transmission_pdf(wo: vec3<f32>, wi: vec3<f32>, eta: f32, alpha: vec2<f32>) -> f32 {
+  let h = refraction_half_vector(wo, wi, eta);
+  let cos_o = dot(wo, h);
+  let cos_i = dot(wi, h);
+  if cos_o <= 0.0 || cos_i >= 0.0 {
+    return 0.0;
+  }
+  let denominator = cos_i + cos_o / eta;
+  let transmitted = 1.0 - fresnel_dielectric(cos_o, eta);
+  let visible = smith_g1(wo, alpha) * cos_o * ggx_distribution(h, alpha) / wo.z;
+  return transmitted * visible * -cos_i / (denominator * denominator);
+}
+
+// Heitz 2018, a microfacet normal seen from wo in the local frame
+fn sample_ggx_visible_normal(wo: vec3<f32>, alpha: vec2<f32>) -> vec3<f32> {
+  let v = normalize(vec3<f32>(alpha.x * wo.x, alpha.y * wo.y, max(wo.z, 1e-4)));
+  let length_sq = v.x * v.x + v.y * v.y;
+  var t1 = RIGHT;
+  if length_sq > 0.0 {
+    t1 = vec3<f32>(-v.y, v.x, 0.0) / sqrt(length_sq);
+  }
+  let t2 = cross(v, t1);
+
+  let r = sqrt(rand());
+  let phi = 2.0 * PI * rand();
+  let p1 = r * cos(phi);
+  let s = 0.5 * (1.0 + v.z);
+  let p2 = (1.0 - s) * sqrt(max(1.0 - p1 * p1, 0.0)) + s * r * sin(phi);
+  let n = p1 * t1 + p2 * t2 + sqrt(max(1.0 - p1 * p1 - p2 * p2, 0.0)) * v;
+  return normalize(vec3<f32>(alpha.x * n.x, alpha.y * n.y, max(n.z, 1e-6)));
+}
+
+fn smith_lambda(w: vec3<f32>, alpha: vec2<f32>) -> f32 {
+  let a = alpha * w.xy;
+  return 0.5 * (sqrt(1.0 + dot(a, a) / max(w.z * w.z, 1e-8)) - 1.0);
+}
+
+fn smith_g1(w: vec3<f32>, alpha: vec2<f32>) -> f32 {
+  return 1.0 / (1.0 + smith_lambda(w, alpha));
+}
+
+fn smith_g2(wo: vec3<f32>, wi: vec3<f32>, alpha: vec2<f32>) -> f32 {
+  return 1.0 / (1.0 + smith_lambda(wo, alpha) + smith_lambda(wi, alpha));
+}
+
+// Unpolarized fresnel reflectance of a dielectric, one when all of it reflects
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+  let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+  if sin2_t >= 1.0 {
+    return 1.0;
+  }
+  let cos_t = sqrt(1.0 - sin2_t);
+  let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+  let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+  return 0.5 * (parallel * parallel + perpendicular * perpendicular);
+}
+
+// Unpolarized fresnel reflectance of a conductor with index of refraction eta + ik
+fn fresnel_conductor(cos_i: f32, eta: vec3<f32>, k: vec3<f32>) -> vec3<f32> {
+  let cos2 = cos_i * cos_i;
+  let sin2 = 1.0 - cos2;
+  let eta2 = eta * eta;
+  let k2 = k * k;
+
+  let t0 = eta2 - k2 - sin2;
+  let a2b2 = sqrt(t0 * t0 + 4.0 * eta2 * k2);
+  let t1 = a2b2 + cos2;
+  let a = sqrt(max(0.5 * (a2b2 + t0), ZERO));
+  let t2 = 2.0 * cos_i * a;
+  let perpendicular = (t1 - t2) / (t1 + t2);
+  let t3 = cos2 * a2b2 + sin2 * sin2;
+  let t4 = t2 * sin2;
+  let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+  return 0.5 * (parallel + perpendicular);
+}
+
+// The color of a metal is its reflectance straight on, the measured
+// constants say how that changes towards grazing angles
+fn conductor_tint(cos_theta: f32, material: Material) -> vec3<f32> {
+  if all(material.k.xyz == ZERO) {
+    return ONE;
+  }
+  let facing = fresnel_conductor(1.0, material.eta.xyz, material.k.xyz);
+  return fresnel_conductor(cos_theta, material.eta.xyz, material.k.xyz) / max(facing, vec3<f32>(1e-4));
+}
+
+// Next event estimation for a surface or a point in a medium
+fn sample_lights(at: ScatterPoint) -> vec3<f32> {
+  var color = sample_punctual_lights(at);
+  if sky.enabled == 1u {
+    return color + sample_sun(at);
+  }
+  if environment.enabled == 1u {
+    color += sample_environment_light(at);
+  }
+  return color;
+}
+
+// Every enabled point, spot and directional light with a shadow ray each
+fn sample_punctual_lights(at: ScatterPoint) -> vec3<f32> {
+  var color = ZERO;
+  for (var i = 0u; i < arrayLength(&lights); i++) {
+    let light = lights[i];
+    if light.enabled == 0u {
+      continue;
+    }
+
+    var direction = -light.direction;
+    var distance = RAY_TMAX;
+    var radiance = light.color * light.intensity;
+    if light.kind != LIGHT_DIRECTIONAL {
+      let to_light = light.position - at.point;
+      distance = length(to_light);
+      direction = to_light / distance;
+      radiance /= distance * distance;
+    }
+    if light.kind == LIGHT_SPOT {
+      radiance *= smoothstep(light.cos_outer, light.cos_inner, dot(-direction, light.direction));
+    }
+
+    let response = scatter_response(at, direction);
+    if all(response == ZERO) || all(radiance == ZERO) {
+      continue;
+    }
+    color += response * radiance * visibility(Ray(at.point, direction), distance);
   }
   return color;
 }
 
+fn sample_environment_light(at: ScatterPoint) -> vec3<f32> {
+  let light = sample_environment();
+  let response = scatter_response(at, light.direction);
+  if all(response == ZERO) || light.pdf <= 0.0 {
+    return ZERO;
+  }
+
+  let weight = power_heuristic(light.pdf, scatter_pdf(at, light.direction));
+  return response * light.radiance * weight / light.pdf * visibility(Ray(at.point, light.direction), RAY_TMAX);
+}
+
+// The bsdf times the cosine or the phase function of the medium
+fn scatter_response(at: ScatterPoint, direction: vec3<f32>) -> vec3<f32> {
+  if all(at.normal == ZERO) {
+    return at.albedo * phase(dot(at.incoming, direction), at.anisotropy);
+  }
+  return bsdf_response(at, direction);
+}
+
+// How likely the bounce after at is to go in direction
+fn scatter_pdf(at: ScatterPoint, direction: vec3<f32>) -> f32 {
+  if all(at.normal == ZERO) {
+    return phase(dot(at.incoming, direction), at.anisotropy);
+  }
+  return bsdf_pdf(at, direction);
+}
+
+// How much light makes it through the media without hitting anything
+fn visibility(ray: Ray, max_distance: f32) -> f32 {
+  if trace_range(ray, max_distance).hit {
+    return 0.0;
+  }
+  return media_transmittance(ray, max_distance);
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+  let a = pdf * pdf;
+  let b = other_pdf * other_pdf;
+  return a / (a + b);
+}
+
+// Environment hits after a bounce that isn't a mirror could also have been
+// found by light sampling so they share the contribution
+fn environment_mis_weight(direction: vec3<f32>, bounce_pdf: f32) -> f32 {
+  if sky.enabled == 1u || environment.enabled == 0u || bounce_pdf <= 0.0 {
+    return 1.0;
+  }
+  return power_heuristic(bounce_pdf, environment_pdf(direction));
+}
+
+// The sun is a directional light so the shadows are sharp
+fn sample_sun(at: ScatterPoint) -> vec3<f32> {
+  let response = scatter_response(at, sky.sun_direction);
+  if all(response == ZERO) || all(sky.sun_irradiance == ZERO) {
+    return ZERO;
+  }
+
+  return response * sky.sun_irradiance * visibility(Ray(at.point, sky.sun_direction), RAY_TMAX);
+}
+
+// MEDIA Stuff
+// Samples a free flight distance in the fog and in every volume and keeps
+// the closest, the ray direction has to be normalized
+fn sample_media(ray: Ray, max_distance: f32) -> MediumEvent {
+  var event = MediumEvent(false, max_distance, ONE, 0.0);
+  if media.fog_density > 0.0 {
+    let distance = sample_fog(ray, -log(1.0 - rand()));
+    if distance < event.distance {
+      event = MediumEvent(true, distance, media.fog_color, media.fog_anisotropy);
+    }
+  }
+
+  for (var i = 0u; i < media.volume_count; i++) {
+    let volume = media.volumes[i];
+    let span = volume_span(ray, volume);
+    let distance = max(span.x, 0.0) - log(1.0 - rand()) / volume.density;
+    if distance < span.y && distance < event.distance {
+      event = MediumEvent(true, distance, volume.color, volume.anisotropy);
+    }
+  }
+
+  if media.grid_majorant > 0.0 {
+    let distance = sample_grid(ray, event.distance);
+    if distance < event.distance {
+      event = MediumEvent(true, distance, media.grid_color, media.grid_anisotropy);
+    }
+  }
+  return event;
+}
+
+// The density of the fog along the ray is a * exp(-b * t)
+fn fog_coefficients(ray: Ray) -> vec2<f32> {
+  let exponent = clamp(-media.fog_falloff * (ray.origin.y - media.fog_height), -80.0, 80.0);
+  return vec2<f32>(media.fog_density * exp(exponent), media.fog_falloff * ray.direction.y);
+}
+
+// Where the optical depth along the ray reaches depth, RAY_TMAX when the fog thins out first
+fn sample_fog(ray: Ray, depth: f32) -> f32 {
+  let c = fog_coefficients(ray);
+  if abs(c.y) < 1e-5 {
+    return depth / c.x;
+  }
+  let x = 1.0 - depth * c.y / c.x;
+  if x <= 0.0 {
+    return RAY_TMAX;
+  }
+  return -log(x) / c.y;
+}
+
+fn fog_optical_depth(ray: Ray, distance: f32) -> f32 {
+  if media.fog_density <= 0.0 {
+    return 0.0;
+  }
+  let c = fog_coefficients(ray);
+  if abs(c.y) < 1e-5 {
+    return c.x * distance;
+  }
+  return c.x * (1.0 - exp(-c.y * distance)) / c.y;
+}
+
+fn media_transmittance(ray: Ray, max_distance: f32) -> f32 {
+  var depth = fog_optical_depth(ray, max_distance);
+  for (var i = 0u; i < media.volume_count; i++) {
+    let volume = media.volumes[i];
+    let span = volume_span(ray, volume);
+    depth += volume.density * max(min(span.y, max_distance) - max(span.x, 0.0), 0.0);
+  }
+  return exp(-depth) * grid_transmittance(ray, max_distance);
+}
+
+// Where the ray enters and leaves the volume, empty when x > y
+fn volume_span(ray: Ray, volume: Volume) -> vec2<f32> {
+  let oc = ray.origin - volume.center;
+  if volume.kind == VOLUME_SPHERE {
+    let half_b = dot(oc, ray.direction);
+    let c = length_squared(oc) - volume.size.x * volume.size.x;
+    let discriminant = half_b * half_b - c;
+    if discriminant < 0.0 {
+      return vec2<f32>(1.0, 0.0);
+    }
+    let sqrtd = sqrt(discriminant);
+    return vec2<f32>(-half_b - sqrtd, -half_b + sqrtd);
+  }
+
+  return box_span(ray, volume.center - volume.size, volume.center + volume.size);
+}
+
+fn box_span(ray: Ray, box_min: vec3<f32>, box_max: vec3<f32>) -> vec2<f32> {
+  let inv_direction = 1.0 / ray.direction;
+  let t0 = (box_min - ray.origin) * inv_direction;
+  let t1 = (box_max - ray.origin) * inv_direction;
+  let near = min(t0, t1);
+  let far = max(t0, t1);
+  return vec2<f32>(max(max(near.x, near.y), near.z), min(min(far.x, far.y), far.z));
+}
+
+// Delta tracking, steps through the grid as if it was filled with the
+// majorant and keeps a step with the chance that the real density is there
+fn sample_grid(ray: Ray, max_distance: f32) -> f32 {
+  let span = box_span(ray, media.grid_min, media.grid_max);
+  let end = min(span.y, max_distance);
+  var t = max(span.x, 0.0);
+  for (var i = 0u; i < GRID_MAX_STEPS; i++) {
+    t -= log(1.0 - rand()) / media.grid_majorant;
+    if t >= end {
+      break;
+    }
+    if rand() * media.grid_majorant < grid_density(ray_at(ray, t)) {
+      return t;
+    }
+  }
+  return RAY_TMAX;
+}
+
+// Ratio tracking, the same steps as delta tracking but every one lets
+// through the part of the light the real density doesn't stop
+fn grid_transmittance(ray: Ray, max_distance: f32) -> f32 {
+  if media.grid_majorant <= 0.0 {
+    return 1.0;
+  }
+  let span = box_span(ray, media.grid_min, media.grid_max);
+  let end = min(span.y, max_distance);
+  var t = max(span.x, 0.0);
+  var transmittance = 1.0;
+  for (var i = 0u; i < GRID_MAX_STEPS && transmittance > 1e-3; i++) {
+    t -= log(1.0 - rand()) / media.grid_majorant;
+    if t >= end {
+      break;
+    }
+    transmittance *= 1.0 - grid_density(ray_at(ray, t)) / media.grid_majorant;
+  }
+  return transmittance;
+}
+
+// Trilinear interpolation between the voxels, the texture can't be filtered
+fn grid_density(point: vec3<f32>) -> f32 {
+  let size = vec3<i32>(textureDimensions(density_grid));
+  let local = (point - media.grid_min) / (media.grid_max - media.grid_min);
+  let voxel = local * vec3<f32>(size) - 0.5;
+  let base = floor(voxel);
+  let f = voxel - base;
+
+  var density = 0.0;
+  for (var i = 0u; i < 8u; i++) {
+    let corner = vec3<i32>(i32(i & 1u), i32((i >> 1u) & 1u), i32((i >> 2u) & 1u));
+    let weight = mix(1.0 - f, f, vec3<f32>(corner));
+    let index = clamp(vec3<i32>(base) + corner, vec3<i32>(0), size - 1);
+    density += weight.x * weight.y * weight.z * textureLoad(density_grid, index, 0).x;
+  }
+  return density * media.grid_density;
+}
+
+// Henyey-Greenstein, cos_theta is between where the path was going and where it goes next
+fn phase(cos_theta: f32, g: f32) -> f32 {
+  let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+  return (1.0 - g * g) / (4.0 * PI * denom * sqrt(denom));
+}
+
+fn sample_phase(direction: vec3<f32>, g: f32) -> vec3<f32> {
+  var cos_theta = 1.0 - 2.0 * rand();
+  if abs(g) > 1e-3 {
+    let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * rand());
+    cos_theta = (1.0 + g * g - s * s) / (2.0 * g);
+  }
+  let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+  let phi = 2.0 * PI * rand();
+  let tangent = normalize(any_perpendicular(direction));
+  let bitangent = cross(direction, tangent);
+  return normalize(cos_theta * direction + sin_theta * (cos(phi) * tangent + sin(phi) * bitangent));
+}
+
+// SKY Stuff
+// Preetham, Shirley and Smits, A Practical Analytic Model for Daylight
+fn sky_radiance(direction: vec3<f32>) -> vec3<f32> {
+  // below the horizon looks like the horizon
+  let cos_theta = max(direction.y, 0.01);
+  let cos_gamma = clamp(dot(direction, sky.sun_direction), -1.0, 1.0);
+  let gamma = acos(cos_gamma);
+
+  let p = sky.perez;
+  let perez = (1.0 + p[0].xyz * exp(p[1].xyz / cos_theta))
+    * (1.0 + p[2].xyz * exp(p[3].xyz * gamma) + p[4].xyz * cos_gamma * cos_gamma);
+  let yxy = sky.zenith * perez;
+  return max(yxy_to_rgb(yxy), ZERO) * sky.fade;
+}
+
+fn sun_radiance(direction: vec3<f32>) -> vec3<f32> {
+  if dot(direction, sky.sun_direction) < sky.sun_cos_radius {
+    return ZERO;
+  }
+  let solid_angle = 2.0 * PI * (1.0 - sky.sun_cos_radius);
+  return sky.sun_irradiance / solid_angle;
+}
+
+fn yxy_to_rgb(yxy: vec3<f32>) -> vec3<f32> {
+  let y = yxy.x;
+  let xyz = vec3<f32>(yxy.y / yxy.z * y, y, (1.0 - yxy.y - yxy.z) / yxy.z * y);
+  return vec3<f32>(
+     3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+    -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+     0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+  );
+}
+
+// ENVIRONMENT Stuff
+fn environment_uv(direction: vec3<f32>) -> vec2<f32> {
+  let phi = atan2(direction.x, -direction.z) + environment.rotation;
+  let u = fract(phi / (2.0 * PI) + 0.5);
+  let v = acos(clamp(direction.y, -1.0, 1.0)) / PI;
+  return vec2<f32>(u, v);
+}
+
+fn environment_direction(env_uv: vec2<f32>) -> vec3<f32> {
+  let phi = (env_uv.x - 0.5) * 2.0 * PI - environment.rotation;
+  let theta = env_uv.y * PI;
+  return vec3<f32>(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+}
+
+fn environment_pixel(env_uv: vec2<f32>) -> vec2<u32> {
+  let dim = vec2<u32>(environment.width, environment.height);
+  return min(vec2<u32>(env_uv * vec2<f32>(dim)), dim - 1u);
+}
+
+fn environment_radiance(direction: vec3<f32>) -> vec3<f32> {
+  let pixel = environment_pixel(environment_uv(direction));
+  return textureLoad(env_texture, pixel, 0).xyz * environment.intensity;
+}
+
+// The per pixel pdfs are stored after the marginal and the row cdfs
+fn environment_pdf_offset() -> u32 {
+  return environment.height + 1u + environment.height * (environment.width + 1u);
+}
+
+// Solid angle pdf of light sampling picking this direction
+fn environment_pdf(direction: vec3<f32>) -> f32 {
+  let env_uv = environment_uv(direction);
+  let sin_theta = sin(env_uv.y * PI);
+  if sin_theta <= 0.0 {
+    return 0.0;
+  }
+
+  let pixel = environment_pixel(env_uv);
+  let pdf_uv = env_distribution[environment_pdf_offset() + pixel.y * environment.width + pixel.x];
+  return pdf_uv / (2.0 * PI * PI * sin_theta);
+}
+
+// Picks a direction proportional to the brightness of the environment
+fn sample_environment() -> LightSample {
+  let width = environment.width;
+  let height = environment.height;
+  let row = sample_cdf(0u, height, rand());
+  let col = sample_cdf(height + 1u + row.index * (width + 1u), width, rand());
+
+  let env_uv = vec2<f32>(
+    (f32(col.index) + col.offset) / f32(width),
+    (f32(row.index) + row.offset) / f32(height),
+  );
+  let sin_theta = sin(env_uv.y * PI);
+  if sin_theta <= 0.0 {
+    return LightSample(UP, ZERO, 0.0);
+  }
+
+  let pdf_uv = env_distribution[environment_pdf_offset() + row.index * width + col.index];
+  let radiance = textureLoad(env_texture, vec2<u32>(col.index, row.index), 0).xyz * environment.intensity;
+  return LightSample(environment_direction(env_uv), radiance, pdf_uv / (2.0 * PI * PI * sin_theta));
+}
+
+// Binary search for the bucket of a cdf with `count` buckets that holds x
+fn sample_cdf(offset: u32, count: u32, x: f32) -> CdfSample {
+  var low = 0u;
+  var high = count - 1u;
+  while low < high {
+    let mid = (low + high + 1u) / 2u;
+    if env_distribution[offset + mid] <= x {
+      low = mid;
+    } else {
+      high = mid - 1u;
+    }
+  }
+
+  let start = env_distribution[offset + low];
+  let end = env_distribution[offset + low + 1u];
+  var t = 0.0;
+  if end > start {
+    t = clamp((x - start) / (end - start), 0.0, 1.0);
+  }
+  return CdfSample(low, t);
+}
+
 fn reflection(dir: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
   return dir - 2.0 * dot(dir, normal) * normal;
 }
@@ -180,23 +1447,491 @@ fn get_random_ray(pixel_center: vec3<f32>, pixel_delta: vec3<f32>, ray_index: u3
 }
 
 fn trace(ray: Ray) -> HitRecord {
+  return trace_range(ray, RAY_TMAX);
+}
+
+// Only hits closer than ray_tmax, for shadow rays towards lights
+fn trace_range(ray: Ray, ray_tmax: f32) -> HitRecord {
   var rec: HitRecord;
   var temp_rec: HitRecord;
-  var closest_so_far = RAY_TMAX;
 
-  for (var i = 0u; i < arrayLength(&objects.spheres); i++) {
-    let sphere = objects.spheres[i];
-    if hit_sphere(ray, sphere, RAY_TMIN, closest_so_far, &temp_rec) {
-      closest_so_far = temp_rec.t;
-      rec = temp_rec;
-      rec.index = i;
-      rec.hit = true;
-    }
+  // Every object is placed by a node of the scene graph so they are all instances
+  if hit_instances(ray, RAY_TMIN, ray_tmax, &temp_rec) {
+    rec = temp_rec;
+    rec.hit = true;
   }
 
   return rec;
 }
 
+// Walks the bvh over the instances starting at the first node
+fn hit_instances(ray: Ray, ray_tmin: f32, ray_tmax: f32, rec: ptr<function, HitRecord>) -> bool {
+  var stack: array<u32, BVH_STACK_SIZE>;
+  var stack_len = 1u;
+  stack[0] = 0u;
+  var closest_so_far = ray_tmax;
+  var hit_anything = false;
+  let inv_direction = 1.0 / ray.direction;
+
+  while stack_len > 0u {
+    stack_len--;
+    let node = bvh[stack[stack_len]];
+    if !hit_aabb(ray.origin, inv_direction, node.min, node.max, ray_tmin, closest_so_far) {
+      continue;
+    }
+
+    if node.count > 0u {
+      for (var i = node.left_or_first; i < node.left_or_first + node.count; i++) {
+        if hit_instance(ray, instances[i], ray_tmin, closest_so_far, rec) {
+          closest_so_far = (*rec).t;
+          hit_anything = true;
+        }
+      }
+    } else if stack_len + 2u <= BVH_STACK_SIZE {
+      stack[stack_len] = node.left_or_first;
+      stack[stack_len + 1u] = node.left_or_first + 1u;
+      stack_len += 2u;
+    }
+  }
+
+  return hit_anything;
+}
+
+// Hits the object with the ray in its own space, the direction isn't
+// normalized again so t is the same in both spaces
+fn hit_instance(ray: Ray, instance: Instance, ray_tmin: f32, ray_tmax: f32, rec: ptr<function, HitRecord>) -> bool {
+  let local = Ray(
+    (instance.world_to_object * vec4<f32>(ray.origin, 1.0)).xyz,
+    (instance.world_to_object * vec4<f32>(ray.direction, 0.0)).xyz,
+  );
+  var hit = false;
+  switch instance.kind {
+    case INSTANCE_MESH: {
+      hit = hit_mesh(local, instance.index, ray_tmin, ray_tmax, rec);
+    }
+    case INSTANCE_SPHERE: {
+      hit = hit_sphere(local, objects.spheres[instance.index], ray_tmin, ray_tmax, rec);
+    }
+    case INSTANCE_PRIMITIVE: {
+      hit = hit_primitive(local, primitives[instance.index], ray_tmin, ray_tmax, rec);
+    }
+    default: {}
+  }
+  if !hit {
+    return false;
+  }
+
+  // Normals go through the inverse transpose, which keeps which side they face
+  let world_to_object = mat3x3<f32>(instance.world_to_object[0].xyz, instance.world_to_object[1].xyz, instance.world_to_object[2].xyz);
+  (*rec).point = ray_at(ray, (*rec).t);
+  (*rec).normal = normalize((*rec).normal * world_to_object);
+  (*rec).tangent = vec4<f32>((instance.object_to_world * vec4<f32>((*rec).tangent.xyz, 0.0)).xyz, (*rec).tangent.w);
+  if instance.material != NO_MATERIAL {
+    (*rec).material_index = instance.material;
+  }
+  return true;
+}
+
+// Walks the bvh over all triangles and keeps the closest hit
+fn hit_mesh(ray: Ray, root: u32, ray_tmin: f32, ray_tmax: f32, rec: ptr<function, HitRecord>) -> bool {
+  var stack: array<u32, BVH_STACK_SIZE>;
+  var stack_len = 1u;
+  stack[0] = root;
+  var closest_so_far = ray_tmax;
+  var hit_anything = false;
+  let inv_direction = 1.0 / ray.direction;
+
+  while stack_len > 0u {
+    stack_len--;
+    let node = bvh[stack[stack_len]];
+    if !hit_aabb(ray.origin, inv_direction, node.min, node.max, ray_tmin, closest_so_far) {
+      continue;
+    }
+
+    if node.count > 0u {
+      for (var i = node.left_or_first; i < node.left_or_first + node.count; i++) {
+        if hit_triangle(ray, i, ray_tmin, closest_so_far, rec) {
+          closest_so_far = (*rec).t;
+          hit_anything = true;
+        }
+      }
+    } else if stack_len + 2u <= BVH_STACK_SIZE {
+      stack[stack_len] = node.left_or_first;
+      stack[stack_len + 1u] = node.left_or_first + 1u;
+      stack_len += 2u;
+    }
+  }
+
+  return hit_anything;
+}
+
+// Slab test, true when the box overlaps the part of the ray between tmin and tmax
+fn hit_aabb(origin: vec3<f32>, inv_direction: vec3<f32>, box_min: vec3<f32>, box_max: vec3<f32>, ray_tmin: f32, ray_tmax: f32) -> bool {
+  let t0 = (box_min - origin) * inv_direction;
+  let t1 = (box_max - origin) * inv_direction;
+  let near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), max(min(t0.z, t1.z), ray_tmin));
+  let far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), min(max(t0.z, t1.z), ray_tmax));
+  return near <= far;
+}
+
+// Möller-Trumbore
+fn hit_triangle(ray: Ray, index: u32, ray_tmin: f32, ray_tmax: f32, rec: ptr<function, HitRecord>) -> bool {
+  let triangle = triangles[index];
+  let v0 = vertices[triangle.vertices.x];
+  let v1 = vertices[triangle.vertices.y];
+  let v2 = vertices[triangle.vertices.z];
+
+  let edge1 = v1.position - v0.position;
+  let edge2 = v2.position - v0.position;
+  let p = cross(ray.direction, edge2);
+  let det = dot(edge1, p);
+  if abs(det) < 1e-12 {
+    return false;
+  }
+
+  let inv_det = 1.0 / det;
+  let s = ray.origin - v0.position;
+  let b1 = dot(s, p) * inv_det;
+  if b1 < 0.0 || b1 > 1.0 {
+    return false;
+  }
+  let q = cross(s, edge1);
+  let b2 = dot(ray.direction, q) * inv_det;
+  if b2 < 0.0 || b1 + b2 > 1.0 {
+    return false;
+  }
+  let t = dot(edge2, q) * inv_det;
+  if t <= ray_tmin || ray_tmax <= t {
+    return false;
+  }
+
+  let b0 = 1.0 - b1 - b2;
+  var normal = normalize(cross(edge1, edge2));
+  let shading_normal = b0 * v0.normal + b1 * v1.normal + b2 * v2.normal;
+  if length_squared(shading_normal) > 0.0 {
+    normal = normalize(shading_normal);
+  }
+
+  (*rec).t = t;
+  (*rec).point = ray_at(ray, t);
+  set_face_normal(rec, ray, normal);
+  (*rec).material_index = triangle.material_index;
+  (*rec).index = index;
+  (*rec).tex_coords = b0 * vec2<f32>(v0.u, v0.v) + b1 * vec2<f32>(v1.u, v1.v) + b2 * vec2<f32>(v2.u, v2.v);
+  (*rec).tangent = b0 * v0.tangent + b1 * v1.tangent + b2 * v2.tangent;
+  (*rec).tangent.w = sign((*rec).tangent.w);
+  (*rec).color = b0 * v0.color + b1 * v1.color + b2 * v2.color;
+  return true;
+}
+
+// The transforms are rigid so t is the same in local space
+fn hit_primitive(ray: Ray, primitive: Primitive, ray_tmin: f32, ray_tmax: f32, rec: ptr<function, HitRecord>) -> bool {
+  var best = LocalHit(ray_tmax, ZERO, vec2<f32>(0.0), ZERO);
+  if primitive.kind == PRIMITIVE_QUAD {
+    hit_quad(ray, primitive, ray_tmin, &best);
+  } else {
+    let offset = ray.origin - primitive.origin;
+    let local = Ray(to_local(primitive, offset), to_local(primitive, ray.direction));
+    switch primitive.kind {
+      case PRIMITIVE_PLANE: {
+        hit_plane(local, ray_tmin, &best);
+      }
+      case PRIMITIVE_BOX: {
+        hit_box(local, primitive.size * 0.5, ray_tmin, &best);
+      }
+      case PRIMITIVE_DISK: {
+        hit_disk(local, primitive.radius, ray_tmin, &best);
+      }
+      case PRIMITIVE_CYLINDER: {
+        hit_cylinder(local, primitive.radius, primitive.size.y * 0.5, ray_tmin, &best);
+      }
+      case PRIMITIVE_CONE: {
+        hit_cone(local, primitive.radius, primitive.size.y, ray_tmin, &best);
+      }
+      case PRIMITIVE_TORUS: {
+        hit_torus(local, primitive.radius, primitive.minor_radius, ray_tmin, &best);
+      }
+      default: {}
+    }
+    best.normal = to_world(primitive, best.normal);
+    best.tangent = to_world(primitive, best.tangent);
+  }
+  if best.t >= ray_tmax {
+    return false;
+  }
+
+  (*rec).t = best.t;
+  (*rec).point = ray_at(ray, best.t);
+  set_face_normal(rec, ray, normalize(best.normal));
+  (*rec).material_index = primitive.material_index;
+  (*rec).tex_coords = best.tex_coords;
+  (*rec).tangent = vec4<f32>(best.tangent, 1.0);
+  (*rec).color = vec3<f32>(1.0);
+  return true;
+}
+
+fn to_local(primitive: Primitive, v: vec3<f32>) -> vec3<f32> {
+  return vec3<f32>(dot(v, primitive.x_axis), dot(v, primitive.y_axis), dot(v, primitive.z_axis));
+}
+
+fn to_world(primitive: Primitive, v: vec3<f32>) -> vec3<f32> {
+  return v.x * primitive.x_axis + v.y * primitive.y_axis + v.z * primitive.z_axis;
+}
+
+// Replaces the best hit when t is in front of it and past ray_tmin
+fn keep_closer(best: ptr<function, LocalHit>, t: f32, ray_tmin: f32, normal: vec3<f32>, tex_coords: vec2<f32>, tangent: vec3<f32>) {
+  if t > ray_tmin && t < (*best).t {
+    *best = LocalHit(t, normal, tex_coords, tangent);
+  }
+}
+
+// The textures repeat every unit across the plane
+fn hit_plane(ray: Ray, ray_tmin: f32, best: ptr<function, LocalHit>) {
+  if abs(ray.direction.y) < 1e-12 {
+    return;
+  }
+  let t = -ray.origin.y / ray.direction.y;
+  let p = ray_at(ray, t);
+  keep_closer(best, t, ray_tmin, UP, p.xz, RIGHT);
+}
+
+fn hit_disk(ray: Ray, radius: f32, ray_tmin: f32, best: ptr<function, LocalHit>) {
+  if abs(ray.direction.y) < 1e-12 {
+    return;
+  }
+  let t = -ray.origin.y / ray.direction.y;
+  let p = ray_at(ray, t);
+  if dot(p.xz, p.xz) <= radius * radius {
+    keep_closer(best, t, ray_tmin, UP, p.xz / (2.0 * radius) + 0.5, RIGHT);
+  }
+}
+
+// The edges are the x and y axes, which don't have to be at a right angle
+fn hit_quad(ray: Ray, quad: Primitive, ray_tmin: f32, best: ptr<function, LocalHit>) {
+  let n = cross(quad.x_axis, quad.y_axis);
+  let denom = dot(quad.z_axis, ray.direction);
+  if abs(denom) < 1e-12 {
+    return;
+  }
+  let t = dot(quad.z_axis, quad.origin - ray.origin) / denom;
+  let offset = ray_at(ray, t) - quad.origin;
+  let w = n / dot(n, n);
+  let alpha = dot(w, cross(offset, quad.y_axis));
+  let beta = dot(w, cross(quad.x_axis, offset));
+  if alpha < 0.0 || alpha > 1.0 || beta < 0.0 || beta > 1.0 {
+    return;
+  }
+  // the image is upright along the y edge
+  keep_closer(best, t, ray_tmin, quad.z_axis, vec2<f32>(alpha, 1.0 - beta), normalize(quad.x_axis));
+}
+
+// Each face shows the whole texture upright, the top and bottom like a plane
+fn hit_box(ray: Ray, half_size: vec3<f32>, ray_tmin: f32, best: ptr<function, LocalHit>) {
+  let inv_direction = 1.0 / ray.direction;
+  let t0 = (-half_size - ray.origin) * inv_direction;
+  let t1 = (half_size - ray.origin) * inv_direction;
+  let near = min(t0, t1);
+  let far = max(t0, t1);
+  let t_near = max(max(near.x, near.y), near.z);
+  let t_far = min(min(far.x, far.y), far.z);
+  if t_near > t_far {
+    return;
+  }
+
+  var t = t_near;
+  if t <= ray_tmin {
+    t = t_far;
+  }
+  let p = ray_at(ray, t);
+  let d = abs(p / half_size);
+  var normal = vec3<f32>(0.0, sign(p.y), 0.0);
+  var bitangent = FORWARD;
+  if d.x >= d.y && d.x >= d.z {
+    normal = vec3<f32>(sign(p.x), 0.0, 0.0);
+    bitangent = UP;
+  } else if d.z >= d.y {
+    normal = vec3<f32>(0.0, 0.0, sign(p.z));
+    bitangent = UP;
+  }
+  let tangent = cross(bitangent, normal);
+  let tex_coords = vec2<f32>(
+    dot(p, tangent) / (2.0 * dot(abs(tangent), half_size)) + 0.5,
+    -dot(p, bitangent) / (2.0 * dot(abs(bitangent), half_size)) + 0.5,
+  );
+  keep_closer(best, t, ray_tmin, normal, tex_coords, tangent);
+}
+
+// u goes around like on spheres and v from the top down, the caps are like disks
+fn hit_cylinder(ray: Ray, radius: f32, half_height: f32, ray_tmin: f32, best: ptr<function, LocalHit>) {
+  let a = dot(ray.direction.xz, ray.direction.xz);
+  let half_b = dot(ray.origin.xz, ray.direction.xz);
+  let c = dot(ray.origin.xz, ray.origin.xz) - radius * radius;
+  let discriminant = half_b * half_b - a * c;
+  if a > 1e-12 && discriminant >= 0.0 {
+    let sqrtd = sqrt(discriminant);
+    for (var i = 0; i < 2; i++) {
+      let t = (-half_b + select(-sqrtd, sqrtd, i == 1)) / a;
+      let p = ray_at(ray, t);
+      if abs(p.y) <= half_height {
+        let normal = vec3<f32>(p.x, 0.0, p.z) / radius;
+        let tex_coords = vec2<f32>(sphere_tex_coords(normal).x, (half_height - p.y) / (2.0 * half_height));
+        keep_closer(best, t, ray_tmin, normal, tex_coords, sphere_tangent(normal));
+      }
+    }
+  }
+  hit_cap(ray, radius, half_height, 1.0, ray_tmin, best);
+  hit_cap(ray, radius, -half_height, -1.0, ray_tmin, best);
+}
+
+// A disk at height facing up or down with side
+fn hit_cap(ray: Ray, radius: f32, height: f32, side: f32, ray_tmin: f32, best: ptr<function, LocalHit>) {
+  if abs(ray.direction.y) < 1e-12 {
+    return;
+  }
+  let t = (height - ray.origin.y) / ray.direction.y;
+  let p = ray_at(ray, t);
+  if dot(p.xz, p.xz) <= radius * radius {
+    let tex_coords = vec2<f32>(p.x, p.z * side) / (2.0 * radius) + 0.5;
+    keep_closer(best, t, ray_tmin, vec3<f32>(0.0, side, 0.0), tex_coords, RIGHT);
+  }
+}
+
+// Base at y = 0 and the tip at y = height
+fn hit_cone(ray: Ray, radius: f32, height: f32, ray_tmin: f32, best: ptr<function, LocalHit>) {
+  let k = radius / height;
+  let k2 = k * k;
+  let from_tip = height - ray.origin.y;
+  let a = dot(ray.direction.xz, ray.direction.xz) - k2 * ray.direction.y * ray.direction.y;
+  let half_b = dot(ray.origin.xz, ray.direction.xz) + k2 * from_tip * ray.direction.y;
+  let c = dot(ray.origin.xz, ray.origin.xz) - k2 * from_tip * from_tip;
+
+  var roots = vec2<f32>(-1.0);
+  if abs(a) < 1e-9 {
+    // parallel to the slope, there is only one root
+    if abs(half_b) > 1e-12 {
+      roots.x = -c / (2.0 * half_b);
+    }
+  } else {
+    let discriminant = half_b * half_b - a * c;
+    if discriminant >= 0.0 {
+      let sqrtd = sqrt(discriminant);
+      roots = vec2<f32>(-half_b - sqrtd, -half_b + sqrtd) / a;
+    }
+  }
+  for (var i = 0; i < 2; i++) {
+    let t = roots[i];
+    let p = ray_at(ray, t);
+    if p.y >= 0.0 && p.y <= height {
+      let normal = normalize(vec3<f32>(p.x, k2 * (height - p.y), p.z));
+      let tex_coords = vec2<f32>(sphere_tex_coords(normalize(vec3<f32>(p.x, 0.0, p.z))).x, 1.0 - p.y / height);
+      keep_closer(best, t, ray_tmin, normal, tex_coords, sphere_tangent(normal));
+    }
+  }
+  hit_cap(ray, radius, 0.0, -1.0, ray_tmin, best);
+}
+
+// The quartic solved like in Inigo Quilez' torus intersector, with the
+// origin moved onto the bounding sphere to keep the precision
+fn hit_torus(ray: Ray, radius: f32, minor_radius: f32, ray_tmin: f32, best: ptr<function, LocalHit>) {
+  let scale = length(ray.direction);
+  let direction = ray.direction / scale;
+  let bound = radius + minor_radius;
+  let n0 = dot(ray.origin, direction);
+  let h0 = n0 * n0 - dot(ray.origin, ray.origin) + bound * bound;
+  if h0 < 0.0 {
+    return;
+  }
+  let start = max(-n0 - sqrt(h0), 0.0);
+  // the solver has the ring around z
+  let ro = (ray.origin + direction * start).xzy;
+  let rd = direction.xzy;
+
+  var po = 1.0;
+  let ra2 = radius * radius;
+  let rb2 = minor_radius * minor_radius;
+  let m = dot(ro, ro);
+  let n = dot(ro, rd);
+  let k = (m - rb2 - ra2) / 2.0;
+  var k3 = n;
+  var k2 = n * n + ra2 * rd.z * rd.z + k;
+  var k1 = k * n + ra2 * ro.z * rd.z;
+  var k0 = k * k + ra2 * ro.z * ro.z - ra2 * rb2;
+  if abs(k3 * (k3 * k3 - k2) + k1) < 0.01 {
+    po = -1.0;
+    let tmp = k1;
+    k1 = k3;
+    k3 = tmp;
+    k0 = 1.0 / k0;
+    k1 = k1 * k0;
+    k2 = k2 * k0;
+    k3 = k3 * k0;
+  }
+
+  var c2 = 2.0 * k2 - 3.0 * k3 * k3;
+  var c1 = k3 * (k3 * k3 - k2) + k1;
+  var c0 = k3 * (k3 * (-3.0 * k3 * k3 + 4.0 * k2) - 8.0 * k1) + 4.0 * k0;
+  c2 /= 3.0;
+  c1 *= 2.0;
+  c0 /= 3.0;
+  let q = c2 * c2 + c0;
+  let r = 3.0 * c0 * c2 - c2 * c2 * c2 - c1 * c1;
+  let h = r * r - q * q * q;
+  var z: f32;
+  if h < 0.0 {
+    let sq = sqrt(q);
+    z = 2.0 * sq * cos(acos(clamp(r / (sq * q), -1.0, 1.0)) / 3.0);
+  } else {
+    let sq = pow(sqrt(h) + abs(r), 1.0 / 3.0);
+    z = sign(r) * abs(sq + q / sq);
+  }
+  z = c2 - z;
+  var d1 = z - 3.0 * c2;
+  var d2 = z * z - 3.0 * c0;
+  if abs(d1) < 1e-4 {
+    if d2 < 0.0 {
+      return;
+    }
+    d2 = sqrt(d2);
+  } else {
+    if d1 < 0.0 {
+      return;
+    }
+    d1 = sqrt(d1 / 2.0);
+    d2 = c1 / d1;
+  }
+
+  var roots = vec4<f32>(-1.0);
+  let h1 = d1 * d1 - z + d2;
+  if h1 > 0.0 {
+    let s1 = sqrt(h1);
+    roots.x = -d1 - s1 - k3;
+    roots.y = -d1 + s1 - k3;
+  }
+  let h2 = d1 * d1 - z - d2;
+  if h2 > 0.0 {
+    let s2 = sqrt(h2);
+    roots.z = d1 - s2 - k3;
+    roots.w = d1 + s2 - k3;
+  }
+  for (var i = 0; i < 4; i++) {
+    var t = roots[i];
+    if po < 0.0 {
+      t = 2.0 / t;
+    }
+    // behind the bounding sphere, or no root at all
+    if t <= 0.0 {
+      continue;
+    }
+    t = (t + start) / scale;
+    let p = ray_at(ray, t);
+    let ring = normalize(vec3<f32>(p.x, 0.0, p.z)) * radius;
+    let normal = normalize(p - ring);
+    let around = atan2(p.y, length(p.xz) - radius);
+    let tex_coords = vec2<f32>(sphere_tex_coords(normalize(ring)).x, 0.5 - around / (2.0 * PI));
+    keep_closer(best, t, ray_tmin, normal, tex_coords, sphere_tangent(normalize(ring)));
+  }
+}
+
 fn hit_sphere(
   ray: Ray, 
   sphere: Sphere, 
@@ -224,8 +1959,12 @@ fn hit_sphere(
 
   (*rec).t = root;
   (*rec).point = ray_at(ray, root);
-  (*rec).normal = ((*rec).point - sphere.center) / sphere.radius;
+  let outward_normal = ((*rec).point - sphere.center) / sphere.radius;
+  set_face_normal(rec, ray, outward_normal);
   (*rec).material_index = sphere.material_index;
+  (*rec).tex_coords = sphere_tex_coords(outward_normal);
+  (*rec).tangent = vec4<f32>(sphere_tangent(outward_normal), 1.0);
+  (*rec).color = vec3<f32>(1.0);
 
   return true;
 }
@@ -302,12 +2041,115 @@ fn random_on_hemisphere(normal: vec3<f32>) -> vec3<f32> {
   }
 }
 
+// NOISE Stuff
+fn pcg3d(v: vec3<u32>) -> vec3<u32> {
+  var x = v * 1664525u + 1013904223u;
+  x.x += x.y * x.z;
+  x.y += x.z * x.x;
+  x.z += x.x * x.y;
+  x ^= x >> vec3<u32>(16u);
+  x.x += x.y * x.z;
+  x.y += x.z * x.x;
+  x.z += x.x * x.y;
+  return x;
+}
+
+// Random vector in the cube from 0 to 1 for a lattice point
+fn hash3(cell: vec3<f32>) -> vec3<f32> {
+  return vec3<f32>(pcg3d(bitcast<vec3<u32>>(vec3<i32>(cell)))) / f32(MAX_U32);
+}
+
+// Gradient noise from -1 to 1 with quintic fading
+fn perlin(p: vec3<f32>) -> f32 {
+  let cell = floor(p);
+  let f = p - cell;
+  let fade = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+  var corners: array<f32, 8>;
+  for (var i = 0u; i < 8u; i++) {
+    let corner = vec3<f32>(f32(i & 1u), f32((i >> 1u) & 1u), f32((i >> 2u) & 1u));
+    let gradient = normalize(hash3(cell + corner) * 2.0 - 1.0);
+    corners[i] = dot(gradient, f - corner);
+  }
+  let x0 = mix(corners[0], corners[1], fade.x);
+  let x1 = mix(corners[2], corners[3], fade.x);
+  let x2 = mix(corners[4], corners[5], fade.x);
+  let x3 = mix(corners[6], corners[7], fade.x);
+  return mix(mix(x0, x1, fade.y), mix(x2, x3, fade.y), fade.z) * 1.5;
+}
+
+fn fbm(p: vec3<f32>, octaves: u32) -> f32 {
+  var sum = 0.0;
+  var amplitude = 0.5;
+  var q = p;
+  for (var i = 0u; i < octaves; i++) {
+    sum += amplitude * perlin(q);
+    amplitude *= 0.5;
+    q *= 2.0;
+  }
+  return sum;
+}
+
+fn turbulence(p: vec3<f32>, octaves: u32) -> f32 {
+  var sum = 0.0;
+  var amplitude = 1.0;
+  var q = p;
+  for (var i = 0u; i < octaves; i++) {
+    sum += amplitude * abs(perlin(q));
+    amplitude *= 0.5;
+    q *= 2.0;
+  }
+  return sum;
+}
+
+// Distance to the closest of one random point per cell
+fn worley(p: vec3<f32>) -> f32 {
+  let cell = floor(p);
+  var closest = 2.0;
+  for (var x = -1; x <= 1; x++) {
+    for (var y = -1; y <= 1; y++) {
+      for (var z = -1; z <= 1; z++) {
+        let neighbour = cell + vec3<f32>(f32(x), f32(y), f32(z));
+        closest = min(closest, distance(p, neighbour + hash3(neighbour)));
+      }
+    }
+  }
+  return closest;
+}
+
 // Helpers
 fn length_squared(x: vec3<f32>) -> f32 {
   let len = length(x);
   return len * len;
 }
 
+fn luminance(color: vec3<f32>) -> f32 {
+  return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
 fn to_vec4(x: vec3<f32>) -> vec4<f32> {
   return vec4<f32> (x.xyz, 1.0);
 }
+
+// u goes around the y axis starting at -x and v from the top down
+fn sphere_tex_coords(normal: vec3<f32>) -> vec2<f32> {
+  let theta = acos(clamp(normal.y, -1.0, 1.0));
+  let phi = atan2(-normal.z, normal.x) + PI;
+  return vec2<f32>(phi / (2.0 * PI), theta / PI);
+}
+
+// Where u grows on a sphere, zero at the poles
+fn sphere_tangent(normal: vec3<f32>) -> vec3<f32> {
+  let tangent = vec3<f32>(normal.z, 0.0, -normal.x);
+  if length_squared(tangent) < 1e-12 {
+    return ZERO;
+  }
+  return normalize(tangent);
+}
+
+fn any_perpendicular(normal: vec3<f32>) -> vec3<f32> {
+  if abs(normal.x) > 0.9 {
+    return cross(normal, UP);
+  }
+  return cross(normal, RIGHT);
+}
@@ -20,21 +20,146 @@ fn vs_main(in: VertexInput) -> VertexOutput {
 
 const BLACK: vec4<f32> = vec4<f32>(0.0,0.0,0.0,1.0);
 
+// View modes
+const VIEW_COLOR: u32 = 0u;
+const VIEW_SAMPLE_HEATMAP: u32 = 1u;
+
+// Tone mappers
+const TONE_CLAMP: u32 = 0u;
+const TONE_REINHARD: u32 = 1u;
+const TONE_ACES: u32 = 2u;
+const TONE_AGX: u32 = 3u;
+
+struct ScreenSettings {
+  view_mode: u32,
+  max_samples: f32,
+  tone_mapper: u32,
+  // in stops
+  exposure: f32,
+  // set when the surface isn't sRGB so the shader has to encode it
+  encode_srgb: u32,
+}
+
 @group(0) @binding(0) var color_buffer: texture_2d<f32>;
 @group(0) @binding(1) var screen_sampler: sampler;
+@group(1) @binding(0) var<uniform> settings: ScreenSettings;
+@group(1) @binding(1) var stats: texture_2d<f32>;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
   let tex_coord = in.tex_coord;
+  if settings.view_mode == VIEW_SAMPLE_HEATMAP {
+    return output(sample_heatmap(tex_coord));
+  }
+
   var color = textureSample(color_buffer, screen_sampler, tex_coord); 
-  return color_ajustments(color.xyz);
+  return output(color_ajustments(color.xyz));
   // return color;
 }
 
 const ONE: vec3<f32> = vec3<f32> (1.0, 1.0, 1.0);
 
-fn color_ajustments(color: vec3<f32>) -> vec4<f32> {
-  let uniformish = pow(color, ONE * 2.2);
-  return vec4<f32>(uniformish.xyz, 1.0);
+fn color_ajustments(color: vec3<f32>) -> vec3<f32> {
+  let exposed = color * exp2(settings.exposure);
+  switch settings.tone_mapper {
+    case TONE_REINHARD: { return reinhard(exposed); }
+    case TONE_ACES: { return aces_filmic(exposed); }
+    case TONE_AGX: { return agx(exposed); }
+    default: { return clamp(exposed, vec3<f32>(0.0), ONE); }
+  }
+}
+
+// Takes display linear colors to whatever the surface expects
+fn output(color: vec3<f32>) -> vec4<f32> {
+  if settings.encode_srgb == 1u {
+    return vec4<f32>(linear_to_srgb(color), 1.0);
+  }
+  return vec4<f32>(color, 1.0);
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+  let c = clamp(color, vec3<f32>(0.0), ONE);
+  let low = c * 12.92;
+  let high = 1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055;
+  return select(high, low, c <= vec3<f32>(0.0031308));
 }
 
+// Tone mapping based on luminance so the hue doesn't shift
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+  let lum = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+  return clamp(color / (1.0 + lum), vec3<f32>(0.0), ONE);
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+fn aces_filmic(color: vec3<f32>) -> vec3<f32> {
+  let aces_input = mat3x3<f32>(
+    vec3<f32>(0.59719, 0.07600, 0.02840),
+    vec3<f32>(0.35458, 0.90834, 0.13383),
+    vec3<f32>(0.04823, 0.01566, 0.83777),
+  );
+  let aces_output = mat3x3<f32>(
+    vec3<f32>(1.60475, -0.10208, -0.00327),
+    vec3<f32>(-0.53108, 1.10813, -0.07276),
+    vec3<f32>(-0.07367, -0.00605, 1.07602),
+  );
+
+  let v = aces_input * color;
+  let a = v * (v + 0.0245786) - 0.000090537;
+  let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+  return clamp(aces_output * (a / b), vec3<f32>(0.0), ONE);
+}
+
+// Minimal AgX with the default look, from Benjamin Wrensch's approximation
+fn agx(color: vec3<f32>) -> vec3<f32> {
+  let agx_inset = mat3x3<f32>(
+    vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+    vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+    vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+  );
+  let agx_outset = mat3x3<f32>(
+    vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+    vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+    vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+  );
+  let min_ev = -12.47393;
+  let max_ev = 4.026069;
+
+  var v = agx_inset * max(color, vec3<f32>(1e-10));
+  v = clamp(log2(v), vec3<f32>(min_ev), vec3<f32>(max_ev));
+  v = (v - min_ev) / (max_ev - min_ev);
+  v = agx_contrast(v);
+  v = agx_outset * v;
+  // The curve outputs a 2.2 gamma encoding so undo it to stay linear
+  return pow(clamp(v, vec3<f32>(0.0), ONE), vec3<f32>(2.2));
+}
+
+// 6th order polynomial fit of the AgX contrast curve
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+  let x2 = x * x;
+  let x4 = x2 * x2;
+  return 15.5 * x4 * x2
+    - 40.14 * x4 * x
+    + 31.96 * x4
+    - 6.868 * x2 * x
+    + 0.4298 * x2
+    + 0.1191 * x
+    - 0.00232;
+}
+
+
+fn sample_heatmap(tex_coord: vec2<f32>) -> vec3<f32> {
+  let dim = textureDimensions(stats);
+  let pixel = vec2<u32>(tex_coord * vec2<f32>(dim));
+  let samples = textureLoad(stats, min(pixel, dim - 1u), 0).z;
+  // log scale so the first few samples are still visible
+  let t = clamp(log2(samples + 1.0) / log2(settings.max_samples + 1.0), 0.0, 1.0);
+  return heat(t);
+}
+
+// Blue -> green -> red
+fn heat(t: f32) -> vec3<f32> {
+  let r = smoothstep(0.5, 1.0, t);
+  let g = 1.0 - abs(t - 0.5) * 2.0;
+  let b = 1.0 - smoothstep(0.0, 0.5, t);
+  return vec3<f32>(r, g, b);
+}
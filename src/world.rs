@@ -1,5 +1,5 @@
 use std::collections::HashSet;
-use crate::{common::UNIFORM_BUFFER_BINDING, render_env::RenderEnv, resources::Material};
+use crate::{common::UNIFORM_BUFFER_BINDING, render_env::RenderEnv, resources::Material, scene::Scene};
 use bytemuck::{bytes_of, Pod, Zeroable, cast_slice};
 use glam::{vec2, vec3, Vec3, Vec3Swizzles, Vec4, Vec2};
 use wgpu::{
@@ -14,8 +14,14 @@ use winit::{
 
 use crate::resources::{
     Camera,
+    Environment,
+    Lights,
+    Media,
+    MaterialTextures,
+    Sky,
     ObjectData,
     Sphere,
+    InstanceData,
 };
 
 pub struct World {
@@ -23,35 +29,70 @@ pub struct World {
     objects_buffer: Buffer,
     materials: Vec<Material>,
     materials_buffer: Buffer,
+    pub lights: Lights,
+    textures: MaterialTextures,
+    media: Media,
+    vertices_buffer: Buffer,
+    triangles_buffer: Buffer,
+    bvh_buffer: Buffer,
+    primitives_buffer: Buffer,
+    instances_buffer: Buffer,
     pub bind_group: BindGroup,
     pub bind_group_layout: BindGroupLayout,
 }
 
 impl World {
-    pub fn new(render_env: &RenderEnv, camera_buffer: &Buffer) -> Self {
+    pub fn new(
+        render_env: &RenderEnv,
+        camera_buffer: &Buffer,
+        environment: &Environment,
+        sky: &Sky,
+        scene: &Scene,
+    ) -> Self {
         let device = &render_env.device;
 
-        let materials = vec![Material::random_new(), Material::random_new(), Material::random_new()];
-        let spheres = vec![
-            Sphere::new(vec3(0.0, -100.5, 0.0), 100.0, 0),
-            Sphere::new(vec3(-1.0, 0.0, -1.0), 0.5, 1),
-            Sphere::new(vec3(1.0, 0.0, -1.0), 0.5, 2),
-            Sphere::new(vec3(0.0, 0.0, -1.0), 0.5, 0),
-        ];
+        let materials = scene.materials.clone();
         let objects = ObjectData {
-            // spheres: Sphere::random_bunch(20, materials.len() as u32),
-            spheres,
+            spheres: scene.spheres.clone(),
+            primitives: scene.primitives.clone(),
+        };
+        let lights = Lights::new(render_env, scene.lights.clone());
+        let textures = MaterialTextures::new(render_env, &scene.images, &scene.textures);
+        let media = Media::new(render_env, scene.fog, &scene.volumes, scene.grid.as_ref());
+
+        let instances = InstanceData::new(&scene.meshes, &scene.spheres, &scene.primitives, &scene.graph.flatten());
+        let storage_buffer = |label, contents| {
+            device.create_buffer_init(&BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: BufferUsages::STORAGE,
+            })
+        };
+        let vertices_buffer = storage_buffer("Mesh vertices buffer", cast_slice(&instances.vertices));
+        let triangles_buffer = storage_buffer("Mesh triangles buffer", cast_slice(&instances.triangles));
+        let bvh_buffer = storage_buffer("Bvh buffer", cast_slice(&instances.bvh));
+        let instances_buffer = storage_buffer("Instances buffer", cast_slice(&instances.instances));
+        let primitives_buffer = storage_buffer("Primitives buffer", &objects.primitives_as_bytes());
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            visibility: ShaderStages::COMPUTE,
+            count: None,
         };
 
         let objects_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Camera Buffer"),
-            contents: objects.as_bytes(),
+            contents: &objects.as_bytes(),
             usage: BufferUsages::COPY_DST | BufferUsages::STORAGE,
         });
 
         let materials_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Camera Buffer"),
-            contents: cast_slice(&materials.as_slice()),
+            contents: cast_slice(materials.as_slice()),
             usage: BufferUsages::COPY_DST | BufferUsages::STORAGE,
         });
 
@@ -84,6 +125,86 @@ impl World {
                     visibility: ShaderStages::COMPUTE,
                     count: None,
                 },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    visibility: ShaderStages::COMPUTE,
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    visibility: ShaderStages::COMPUTE,
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 5,
+                    ty: UNIFORM_BUFFER_BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 6,
+                    ty: UNIFORM_BUFFER_BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 7,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    visibility: ShaderStages::COMPUTE,
+                    count: None,
+                },
+                storage_entry(8),
+                storage_entry(9),
+                storage_entry(10),
+                BindGroupLayoutEntry {
+                    binding: 11,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    visibility: ShaderStages::COMPUTE,
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 12,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    visibility: ShaderStages::COMPUTE,
+                    count: None,
+                },
+                storage_entry(13),
+                BindGroupLayoutEntry {
+                    binding: 14,
+                    ty: UNIFORM_BUFFER_BINDING,
+                    visibility: ShaderStages::COMPUTE,
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 15,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    visibility: ShaderStages::COMPUTE,
+                    count: None,
+                },
+                storage_entry(16),
+                storage_entry(17),
             ],
         });
 
@@ -103,20 +224,96 @@ impl World {
                     binding: 2,
                     resource: materials_buffer.as_entire_binding(),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&environment.view),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: environment.distribution_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: environment.params_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: sky.params_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 7,
+                    resource: lights.buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 8,
+                    resource: vertices_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 9,
+                    resource: triangles_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 10,
+                    resource: bvh_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 11,
+                    resource: BindingResource::TextureView(&textures.view),
+                },
+                BindGroupEntry {
+                    binding: 12,
+                    resource: BindingResource::Sampler(&textures.sampler),
+                },
+                BindGroupEntry {
+                    binding: 13,
+                    resource: textures.table_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 14,
+                    resource: media.buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 15,
+                    resource: BindingResource::TextureView(&media.grid_view),
+                },
+                BindGroupEntry {
+                    binding: 16,
+                    resource: primitives_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 17,
+                    resource: instances_buffer.as_entire_binding(),
+                },
             ],
         });
 
-        return Self {
+        Self {
             objects,
             objects_buffer,
             materials,
             materials_buffer,
+            lights,
+            textures,
+            media,
+            vertices_buffer,
+            triangles_buffer,
+            bvh_buffer,
+            primitives_buffer,
+            instances_buffer,
             bind_group_layout,
             bind_group,
-        };
+        }
     }
 
-    pub fn reload(&mut self, render_env: &RenderEnv, camera_buffer: &Buffer) {
-        *self = Self::new(render_env, camera_buffer);
+    pub fn reload(
+        &mut self,
+        render_env: &RenderEnv,
+        camera_buffer: &Buffer,
+        environment: &Environment,
+        sky: &Sky,
+        scene: &Scene,
+    ) {
+        let previous = std::mem::replace(self, Self::new(render_env, camera_buffer, environment, sky, scene));
+        self.lights.keep_selection(&previous.lights);
     }
 }